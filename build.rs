@@ -0,0 +1,50 @@
+//! Bakes the directory named by `SAMSERVE_EMBED_DIR` into the binary for
+//! `--embedded`. Without it the binary embeds nothing.
+
+use std::fmt::Write;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=SAMSERVE_EMBED_DIR");
+    let mut files = Vec::new();
+    if let Some(dir) = std::env::var_os("SAMSERVE_EMBED_DIR") {
+        let dir = std::fs::canonicalize(&dir)
+            .unwrap_or_else(|e| panic!("SAMSERVE_EMBED_DIR {:?}: {}", dir, e));
+        println!("cargo:rerun-if-changed={}", dir.display());
+        collect(&dir, "", &mut files);
+        files.sort();
+    }
+
+    let built = std::time::SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    let mut code = format!("pub const BUILT: u64 = {};\n", built);
+    code.push_str("pub static FILES: &[(&str, &[u8])] = &[\n");
+    for (name, path) in &files {
+        writeln!(code, "    ({:?}, include_bytes!({:?})),", name, path).unwrap();
+    }
+    code.push_str("];\n");
+    let out = Path::new(&std::env::var_os("OUT_DIR").unwrap()).join("embedded.rs");
+    std::fs::write(out, code).unwrap();
+}
+
+/// Adds the files below `dir`, named by their `/`-separated path below the
+/// embedded directory.
+fn collect(dir: &Path, prefix: &str, files: &mut Vec<(String, String)>) {
+    let entries = std::fs::read_dir(dir).unwrap_or_else(|e| panic!("cannot read {:?}: {}", dir, e));
+    for entry in entries {
+        let entry = entry.unwrap();
+        let Ok(name) = entry.file_name().into_string() else {
+            println!("cargo:warning=skipping non-UTF-8 name in {:?}", dir);
+            continue;
+        };
+        let path = entry.path();
+        let name = format!("{}{}", prefix, name);
+        if path.is_dir() {
+            collect(&path, &format!("{}/", name), files);
+        } else if path.is_file() {
+            files.push((name, path.to_string_lossy().into_owned()));
+        }
+    }
+}
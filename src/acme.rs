@@ -0,0 +1,307 @@
+//! Automatic certificates over ACME (RFC 8555) with HTTP-01 challenges, as
+//! issued by Let's Encrypt. The account, certificate and key are kept in a
+//! state directory so restarts reuse them instead of ordering new ones.
+
+use hyper::{Body, Response, header};
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, OrderStatus, RetryPolicy,
+};
+use log::{error, info, warn};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+
+/// Certificates are renewed once they are this old; Let's Encrypt issues
+/// them for 90 days.
+const RENEW_AFTER: Duration = Duration::from_secs(60 * 24 * 60 * 60);
+/// How often the certificate's age is checked.
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// Wait before trying again after a failed order.
+const RETRY_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How long the CA gets to validate challenges and issue the certificate.
+const ORDER_TIMEOUT: Duration = Duration::from_secs(120);
+
+const CHALLENGE_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Key authorizations of pending HTTP-01 challenges, by token.
+static CHALLENGES: LazyLock<Mutex<HashMap<String, String>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Serves the certificate currently on hand to TLS handshakes. Until the
+/// first certificate is issued handshakes are refused.
+#[derive(Debug, Default)]
+pub struct CertStore {
+    current: RwLock<Option<Arc<CertifiedKey>>>,
+}
+
+impl CertStore {
+    fn set(&self, key: CertifiedKey) {
+        *self.current.write().unwrap() = Some(Arc::new(key));
+    }
+
+    fn is_empty(&self) -> bool {
+        self.current.read().unwrap().is_none()
+    }
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+/// Obtains and renews the certificate for `domains`.
+pub struct Manager {
+    domains: Vec<String>,
+    email: Option<String>,
+    directory_url: String,
+    state_dir: PathBuf,
+    store: Arc<CertStore>,
+}
+
+impl Manager {
+    /// Creates the state directory and loads a previously issued certificate
+    /// from it, if one exists for the same domains.
+    pub fn new(
+        domains: Vec<String>,
+        email: Option<String>,
+        directory_url: String,
+        state_dir: PathBuf,
+    ) -> Result<Self, String> {
+        std::fs::create_dir_all(&state_dir)
+            .map_err(|e| format!("failed to create {:?}: {}", state_dir, e))?;
+        let manager = Manager {
+            domains,
+            email,
+            directory_url,
+            state_dir,
+            store: Arc::new(CertStore::default()),
+        };
+        match manager.load() {
+            Ok(true) => info!(
+                "Loaded ACME certificate | domains: {:?} | dir: {:?}",
+                manager.domains, manager.state_dir
+            ),
+            Ok(false) => {}
+            Err(e) => warn!(
+                "Ignoring stored ACME certificate | dir: {:?} | error: {}",
+                manager.state_dir, e
+            ),
+        }
+        Ok(manager)
+    }
+
+    pub fn store(&self) -> Arc<CertStore> {
+        Arc::clone(&self.store)
+    }
+
+    /// Orders a certificate whenever none is loaded or the current one is
+    /// due for renewal. Runs for the life of the server.
+    pub async fn maintain(self) {
+        loop {
+            let wait = if self.needs_certificate() {
+                match self.issue().await {
+                    Ok(()) => {
+                        info!("Issued ACME certificate | domains: {:?}", self.domains);
+                        CHECK_INTERVAL
+                    }
+                    Err(e) => {
+                        error!(
+                            "ACME order failed | domains: {:?} | error: {}",
+                            self.domains, e
+                        );
+                        RETRY_INTERVAL
+                    }
+                }
+            } else {
+                CHECK_INTERVAL
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.state_dir.join(name)
+    }
+
+    fn needs_certificate(&self) -> bool {
+        if self.store.is_empty() {
+            return true;
+        }
+        std::fs::metadata(self.path("cert.pem"))
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|issued| SystemTime::now().duration_since(issued).ok())
+            .is_none_or(|age| age >= RENEW_AFTER)
+    }
+
+    /// Returns `false` when nothing is stored or it was issued for a
+    /// different set of domains.
+    fn load(&self) -> Result<bool, String> {
+        let Ok(domains) = std::fs::read_to_string(self.path("domains")) else {
+            return Ok(false);
+        };
+        if domains.lines().ne(self.domains.iter().map(String::as_str)) {
+            return Ok(false);
+        }
+        let chain = std::fs::read(self.path("cert.pem")).map_err(|e| e.to_string())?;
+        let key = std::fs::read(self.path("key.pem")).map_err(|e| e.to_string())?;
+        self.store.set(certified_key(&chain, &key)?);
+        Ok(true)
+    }
+
+    async fn issue(&self) -> Result<(), String> {
+        let account = self.account().await?;
+        let identifiers = self
+            .domains
+            .iter()
+            .map(|domain| Identifier::Dns(domain.clone()))
+            .collect::<Vec<_>>();
+        let mut order = account
+            .new_order(&NewOrder::new(&identifiers))
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let mut tokens = Vec::new();
+        let result = async {
+            let mut authorizations = order.authorizations();
+            while let Some(authz) = authorizations.next().await {
+                let mut authz = authz.map_err(|e| e.to_string())?;
+                match authz.status {
+                    AuthorizationStatus::Pending => {}
+                    AuthorizationStatus::Valid => continue,
+                    status => return Err(format!("authorization is {:?}", status)),
+                }
+                let mut challenge = authz
+                    .challenge(ChallengeType::Http01)
+                    .ok_or("the CA offered no HTTP-01 challenge")?;
+                CHALLENGES.lock().unwrap().insert(
+                    challenge.token.clone(),
+                    challenge.key_authorization().as_str().to_string(),
+                );
+                tokens.push(challenge.token.clone());
+                challenge.set_ready().await.map_err(|e| e.to_string())?;
+            }
+            let retries = RetryPolicy::new().timeout(ORDER_TIMEOUT);
+            let status = order
+                .poll_ready(&retries)
+                .await
+                .map_err(|e| e.to_string())?;
+            if status != OrderStatus::Ready {
+                return Err(format!("order is {:?}", status));
+            }
+            let key = order.finalize().await.map_err(|e| e.to_string())?;
+            let chain = order
+                .poll_certificate(&retries)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok((chain, key))
+        }
+        .await;
+        let mut challenges = CHALLENGES.lock().unwrap();
+        for token in &tokens {
+            challenges.remove(token);
+        }
+        drop(challenges);
+
+        let (chain, key) = result?;
+        let certified = certified_key(chain.as_bytes(), key.as_bytes())?;
+        self.save("key.pem", key.as_bytes())?;
+        self.save("cert.pem", chain.as_bytes())?;
+        self.save("domains", self.domains.join("\n").as_bytes())?;
+        self.store.set(certified);
+        Ok(())
+    }
+
+    /// Restores the ACME account from the state directory, registering a new
+    /// one on first use.
+    async fn account(&self) -> Result<Account, String> {
+        let path = self.path("account.json");
+        if let Ok(saved) = std::fs::read(&path) {
+            let credentials: AccountCredentials = serde_json::from_slice(&saved)
+                .map_err(|e| format!("invalid account file {:?}: {}", path, e))?;
+            return Account::builder()
+                .map_err(|e| e.to_string())?
+                .from_credentials(credentials)
+                .await
+                .map_err(|e| e.to_string());
+        }
+        let contact = self.email.as_ref().map(|email| format!("mailto:{}", email));
+        let contact = contact
+            .as_slice()
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        let (account, credentials) = Account::builder()
+            .map_err(|e| e.to_string())?
+            .create(
+                &NewAccount {
+                    contact: &contact,
+                    terms_of_service_agreed: true,
+                    only_return_existing: false,
+                },
+                self.directory_url.clone(),
+                None,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+        let saved = serde_json::to_vec_pretty(&credentials).map_err(|e| e.to_string())?;
+        self.save("account.json", &saved)?;
+        info!("Registered ACME account | id: {}", account.id());
+        Ok(account)
+    }
+
+    /// Writes `name` readable by the owner only, replacing it atomically.
+    fn save(&self, name: &str, contents: &[u8]) -> Result<(), String> {
+        let path = self.path(name);
+        let temp = self.path(&format!(".{}.tmp", name));
+        write_private(&temp, contents)
+            .and_then(|()| std::fs::rename(&temp, &path))
+            .map_err(|e| format!("failed to write {:?}: {}", path, e))
+    }
+}
+
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)?.write_all(contents)
+}
+
+fn certified_key(chain: &[u8], key: &[u8]) -> Result<CertifiedKey, String> {
+    let chain = CertificateDer::pem_slice_iter(chain)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("invalid certificate: {}", e))?;
+    if chain.is_empty() {
+        return Err("no certificate found".to_string());
+    }
+    let key = PrivateKeyDer::from_pem_slice(key).map_err(|e| format!("invalid key: {}", e))?;
+    CertifiedKey::from_der(
+        chain,
+        key,
+        &tokio_rustls::rustls::crypto::ring::default_provider(),
+    )
+    .map_err(|e| format!("invalid certificate or key: {}", e))
+}
+
+/// The response to an HTTP-01 challenge request for `path`, or `None` when
+/// it is not one of the pending challenges.
+pub fn challenge_response(path: &str) -> Option<Response<Body>> {
+    let token = path.strip_prefix(CHALLENGE_PREFIX)?;
+    let key_authorization = CHALLENGES.lock().unwrap().get(token).cloned()?;
+    Some(
+        Response::builder()
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .body(Body::from(key_authorization))
+            .unwrap(),
+    )
+}
@@ -0,0 +1,482 @@
+//! `--root archive.zip`: serves the contents of a zip or tar archive
+//! without extracting it. Entries stored uncompressed (all of a plain
+//! `.tar`, `stored` ones in a zip) are read straight from the archive, so
+//! ranges are cheap; compressed ones are inflated on the fly, skipping up to
+//! the start of a range.
+
+use crate::storage::{ByteStream, DirEntry, Metadata, Staged, Storage};
+use chrono::NaiveDateTime;
+use flate2::read::GzDecoder;
+use futures_util::StreamExt;
+use futures_util::future::BoxFuture;
+use hyper::body::Bytes;
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use tokio::sync::mpsc;
+use tokio_util::io::ReaderStream;
+
+/// Size of the chunks inflated entries are sent in.
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl Format {
+    fn of(path: &Path) -> Option<Format> {
+        let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+        if name.ends_with(".zip") {
+            Some(Format::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Format::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Format::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether `path` is an archive file `--root` can serve.
+pub fn is_archive(path: &Path) -> bool {
+    Format::of(path).is_some() && path.is_file()
+}
+
+/// Where the data of an entry is found.
+#[derive(Debug, Clone, Copy)]
+enum Data {
+    Dir,
+    /// Uncompressed, at this offset in the archive.
+    Stored(u64),
+    /// The zip entry with this index, to be inflated.
+    Zip(usize),
+    /// Somewhere in a compressed tar, found by reading up to it.
+    TarGz,
+}
+
+#[derive(Debug)]
+struct Entry {
+    metadata: Metadata,
+    data: Data,
+}
+
+/// The entries of an archive, read once when it is opened.
+#[derive(Debug)]
+pub struct ArchiveStorage {
+    /// The archive; paths below it are entries.
+    root: PathBuf,
+    /// Entries by their `/`-separated path; the top level is `""`.
+    entries: BTreeMap<String, Entry>,
+}
+
+impl ArchiveStorage {
+    /// Reads the table of contents of the archive at `path`.
+    pub fn open(path: &Path) -> Result<Arc<ArchiveStorage>, String> {
+        let format = Format::of(path).ok_or_else(|| format!("{:?} is not an archive", path))?;
+        let invalid = |e: &dyn std::fmt::Display| format!("cannot read archive {:?}: {}", path, e);
+        let file = std::fs::File::open(path).map_err(|e| invalid(&e))?;
+        let found = match format {
+            Format::Zip => zip_entries(file).map_err(io::Error::other),
+            Format::Tar => tar_entries(tar::Archive::new(file), true),
+            Format::TarGz => tar_entries(tar::Archive::new(GzDecoder::new(file)), false),
+        }
+        .map_err(|e| invalid(&e))?;
+
+        let directory = Entry {
+            metadata: Metadata {
+                is_dir: true,
+                len: 0,
+                modified: None,
+            },
+            data: Data::Dir,
+        };
+        let mut entries = BTreeMap::new();
+        entries.insert(String::new(), directory);
+        for (name, entry) in found {
+            // Directories are implied by the entries below them.
+            let mut parent = name.as_str();
+            while let Some((dir, _)) = parent.rsplit_once('/') {
+                entries.entry(dir.to_string()).or_insert(Entry {
+                    metadata: Metadata {
+                        is_dir: true,
+                        len: 0,
+                        modified: None,
+                    },
+                    data: Data::Dir,
+                });
+                parent = dir;
+            }
+            entries.insert(name, entry);
+        }
+        Ok(Arc::new(ArchiveStorage {
+            root: path.to_path_buf(),
+            entries,
+        }))
+    }
+
+    /// The entry name of `path`, which lies below the archive.
+    fn name(&self, path: &Path) -> io::Result<String> {
+        let relative = path.strip_prefix(&self.root).map_err(|_| not_found())?;
+        entry_name(relative).ok_or_else(not_found)
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<(String, &Entry)> {
+        let name = self.name(path)?;
+        let entry = self.entries.get(&name).ok_or_else(not_found)?;
+        Ok((name, entry))
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such entry in the archive")
+}
+
+/// Joins the parts of a relative path with `/`, refusing any that could
+/// point outside the archive.
+pub fn entry_name(path: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for part in path.components() {
+        match part {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
+
+fn zip_entries(file: std::fs::File) -> zip::result::ZipResult<Vec<(String, Entry)>> {
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut found = Vec::new();
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index)?;
+        let Some(name) = entry.enclosed_name().as_deref().and_then(entry_name) else {
+            continue;
+        };
+        if name.is_empty() || entry.encrypted() || entry.is_symlink() {
+            continue;
+        }
+        let modified = entry
+            .last_modified()
+            .and_then(|time| NaiveDateTime::try_from(time).ok())
+            .map(|time| time.and_utc().into());
+        let data = if entry.is_dir() {
+            Data::Dir
+        } else if entry.compression() == zip::CompressionMethod::Stored {
+            Data::Stored(entry.data_start())
+        } else {
+            Data::Zip(index)
+        };
+        let metadata = Metadata {
+            is_dir: entry.is_dir(),
+            len: if entry.is_dir() { 0 } else { entry.size() },
+            modified,
+        };
+        found.push((name, Entry { metadata, data }));
+    }
+    Ok(found)
+}
+
+/// Lists a tar archive; `seekable` ones record where each file's data is.
+fn tar_entries<R: Read>(
+    mut archive: tar::Archive<R>,
+    seekable: bool,
+) -> io::Result<Vec<(String, Entry)>> {
+    let mut found = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let kind = entry.header().entry_type();
+        if !kind.is_file() && !kind.is_dir() {
+            continue;
+        }
+        let Some(name) = entry_name(&entry.path()?) else {
+            continue;
+        };
+        if name.is_empty() {
+            continue;
+        }
+        let modified = entry
+            .header()
+            .mtime()
+            .ok()
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+        let data = if kind.is_dir() {
+            Data::Dir
+        } else if seekable {
+            Data::Stored(entry.raw_file_position())
+        } else {
+            Data::TarGz
+        };
+        let metadata = Metadata {
+            is_dir: kind.is_dir(),
+            len: if kind.is_dir() { 0 } else { entry.size() },
+            modified,
+        };
+        found.push((name, Entry { metadata, data }));
+    }
+    Ok(found)
+}
+
+impl Storage for ArchiveStorage {
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Metadata>> {
+        Box::pin(async move { self.entry(path).map(|(_, entry)| entry.metadata) })
+    }
+
+    fn list<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<DirEntry>>> {
+        Box::pin(async move {
+            let (name, entry) = self.entry(path)?;
+            if !entry.metadata.is_dir {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    "not a directory",
+                ));
+            }
+            let prefix = if name.is_empty() {
+                name
+            } else {
+                format!("{}/", name)
+            };
+            Ok(self
+                .entries
+                .range(prefix.clone()..)
+                .take_while(|(child, _)| child.starts_with(&prefix))
+                .filter_map(|(child, entry)| {
+                    let child = &child[prefix.len()..];
+                    (!child.is_empty() && !child.contains('/')).then(|| DirEntry {
+                        name: child.into(),
+                        metadata: Some(entry.metadata),
+                    })
+                })
+                .collect())
+        })
+    }
+
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<ByteStream>> {
+        Box::pin(async move {
+            let (_, entry) = self.entry(path)?;
+            self.read_range(path, 0, entry.metadata.len).await
+        })
+    }
+
+    fn read_range<'a>(
+        &'a self,
+        path: &'a Path,
+        start: u64,
+        len: u64,
+    ) -> BoxFuture<'a, io::Result<ByteStream>> {
+        Box::pin(async move {
+            let (name, entry) = self.entry(path)?;
+            match entry.data {
+                Data::Dir => Err(io::Error::new(
+                    io::ErrorKind::IsADirectory,
+                    "is a directory",
+                )),
+                Data::Stored(offset) => {
+                    let mut file = File::open(&self.root).await?;
+                    file.seek(SeekFrom::Start(offset + start)).await?;
+                    Ok(ReaderStream::new(file.take(len)).boxed())
+                }
+                Data::Zip(index) => Ok(inflate(self.root.clone(), start, len, move |file| {
+                    let archive = zip::ZipArchive::new(file).map_err(io::Error::other)?;
+                    Ok(Box::new(ZipReader { archive, index }))
+                })),
+                Data::TarGz => Ok(inflate(self.root.clone(), start, len, move |file| {
+                    Ok(Box::new(TarGzReader {
+                        archive: tar::Archive::new(GzDecoder::new(file)),
+                        name,
+                    }))
+                })),
+            }
+        })
+    }
+
+    fn write<'a>(&'a self, _path: &'a Path, _data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        Box::pin(async move {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "archives are read-only",
+            ))
+        })
+    }
+
+    fn persist<'a>(&'a self, staged: Staged, _path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            staged.discard().await;
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "archives are read-only",
+            ))
+        })
+    }
+}
+
+/// A compressed entry, decompressed into `sink` a chunk at a time.
+trait Inflate: Send {
+    fn copy_to(&mut self, sink: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()>;
+}
+
+/// Reads a zip entry; `ZipFile` borrows its archive, so the two travel
+/// together.
+struct ZipReader {
+    archive: zip::ZipArchive<std::fs::File>,
+    index: usize,
+}
+
+impl Inflate for ZipReader {
+    fn copy_to(&mut self, sink: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+        let entry = self
+            .archive
+            .by_index(self.index)
+            .map_err(io::Error::other)?;
+        copy_chunks(entry, sink)
+    }
+}
+
+/// Reads an entry of a `.tar.gz`, which means reading up to it.
+struct TarGzReader {
+    archive: tar::Archive<GzDecoder<std::fs::File>>,
+    name: String,
+}
+
+impl Inflate for TarGzReader {
+    fn copy_to(&mut self, sink: &mut dyn FnMut(&[u8]) -> io::Result<()>) -> io::Result<()> {
+        for entry in self.archive.entries()? {
+            let entry = entry?;
+            if entry_name(&entry.path()?).as_deref() == Some(self.name.as_str()) {
+                return copy_chunks(entry, sink);
+            }
+        }
+        Err(not_found())
+    }
+}
+
+fn copy_chunks(
+    mut reader: impl Read,
+    sink: &mut dyn FnMut(&[u8]) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(());
+        }
+        sink(&buffer[..n])?;
+    }
+}
+
+/// Streams `len` bytes from `start` of a compressed entry, decompressing
+/// on a blocking thread. Bytes before `start` are decompressed and dropped.
+fn inflate<F>(archive: PathBuf, start: u64, len: u64, open: F) -> ByteStream
+where
+    F: FnOnce(std::fs::File) -> io::Result<Box<dyn Inflate>> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel::<io::Result<Bytes>>(4);
+    tokio::task::spawn_blocking(move || {
+        let mut skip = start;
+        let mut left = len;
+        let result = std::fs::File::open(&archive)
+            .and_then(open)
+            .and_then(|mut reader| {
+                reader.copy_to(&mut |mut chunk| {
+                    let skipped = chunk.len().min(skip as usize);
+                    chunk = &chunk[skipped..];
+                    skip -= skipped as u64;
+                    let chunk = &chunk[..chunk.len().min(left as usize)];
+                    if !chunk.is_empty() {
+                        left -= chunk.len() as u64;
+                        sender
+                            .blocking_send(Ok(Bytes::copy_from_slice(chunk)))
+                            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+                    }
+                    if left == 0 {
+                        // Done; stop decompressing the rest.
+                        return Err(io::Error::from(io::ErrorKind::Interrupted));
+                    }
+                    Ok(())
+                })
+            });
+        match result {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(err) => {
+                let _ = sender.blocking_send(Err(err));
+            }
+            Ok(()) => {}
+        }
+    });
+    futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|item| (item, receiver))
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::collect;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn serves_zip_and_tar_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("site.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        let stored = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        let deflated = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated);
+        zip.start_file("docs/stored.txt", stored).unwrap();
+        zip.write_all(b"hello stored").unwrap();
+        zip.start_file("deflated.txt", deflated).unwrap();
+        zip.write_all(&b"hello deflated ".repeat(100)).unwrap();
+        zip.finish().unwrap();
+
+        let storage = ArchiveStorage::open(&zip_path).unwrap();
+        let docs = storage.metadata(&zip_path.join("docs")).await.unwrap();
+        assert!(docs.is_dir);
+        let mut names = storage
+            .list(&zip_path)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name.into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["deflated.txt", "docs"]);
+        let stored = zip_path.join("docs/stored.txt");
+        let range = storage.read_range(&stored, 6, 6).await.unwrap();
+        assert_eq!(collect(range).await.unwrap(), b"stored");
+        let deflated = zip_path.join("deflated.txt");
+        assert_eq!(storage.metadata(&deflated).await.unwrap().len, 1500);
+        let range = storage.read_range(&deflated, 15, 8).await.unwrap();
+        assert_eq!(collect(range).await.unwrap(), b"hello de");
+        assert!(storage.metadata(&zip_path.join("../x")).await.is_err());
+
+        let tar_path = dir.path().join("site.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(
+            std::fs::File::create(&tar_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(encoder);
+        let mut header = tar::Header::new_gnu();
+        header.set_size(5);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, "a/b.txt", &b"tar!\n"[..])
+            .unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+
+        let storage = ArchiveStorage::open(&tar_path).unwrap();
+        let whole = storage.open(&tar_path.join("a/b.txt")).await.unwrap();
+        assert_eq!(collect(whole).await.unwrap(), b"tar!\n");
+        assert!(storage.metadata(&tar_path.join("a")).await.unwrap().is_dir);
+    }
+}
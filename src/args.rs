@@ -1,4 +1,9 @@
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, Parser, ValueEnum};
+use std::ffi::OsString;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 
 /// A minimal file server with upload support and Basic Auth
 #[derive(Parser, Debug)]
@@ -15,18 +20,762 @@ use clap::Parser;
     propagate_version = true
 )]
 pub struct Args {
-    #[arg(short, long, default_value = ".", help = "Root directory to serve files from")]
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read options from a TOML file; command-line flags take precedence"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        default_value = ".",
+        help = "Root directory to serve files from"
+    )]
     pub root: String,
 
-    #[arg(short, long, default_value = "0.0.0.0", help = "IP address to bind to")]
-    pub ip: String,
+    #[arg(long, value_name = "URL", value_parser = crate::s3::parse_backend, conflicts_with_all = ["sandbox", "webdav", "cgi"], help = "Serve the objects of an S3 or S3-compatible bucket instead of --root, e.g. s3://bucket/prefix; see src/s3.rs for the environment variables it reads")]
+    pub backend: Option<crate::s3::Bucket>,
+
+    #[arg(long, conflicts_with_all = ["backend", "upload", "sandbox", "webdav", "cgi"], help = "Serve the files baked into this binary at build time (SAMSERVE_EMBED_DIR) instead of --root")]
+    pub embedded: bool,
+
+    #[arg(long, conflicts_with_all = ["backend", "embedded", "webdav", "cgi"], help = "Keep uploads in memory and serve them back instead of --root, never writing to disk; implies --upload and everything is lost on exit")]
+    pub tmpfs: bool,
+
+    #[arg(long, value_name = "PREFIX", value_parser = crate::config::parse_base_path, help = "URL prefix all routes are served under, e.g. /files when behind a reverse proxy")]
+    pub base_path: Option<String>,
+
+    #[arg(long = "mount", value_name = "PREFIX=DIR[:ro|:upload]", value_parser = crate::config::parse_mount, help = "Serve DIR under the URL prefix PREFIX, e.g. /docs=/home/me/docs; :ro refuses uploads there and :upload allows them regardless of --upload (repeatable)")]
+    pub mounts: Vec<crate::config::Mount>,
+
+    #[arg(long = "vhost", value_name = "HOST=DIR", value_parser = crate::config::parse_vhost, help = "Serve DIR to requests for HOST, e.g. photos.lan=/srv/photos; other hosts get --root (repeatable)")]
+    pub vhosts: Vec<(String, String)>,
+
+    #[arg(long = "proxy", value_name = "PREFIX=URL", value_parser = crate::proxy::parse_rule, help = "Forward requests under PREFIX, path unchanged, to an upstream HTTP server, e.g. /api=http://127.0.0.1:3000 (repeatable)")]
+    pub proxies: Vec<crate::proxy::ProxyRule>,
+
+    #[arg(long, value_name = "DIR", value_parser = crate::config::parse_cgi_dir, conflicts_with = "sandbox", help = "Run executable files in DIR, a directory below the root such as cgi-bin/, as CGI scripts instead of serving them")]
+    pub cgi: Option<String>,
 
-    #[arg(short, long, default_value_t = 8000, help = "Port to listen on")]
+    #[arg(
+        long = "plugin",
+        value_name = "FILE",
+        help = "WebAssembly module whose hooks can refuse requests and uploads or rewrite listings; see src/plugin.rs for the interface (repeatable)"
+    )]
+    pub plugins: Vec<PathBuf>,
+
+    #[arg(short, long, default_value = "0.0.0.0", value_parser = crate::listener::parse_ip, help = "IP address to bind to (IPv6 with or without brackets, e.g. :: or [::1])")]
+    pub ip: IpAddr,
+
+    #[arg(
+        short,
+        long,
+        default_value_t = 8000,
+        help = "Port to listen on (0 picks a free one)"
+    )]
     pub port: u16,
 
+    #[arg(long, value_name = "START-END", value_parser = crate::listener::parse_port_range, conflicts_with_all = ["port", "listen", "listen_fd", "unix_socket"], help = "Listen on the first free port in this range instead of --port, e.g. 8000-8100")]
+    pub port_range: Option<std::ops::RangeInclusive<u16>>,
+
+    #[arg(long, value_name = "ADDR:PORT", conflicts_with_all = ["ip", "port", "listen_fd", "unix_socket"], help = "Listen on this address instead of --ip/--port (repeatable, e.g. 127.0.0.1:8000 and [::1]:8000)")]
+    pub listen: Vec<SocketAddr>,
+
+    #[arg(
+        long,
+        value_name = "PEM",
+        requires = "tls_key",
+        help = "Serve HTTPS with this certificate chain (PEM)"
+    )]
+    pub tls_cert: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "PEM",
+        requires = "tls_cert",
+        help = "Private key for --tls-cert (PEM)"
+    )]
+    pub tls_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "tls_cert",
+        help = "Serve HTTPS with a certificate generated at startup (its fingerprint is logged)"
+    )]
+    pub tls_self_signed: bool,
+
+    #[arg(
+        long,
+        value_name = "PEM",
+        help = "Require HTTPS clients to present a certificate signed by this CA; with --auth it is accepted instead of the password"
+    )]
+    pub tls_client_ca: Option<PathBuf>,
+
+    #[arg(long, value_name = "DOMAIN", requires = "acme_state_dir", conflicts_with_all = ["tls_cert", "tls_self_signed"], help = "Obtain and renew a certificate for this domain via ACME HTTP-01 (repeatable)")]
+    pub acme_domain: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Directory for the ACME account, certificate and key (keep it outside the root)"
+    )]
+    pub acme_state_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "EMAIL",
+        help = "Contact address for the ACME account"
+    )]
+    pub acme_email: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        default_value = "https://acme-v02.api.letsencrypt.org/directory",
+        help = "ACME directory to order certificates from"
+    )]
+    pub acme_directory: String,
+
+    #[arg(
+        long,
+        conflicts_with = "acme_directory",
+        help = "Use the Let's Encrypt staging directory (untrusted certificates, higher rate limits)"
+    )]
+    pub acme_staging: bool,
+
+    #[arg(
+        long,
+        default_value_t = 80,
+        help = "Port answering ACME HTTP-01 challenges"
+    )]
+    pub acme_http_port: u16,
+
+    #[arg(
+        long,
+        value_name = "PORT",
+        help = "Also listen for plain HTTP on this port and redirect it to HTTPS"
+    )]
+    pub redirect_http: Option<u16>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "Send Strict-Transport-Security with this max-age (HTTPS only)"
+    )]
+    pub hsts: Option<u64>,
+
+    #[arg(
+        long,
+        requires = "hsts",
+        help = "Add includeSubDomains to the HSTS header"
+    )]
+    pub hsts_subdomains: bool,
+
+    #[arg(
+        long,
+        value_name = "POLICY",
+        help = "Content-Security-Policy for every response (replaces the directory listing default)"
+    )]
+    pub csp: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "POLICY",
+        help = "Referrer-Policy for every response (replaces the directory listing default)"
+    )]
+    pub referrer_policy: Option<String>,
+
+    #[arg(long, help = "Do not send X-Content-Type-Options: nosniff")]
+    pub no_nosniff: bool,
+
+    #[arg(long, value_name = "ORIGIN", value_parser = crate::cors::parse_origin, help = "Allow cross-origin requests from ORIGIN (e.g. http://localhost:3000) or from any origin with * (repeatable)")]
+    pub cors: Vec<String>,
+
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = crate::config::parse_header, help = "Add this header to every response, replacing one the server would send (repeatable)")]
+    pub headers: Vec<(hyper::header::HeaderName, hyper::header::HeaderValue)>,
+
+    #[arg(
+        long,
+        help = "Exit with an error when the root directory disappears or stops being a directory"
+    )]
+    pub exit_on_root_loss: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "acme_domain",
+        help = "Confine the process to the root directory with Landlock (Linux), or a chroot where Landlock is unavailable (needs root)"
+    )]
+    pub sandbox: bool,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 30,
+        help = "On SIGINT/SIGTERM, how long to let open transfers finish before exiting"
+    )]
+    pub drain_timeout: u64,
+
+    #[arg(
+        long,
+        help = "Serve on an inherited, already listening socket instead of binding --ip/--port (Unix)"
+    )]
+    pub listen_fd: Option<i32>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        conflicts_with = "listen_fd",
+        help = "Serve on a Unix domain socket at this path instead of binding --ip/--port (Unix)"
+    )]
+    pub unix_socket: Option<PathBuf>,
+
+    #[arg(long, value_name = "OCTAL", default_value = "660", value_parser = crate::listener::parse_mode, help = "Permissions of the --unix-socket file")]
+    pub unix_socket_mode: u32,
+
+    #[arg(
+        long,
+        default_value_t = 1024,
+        help = "Maximum number of pending connections in the accept queue"
+    )]
+    pub backlog: i32,
+
+    #[arg(
+        long,
+        help = "Set SO_REUSEPORT so several samserve processes can share the port (Unix)"
+    )]
+    pub reuse_port: bool,
+
+    #[arg(
+        long,
+        help = "Let IPv6 listeners also accept IPv4 connections (they are IPv6-only otherwise)"
+    )]
+    pub dual_stack: bool,
+
+    #[arg(
+        long,
+        value_name = "USER",
+        help = "Switch to this user (name or uid) once the sockets are bound, e.g. to serve port 80 without staying root (Unix)"
+    )]
+    pub user: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "GROUP",
+        requires = "user",
+        help = "Switch to this group (name or gid) instead of the --user's primary group"
+    )]
+    pub group: Option<String>,
+
+    #[arg(
+        long,
+        help = "Detach from the terminal and run in the background (Unix)"
+    )]
+    pub daemon: bool,
+
+    #[arg(long, help = "Write the daemon's process id to this file")]
+    pub pidfile: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Append log output to this file in --daemon mode (default: discard)"
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = ColorChoice::Auto, help = "Color log output: auto (only on a terminal), always or never")]
+    pub color: ColorChoice,
+
     #[arg(short, long, default_value = "false", help = "Enable upload support")]
     pub upload: bool,
 
-    #[arg(short, long, help = "Enable basic authentication. Format: username:password")]
+    #[arg(
+        long,
+        help = "Serve the root over WebDAV so it can be mounted as a network drive (writing needs --upload)"
+    )]
+    pub webdav: bool,
+
+    #[arg(long, help = "Send a SHA-256 Digest header with file responses")]
+    pub digest: bool,
+
+    #[arg(
+        long,
+        help = "Compress text-like files (HTML, CSS, JS, JSON, logs, ...) on the fly with zstd, brotli or gzip, as the client accepts"
+    )]
+    pub compress: bool,
+
+    #[arg(
+        long,
+        value_name = "BYTES",
+        default_value_t = 1024,
+        requires = "compress",
+        help = "Files smaller than this are sent uncompressed"
+    )]
+    pub compress_min_size: u64,
+
+    #[arg(
+        long,
+        value_name = "EXTS",
+        value_delimiter = ',',
+        requires = "compress",
+        help = "Comma-separated extensions never compressed, besides the built-in jpg, png, gif, webp, mp4, zip, gz, ... (repeatable)"
+    )]
+    pub no_compress_ext: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Serve precompressed siblings (app.js.br, .zst or .gz) in place of a file when the client accepts that coding"
+    )]
+    pub precompressed: bool,
+
+    #[arg(long = "cache", value_name = "PATTERN=DIRECTIVES", value_parser = crate::cache::parse_rule, help = "Cache-Control sent with files matching PATTERN, e.g. \"*.css=max-age=604800\"; patterns with a / match the path below the root (repeatable, first match wins)")]
+    pub cache: Vec<crate::cache::CacheRule>,
+
+    #[arg(long, value_name = "PATTERN", value_parser = crate::cache::parse_no_cache, help = "Send Cache-Control: no-cache with files matching PATTERN; takes precedence over --cache (repeatable)")]
+    pub no_cache: Vec<crate::cache::CacheRule>,
+
+    #[arg(
+        long,
+        help = "Detect the Content-Type of files without a known extension from their first bytes"
+    )]
+    pub mime_sniff: bool,
+
+    #[arg(
+        long,
+        value_name = "NAMES",
+        value_delimiter = ',',
+        default_value = "index.html",
+        help = "Comma-separated files served in place of a directory listing, first match wins"
+    )]
+    pub index: Vec<String>,
+
+    #[arg(
+        long,
+        conflicts_with = "index",
+        help = "Always list directories, even when they contain an index file"
+    )]
+    pub no_index: bool,
+
+    #[arg(
+        long,
+        help = "Answer 403 for directories without an index file instead of listing them"
+    )]
+    pub no_listing: bool,
+
+    #[arg(
+        long,
+        help = "Serve and list dotfiles such as .env and .git, which are hidden by default"
+    )]
+    pub hidden: bool,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        default_value = ".well-known",
+        conflicts_with = "hidden",
+        help = "Dotfiles to serve and list even though they are hidden; globs with a / match the path below the root (repeatable, replaces the default)"
+    )]
+    pub dotfile_allow: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Hide matching paths from listings, requests and uploads, e.g. '*.key' or 'secrets/**'; globs with a / match the path below the root (repeatable, added to the root's .samserveignore)"
+    )]
+    pub exclude: Vec<String>,
+
+    #[arg(long, value_enum, default_value_t = crate::symlinks::FollowSymlinks::Always, help = "Which symlinks are followed: never, within-root (only those whose target stays inside the root) or always")]
+    pub follow_symlinks: crate::symlinks::FollowSymlinks,
+
+    #[arg(
+        long,
+        help = "Page served at / when the root directory has no index file"
+    )]
+    pub welcome_page: Option<PathBuf>,
+
+    #[arg(
+        long,
+        help = "Serve the root index file with 200 for paths that do not exist, for single-page apps with client-side routing"
+    )]
+    pub spa: bool,
+
+    #[arg(
+        long = "var",
+        value_name = "NAME=VALUE",
+        value_parser = crate::template::parse_var,
+        help = "Variable available as {{NAME}} in the welcome and auth pages (repeatable)"
+    )]
+    pub vars: Vec<(String, String)>,
+
+    #[arg(
+        long,
+        help = "Serve page.<lang>.html variants according to Accept-Language"
+    )]
+    pub lang_negotiation: bool,
+
+    #[arg(
+        long,
+        help = "Count completed downloads per file and show them in listings"
+    )]
+    pub download_stats: bool,
+
+    #[arg(
+        long,
+        help = "JSON file the download counts are loaded from and saved to on shutdown"
+    )]
+    pub download_stats_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "STRFTIME", default_value = "%Y-%m-%d %H:%M", value_parser = crate::timefmt::parse_date_format, help = "strftime format of modification dates in directory listings")]
+    pub date_format: String,
+
+    #[arg(long, value_name = "local|utc|+HH:MM", default_value = "local", value_parser = crate::timefmt::parse_timezone, help = "Timezone of modification dates in directory listings")]
+    pub timezone: crate::timefmt::Timezone,
+
+    #[arg(
+        long,
+        help = "Show the total size of directories in listings (computed recursively and cached)"
+    )]
+    pub dir_sizes: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Deepest directory level walked by recursive operations such as --dir-sizes (1 = only the directory itself)"
+    )]
+    pub max_depth: Option<usize>,
+
+    #[arg(long, help = "Show image thumbnails in directory listings")]
+    pub thumbnails: bool,
+
+    #[arg(
+        long,
+        value_name = "NAME",
+        default_value = "file",
+        help = "Multipart field holding the uploaded file (\"*\" accepts any field with a filename)"
+    )]
+    pub upload_field: String,
+
+    #[arg(
+        long,
+        value_name = "PREFIX",
+        help = "URL prefix removed from upload paths before mapping them under the root"
+    )]
+    pub upload_strip_prefix: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = 4096,
+        help = "Maximum length in bytes of a decoded request path (longer ones get 414)"
+    )]
+    pub max_path_length: usize,
+
+    #[arg(
+        long,
+        help = "Skip uploads identical (same size and SHA-256) to the existing file"
+    )]
+    pub dedupe: bool,
+
+    #[arg(
+        long,
+        default_value_t = 64,
+        help = "Maximum number of fields accepted in a multipart upload"
+    )]
+    pub max_fields: usize,
+
+    #[arg(
+        long,
+        default_value_t = 256,
+        help = "Maximum length in bytes of a multipart field name"
+    )]
+    pub max_field_name_size: usize,
+
+    #[arg(
+        short,
+        long,
+        help = "Enable basic authentication. Format: username:password, where the password may be a SHA-crypt ($5$/$6$, e.g. from openssl passwd -6), bcrypt or argon2 hash so it never appears in plain text"
+    )]
     pub auth: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Accept the accounts in this htpasswd-style file (user:hash per line, SHA-crypt, bcrypt, argon2 or plain text); re-read on SIGHUP"
+    )]
+    pub auth_file: Option<PathBuf>,
+
+    #[arg(
+        long = "token",
+        value_name = "SECRET",
+        help = "Accept requests with the header Authorization: Bearer SECRET, as an alternative to Basic Auth (repeatable)"
+    )]
+    pub tokens: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Ask for RFC 7616 Digest auth (SHA-256) instead of Basic Auth, so passwords never cross the wire; needs plain-text --auth or --auth-file passwords"
+    )]
+    pub digest_auth: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "digest_auth",
+        help = "Ask browsers to log in on an HTML form at /_login and keep them logged in with a session cookie, instead of the Basic Auth prompt; /_logout ends the session"
+    )]
+    pub login_page: bool,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 86400,
+        requires = "login_page",
+        help = "How long a --login-page session lasts"
+    )]
+    pub session_lifetime: u64,
+
+    #[arg(
+        long,
+        value_name = "GLOB",
+        help = "Only ask for authentication on matching paths, e.g. '/private/**'; the rest is public. Globs with a / match the path below the root (repeatable)"
+    )]
+    pub protect: Vec<String>,
+
+    #[arg(
+        long,
+        help = "Let anyone read (GET, HEAD, PROPFIND) and only ask for authentication on requests that change files"
+    )]
+    pub protect_writes: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with = "digest_auth",
+        help = "Only accept the accounts in this htpasswd-style file, over Basic Auth, for requests that change files; reads keep --auth, --auth-file and --token. Re-read on SIGHUP"
+    )]
+    pub write_auth_file: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 10,
+        help = "Refuse a client for --auth-ban-time after this many failed logins in a row (0 to never)"
+    )]
+    pub auth_max_failures: u32,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value_t = 600,
+        help = "How long a client that reached --auth-max-failures is refused"
+    )]
+    pub auth_ban_time: u64,
+
+    #[arg(
+        long,
+        value_name = "RATE",
+        value_parser = crate::ratelimit::parse_rate_limit,
+        help = "Answer clients sending more requests than this with 429 Too Many Requests, per IP address, e.g. '10r/s burst=20' (r/s, r/m or r/h; the burst defaults to one second's worth)"
+    )]
+    pub rate_limit: Option<crate::ratelimit::RateLimit>,
+
+    #[arg(
+        long,
+        value_name = "RATE",
+        value_parser = crate::bandwidth::parse_bandwidth,
+        help = "Cap the bytes per second sent across all responses together, e.g. 50MBps, 10MiB/s or 100Mbps"
+    )]
+    pub max_bandwidth: Option<u64>,
+
+    #[arg(
+        long,
+        default_value = "Restricted",
+        help = "Realm sent in the WWW-Authenticate challenge"
+    )]
+    pub auth_realm: String,
+
+    #[arg(
+        long,
+        help = "HTML page served as the body of 401 Unauthorized responses"
+    )]
+    pub auth_page: Option<PathBuf>,
+
+    #[arg(long = "error-page", value_name = "STATUS=FILE", value_parser = crate::config::parse_error_page, help = "Page sent as the body of STATUS error responses, relative to the root; 403.html and 404.html are used by default when they exist (repeatable)")]
+    pub error_pages: Vec<(u16, PathBuf)>,
+
+    #[arg(long, help = "Expose Prometheus metrics")]
+    pub metrics: bool,
+
+    #[arg(
+        long,
+        default_value = "/metrics",
+        help = "URL path of the metrics endpoint"
+    )]
+    pub metrics_path: String,
+
+    #[arg(long, help = "Serve metrics on this port instead of the main listener")]
+    pub metrics_port: Option<u16>,
+
+    #[arg(
+        long,
+        help = "Honor X-HTTP-Method-Override / ?_method= on POST requests (GET, HEAD, PUT, DELETE only)"
+    )]
+    pub allow_method_override: bool,
+}
+
+impl Args {
+    /// Parses the process arguments, merged with the `--config` file.
+    /// Exits with a usage error like `Args::parse` does.
+    pub fn load() -> Args {
+        Args::load_from(std::env::args_os().collect()).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Parses `cli` (program name first). Options it does not set are taken
+    /// from the `--config` file, if one is given.
+    pub fn load_from(cli: Vec<OsString>) -> Result<Args, clap::Error> {
+        // A first, lenient pass finds the file and what the command line
+        // sets; requirements may well be met by the file.
+        let matches = Args::command()
+            .ignore_errors(true)
+            .try_get_matches_from(&cli)?;
+        let Some(path) = matches.get_one::<PathBuf>("config") else {
+            return Args::try_parse_from(cli);
+        };
+        let on_command_line = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        let (program, flags) = cli.split_first().expect("program name");
+        let mut merged = vec![program.clone()];
+        merged.extend(file_arguments(path, on_command_line)?);
+        merged.extend_from_slice(flags);
+        Args::try_parse_from(merged)
+    }
+
+    /// Whether the server speaks HTTPS, with any of the certificate options.
+    pub fn serves_https(&self) -> bool {
+        self.tls_cert.is_some() || self.tls_self_signed || !self.acme_domain.is_empty()
+    }
+}
+
+/// Turns the TOML file at `path` into `--name=value` arguments. Keys are
+/// long option names (`max-depth` or `max_depth`); `true` stands for a flag
+/// given, arrays for a repeated option and tables for `NAME=VALUE` pairs.
+/// Options `skip` returns true for are left out.
+fn file_arguments(path: &Path, skip: impl Fn(&str) -> bool) -> Result<Vec<OsString>, clap::Error> {
+    let command = Args::command();
+    let invalid = |message: String| {
+        Args::command().error(
+            ErrorKind::InvalidValue,
+            format!("{}: {}", path.display(), message),
+        )
+    };
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        Args::command().error(
+            ErrorKind::Io,
+            format!("failed to read {}: {}", path.display(), e),
+        )
+    })?;
+    let table: toml::Table = text
+        .parse()
+        .map_err(|e: toml::de::Error| invalid(e.to_string().trim_end().to_string()))?;
+    let mut out = Vec::new();
+    for (key, value) in table {
+        let long = key.replace('_', "-");
+        let arg = command
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && long != "config")
+            .ok_or_else(|| invalid(format!("unknown option {:?}", key)))?;
+        if skip(arg.get_id().as_str()) {
+            continue;
+        }
+        let values = match value {
+            toml::Value::Array(items) => items,
+            toml::Value::Table(pairs) => pairs
+                .into_iter()
+                .map(|(name, value)| {
+                    Ok(toml::Value::String(format!(
+                        "{}={}",
+                        name,
+                        scalar(&key, value)?
+                    )))
+                })
+                .collect::<Result<_, String>>()
+                .map_err(invalid)?,
+            value => vec![value],
+        };
+        for value in values {
+            match value {
+                toml::Value::Boolean(true) => out.push(format!("--{}", long).into()),
+                toml::Value::Boolean(false) => {}
+                value => {
+                    out.push(format!("--{}={}", long, scalar(&key, value).map_err(invalid)?).into())
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn scalar(key: &str, value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Integer(i) => Ok(i.to_string()),
+        toml::Value::Float(f) => Ok(f.to_string()),
+        toml::Value::Datetime(d) => Ok(d.to_string()),
+        _ => Err(format!("unsupported value for {:?}", key)),
+    }
+}
+
+/// When log lines get ANSI colors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write as _;
+
+    fn load(file: &str, flags: &[&str]) -> Result<Args, clap::Error> {
+        let mut config = tempfile::NamedTempFile::new().unwrap();
+        config.write_all(file.as_bytes()).unwrap();
+        let mut cli: Vec<OsString> =
+            vec!["samserve".into(), "--config".into(), config.path().into()];
+        cli.extend(flags.iter().map(OsString::from));
+        Args::load_from(cli)
+    }
+
+    #[test]
+    fn file_values_fill_in_options() {
+        let args = load(
+            "root = \"/srv\"\nport = 9000\nupload = true\nmax_depth = 3\n\
+             acme-domain = [\"a.test\", \"b.test\"]\nacme-state-dir = \"/var/lib/samserve\"\n\
+             \n[var]\nTITLE = \"Files\"\n",
+            &[],
+        )
+        .unwrap();
+        assert_eq!(args.root, "/srv");
+        assert_eq!(args.port, 9000);
+        assert!(args.upload);
+        assert_eq!(args.max_depth, Some(3));
+        assert_eq!(args.acme_domain, ["a.test", "b.test"]);
+        assert!(args.serves_https());
+        assert_eq!(args.vars, [("TITLE".to_string(), "Files".to_string())]);
+    }
+
+    #[test]
+    fn command_line_overrides_the_file() {
+        let args = load(
+            "port = 9000\nacme-domain = [\"a.test\"]\nacme-state-dir = \"/var/lib/samserve\"\n",
+            &["--port", "9001", "--acme-domain", "c.test"],
+        )
+        .unwrap();
+        assert_eq!(args.port, 9001);
+        assert_eq!(args.acme_domain, ["c.test"]);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let err = load("prot = 9000\n", &[]).unwrap_err();
+        assert!(err.to_string().contains("unknown option \"prot\""));
+    }
 }
@@ -0,0 +1,176 @@
+//! `--max-bandwidth`: a cap on the bytes per second sent across all
+//! responses together, so the server does not saturate the uplink. Every
+//! response body draws from one shared schedule: each chunk books the time
+//! it takes at the configured rate, and waits for its slot before going out.
+
+use futures_util::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, Response, header};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::time::Sleep;
+
+/// Largest piece booked at once, so one big chunk does not hold up the
+/// other transfers for long.
+const MAX_PIECE: usize = 64 * 1024;
+
+/// How far the schedule may fall behind while idle, letting a short burst
+/// through at full speed.
+const BURST: Duration = Duration::from_millis(100);
+
+/// Parses a rate such as `50MBps`, `50MB/s`, `10MiB/s` or `100Mbps` (bits)
+/// into bytes per second.
+pub fn parse_bandwidth(s: &str) -> Result<u64, String> {
+    let rate = s
+        .strip_suffix("ps")
+        .or_else(|| s.strip_suffix("/s"))
+        .unwrap_or(s);
+    let split = rate
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(rate.len());
+    let (number, unit) = rate.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid bandwidth {:?}, expected e.g. 50MBps", s))?;
+    let bytes = match unit {
+        "" | "B" => 1.0,
+        "KB" | "kB" => 1e3,
+        "MB" => 1e6,
+        "GB" => 1e9,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        "b" => 1.0 / 8.0,
+        "Kb" | "kb" => 1e3 / 8.0,
+        "Mb" => 1e6 / 8.0,
+        "Gb" => 1e9 / 8.0,
+        _ => return Err(format!("unknown bandwidth unit {:?} in {:?}", unit, s)),
+    };
+    let rate = (number * bytes).round();
+    if rate < 1.0 || !rate.is_finite() {
+        return Err(format!("bandwidth {:?} is below one byte per second", s));
+    }
+    Ok(rate as u64)
+}
+
+/// The schedule shared by all responses.
+#[derive(Debug)]
+pub struct Bandwidth {
+    bytes_per_second: u64,
+    /// When the data booked so far will have been sent.
+    booked_until: Mutex<Instant>,
+}
+
+impl Bandwidth {
+    pub fn new(bytes_per_second: u64) -> Bandwidth {
+        Bandwidth {
+            bytes_per_second,
+            booked_until: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// Books `len` bytes, returning how long to wait before sending them.
+    fn book(&self, len: usize) -> Duration {
+        let now = Instant::now();
+        let mut booked_until = self.booked_until.lock().unwrap();
+        let start = (*booked_until).max(now.checked_sub(BURST).unwrap_or(now));
+        *booked_until = start + Duration::from_secs_f64(len as f64 / self.bytes_per_second as f64);
+        booked_until.saturating_duration_since(now)
+    }
+}
+
+/// Sends the body of `response` no faster than `bandwidth` allows.
+pub fn throttle(response: Response<Body>, bandwidth: &Arc<Bandwidth>) -> Response<Body> {
+    if response.body().is_end_stream() {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Wrapping hides the body's exact size from hyper, so keep it explicit.
+    if !parts.headers.contains_key(header::CONTENT_LENGTH)
+        && let Some(len) = HttpBody::size_hint(&body).exact()
+    {
+        parts.headers.insert(header::CONTENT_LENGTH, len.into());
+    }
+    let body = Body::wrap_stream(Throttled {
+        inner: body,
+        bandwidth: Arc::clone(bandwidth),
+        pending: Bytes::new(),
+        delay: None,
+    });
+    Response::from_parts(parts, body)
+}
+
+struct Throttled {
+    inner: Body,
+    bandwidth: Arc<Bandwidth>,
+    /// Data taken from `inner` and not yet sent.
+    pending: Bytes,
+    /// The wait before the next piece of `pending`, which is booked already.
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Stream for Throttled {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(delay) = &mut self.delay {
+                if delay.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.delay = None;
+                let len = self.pending.len().min(MAX_PIECE);
+                return Poll::Ready(Some(Ok(self.pending.split_to(len))));
+            }
+            if self.pending.is_empty() {
+                match Pin::new(&mut self.inner).poll_data(cx) {
+                    Poll::Ready(Some(Ok(chunk))) => self.pending = chunk,
+                    other => return other,
+                }
+                continue;
+            }
+            let wait = self.bandwidth.book(self.pending.len().min(MAX_PIECE));
+            self.delay = Some(Box::pin(tokio::time::sleep(wait)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_bandwidth("50MBps"), Ok(50_000_000));
+        assert_eq!(parse_bandwidth("50MB/s"), Ok(50_000_000));
+        assert_eq!(parse_bandwidth("1.5KiB/s"), Ok(1536));
+        assert_eq!(parse_bandwidth("100Mbps"), Ok(12_500_000));
+        assert_eq!(parse_bandwidth("4096"), Ok(4096));
+        assert!(parse_bandwidth("fast").is_err());
+        assert!(parse_bandwidth("10XBps").is_err());
+        assert!(parse_bandwidth("0MBps").is_err());
+    }
+
+    #[tokio::test]
+    async fn caps_the_rate_of_all_bodies_together() {
+        let bandwidth = Arc::new(Bandwidth::new(100_000));
+        let response = |len| throttle(Response::new(Body::from(vec![0; len])), &bandwidth);
+        let (first, second) = (response(15_000), response(15_000));
+        assert_eq!(first.headers()[header::CONTENT_LENGTH], "15000");
+
+        let start = Instant::now();
+        let (first, second) = tokio::join!(
+            hyper::body::to_bytes(first.into_body()),
+            hyper::body::to_bytes(second.into_body())
+        );
+        assert_eq!(first.unwrap().len() + second.unwrap().len(), 30_000);
+        // 30 kB at 100 kB/s, less the 100 ms burst allowance.
+        assert!(start.elapsed() >= Duration::from_millis(190));
+    }
+}
@@ -0,0 +1,81 @@
+//! `--cache` and `--no-cache`: `Cache-Control` headers for files, chosen by
+//! matching the file against glob patterns.
+
+use crate::pathfilter;
+
+/// Directory listings change whenever a file is added, so caches must
+/// always revalidate them.
+pub const LISTING_CACHE_CONTROL: &str = "no-cache";
+
+/// A `PATTERN=DIRECTIVES` rule; see `pathfilter::matches` for patterns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheRule {
+    pub pattern: String,
+    pub directives: String,
+}
+
+/// Parses a `--cache PATTERN=DIRECTIVES` argument.
+pub fn parse_rule(s: &str) -> Result<CacheRule, String> {
+    match s.split_once('=') {
+        Some((pattern, directives))
+            if !pattern.trim().is_empty() && !directives.trim().is_empty() =>
+        {
+            let directives = directives.trim();
+            if hyper::header::HeaderValue::from_str(directives).is_err() {
+                return Err(format!("{:?} is not a valid header value", directives));
+            }
+            Ok(CacheRule {
+                pattern: pattern.trim().to_string(),
+                directives: directives.to_string(),
+            })
+        }
+        _ => Err(format!("expected PATTERN=DIRECTIVES, got {:?}", s)),
+    }
+}
+
+/// Parses a `--no-cache PATTERN` argument.
+pub fn parse_no_cache(s: &str) -> Result<CacheRule, String> {
+    if s.trim().is_empty() {
+        return Err("expected a pattern".to_string());
+    }
+    Ok(CacheRule {
+        pattern: s.trim().to_string(),
+        directives: "no-cache".to_string(),
+    })
+}
+
+/// Returns the directives of the first rule matching `path`, a path below
+/// the root starting with `/`.
+pub fn cache_control<'a>(rules: &'a [CacheRule], path: &str) -> Option<&'a str> {
+    rules
+        .iter()
+        .find(|rule| pathfilter::matches(&rule.pattern, path))
+        .map(|rule| rule.directives.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_names_and_paths() {
+        let rules = [
+            parse_rule("/assets/*=max-age=31536000, immutable").unwrap(),
+            parse_rule("*.css=max-age=604800").unwrap(),
+            parse_rule("page-?.html=no-store").unwrap(),
+        ];
+        assert_eq!(
+            cache_control(&rules, "/assets/img/logo.png"),
+            Some("max-age=31536000, immutable")
+        );
+        assert_eq!(
+            cache_control(&rules, "/css/site.css"),
+            Some("max-age=604800")
+        );
+        assert_eq!(cache_control(&rules, "/page-1.html"), Some("no-store"));
+        assert_eq!(cache_control(&rules, "/page-10.html"), None);
+        assert_eq!(cache_control(&rules, "/site.css.map"), None);
+        assert!(parse_rule("*.css").is_err());
+        assert!(parse_rule("=max-age=60").is_err());
+    }
+}
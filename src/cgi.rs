@@ -0,0 +1,378 @@
+//! `--cgi`: runs the scripts in one directory below the root as CGI/1.1
+//! programs (RFC 3875) instead of serving them as files.
+
+use crate::config::Config;
+use futures_util::TryStreamExt;
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{Body, Request, Response, StatusCode};
+use log::{error, info, warn};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// Most header lines a script may print before its body.
+const MAX_HEADER_LINES: usize = 100;
+
+/// Whether a decoded request path lies in the CGI directory `dir`.
+pub fn handles(path: &str, dir: &str) -> bool {
+    path.strip_prefix(dir)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Whether `path`, below the root in the form `pathfilter::relative_path`
+/// gives, is in the CGI directory `dir` or contains it. Files must not be
+/// uploaded or moved there, which could turn them into scripts, nor moved
+/// out, which would serve scripts as files.
+pub fn overlaps(path: &str, dir: &str) -> bool {
+    handles(path, dir)
+        || dir
+            .strip_prefix(path)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Runs the script a request names and answers with its output. The first
+/// file along the path is the script; what follows it is `PATH_INFO`.
+/// `user` is the user the server authenticated, if any.
+pub async fn run(
+    req: Request<Body>,
+    decoded_path: &str,
+    user: Option<&str>,
+    config: &Config,
+    remote_addr: SocketAddr,
+) -> Response<Body> {
+    let Some((script, script_name, path_info)) = find_script(decoded_path, &config.root).await
+    else {
+        warn!(
+            "CGI script not found | path: {:?} | status: {} | remote: {}",
+            decoded_path,
+            StatusCode::NOT_FOUND,
+            remote_addr
+        );
+        return reply(StatusCode::NOT_FOUND, "Script not found");
+    };
+    if !is_executable(&script).await {
+        warn!(
+            "CGI script is not executable | script: {:?} | status: {} | remote: {}",
+            script,
+            StatusCode::FORBIDDEN,
+            remote_addr
+        );
+        return reply(StatusCode::FORBIDDEN, "Script not executable");
+    }
+
+    let mut command = Command::new(&script);
+    command
+        .env_clear()
+        .envs(environment(
+            &req,
+            &format!("{}{}", config.base_path, script_name),
+            &path_info,
+            user,
+            config,
+            remote_addr,
+        ))
+        .current_dir(script.parent().unwrap_or(Path::new(&config.root)))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(err) => {
+            error!(
+                "CGI script failed to start | script: {:?} | error: {} | status: {} | remote: {}",
+                script,
+                err,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                remote_addr
+            );
+            return reply(StatusCode::INTERNAL_SERVER_ERROR, "Script failed");
+        }
+    };
+
+    // The body is fed to the script while its output is read, so neither
+    // side waits on a full pipe.
+    let mut stdin = child.stdin.take().unwrap();
+    let mut body = StreamReader::new(req.into_body().map_err(std::io::Error::other));
+    tokio::spawn(async move {
+        let _ = tokio::io::copy(&mut body, &mut stdin).await;
+        let _ = stdin.shutdown().await;
+    });
+    let mut stdout = BufReader::new(child.stdout.take().unwrap());
+    // Reaps the script once it exits.
+    tokio::spawn(async move {
+        let _ = child.wait().await;
+    });
+
+    let header_lines = read_header_lines(&mut stdout).await;
+    let builder = match header_lines.and_then(|lines| parse_headers(&lines)) {
+        Ok(builder) => builder,
+        Err(reason) => {
+            error!(
+                "CGI script sent an invalid response | script: {:?} | error: {} | status: {} | remote: {}",
+                script,
+                reason,
+                StatusCode::BAD_GATEWAY,
+                remote_addr
+            );
+            return reply(StatusCode::BAD_GATEWAY, "Invalid script response");
+        }
+    };
+    let response = builder
+        .body(Body::wrap_stream(ReaderStream::new(stdout)))
+        .unwrap();
+    info!(
+        "CGI | script: {:?} | path_info: {:?} | status: {} | remote: {}",
+        script,
+        path_info,
+        response.status(),
+        remote_addr
+    );
+    response
+}
+
+fn reply(status: StatusCode, body: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// Walks `decoded_path` from the root until it reaches a file. Returns the
+/// file, its URL path and the rest of the request path.
+async fn find_script(decoded_path: &str, root: &str) -> Option<(PathBuf, String, String)> {
+    let mut path = PathBuf::from(root);
+    let mut script_name = String::new();
+    let mut parts = decoded_path.split('/').filter(|part| !part.is_empty());
+    while let Some(part) = parts.next() {
+        if part == "." || part == ".." {
+            return None;
+        }
+        path.push(part);
+        script_name.push('/');
+        script_name.push_str(part);
+        let metadata = fs::metadata(&path).await.ok()?;
+        if metadata.is_file() {
+            let path_info = parts.map(|part| format!("/{}", part)).collect();
+            return Some((path, script_name, path_info));
+        }
+    }
+    None
+}
+
+#[cfg(unix)]
+async fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .await
+        .is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+async fn is_executable(_path: &Path) -> bool {
+    true
+}
+
+/// The meta-variables of RFC 3875, section 4.1, plus `HTTP_*` ones for the
+/// request headers.
+fn environment(
+    req: &Request<Body>,
+    script_name: &str,
+    path_info: &str,
+    user: Option<&str>,
+    config: &Config,
+    remote_addr: SocketAddr,
+) -> Vec<(String, String)> {
+    let header_str = |name: HeaderName| {
+        req.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let host = header_str(header::HOST)
+        .or_else(|| req.uri().authority().map(|a| a.to_string()))
+        .unwrap_or_default();
+    let (server_name, server_port) = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => (name.to_string(), port.to_string()),
+        _ => (host.clone(), "80".to_string()),
+    };
+    let mut env = vec![
+        ("GATEWAY_INTERFACE", "CGI/1.1".to_string()),
+        (
+            "SERVER_SOFTWARE",
+            format!("samserve/{}", env!("CARGO_PKG_VERSION")),
+        ),
+        ("SERVER_PROTOCOL", format!("{:?}", req.version())),
+        ("SERVER_NAME", server_name),
+        ("SERVER_PORT", server_port),
+        ("REQUEST_METHOD", req.method().to_string()),
+        ("QUERY_STRING", req.uri().query().unwrap_or("").to_string()),
+        ("SCRIPT_NAME", script_name.to_string()),
+        ("PATH_INFO", path_info.to_string()),
+        ("REMOTE_ADDR", remote_addr.ip().to_string()),
+        ("REMOTE_PORT", remote_addr.port().to_string()),
+        ("PATH", std::env::var("PATH").unwrap_or_default()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect::<Vec<_>>();
+    if !path_info.is_empty() {
+        let translated = Path::new(&config.root).join(path_info.trim_start_matches('/'));
+        env.push((
+            "PATH_TRANSLATED".to_string(),
+            translated.to_string_lossy().into_owned(),
+        ));
+    }
+    if let Some(content_type) = header_str(header::CONTENT_TYPE) {
+        env.push(("CONTENT_TYPE".to_string(), content_type));
+    }
+    if let Some(length) = header_str(header::CONTENT_LENGTH) {
+        env.push(("CONTENT_LENGTH".to_string(), length));
+    }
+    if let Some(user) = user {
+        // A session cookie has no scheme to name.
+        if let Some((scheme, _)) = header_str(header::AUTHORIZATION)
+            .as_deref()
+            .and_then(|v| v.split_once(' '))
+        {
+            env.push(("AUTH_TYPE".to_string(), scheme.to_string()));
+        }
+        env.push(("REMOTE_USER".to_string(), user.to_string()));
+    }
+    for (name, value) in req.headers() {
+        // Credentials stay with the server; the length and type are above.
+        // `Proxy` would become `HTTP_PROXY`, which many HTTP clients take
+        // as their outbound proxy (httpoxy).
+        if matches!(
+            *name,
+            header::AUTHORIZATION
+                | header::PROXY_AUTHORIZATION
+                | header::CONTENT_TYPE
+                | header::CONTENT_LENGTH
+        ) || name.as_str() == "proxy"
+        {
+            continue;
+        }
+        let Ok(value) = value.to_str() else { continue };
+        let key = format!(
+            "HTTP_{}",
+            name.as_str().to_ascii_uppercase().replace('-', "_")
+        );
+        match env.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, existing)) => {
+                existing.push_str(", ");
+                existing.push_str(value);
+            }
+            None => env.push((key, value.to_string())),
+        }
+    }
+    env
+}
+
+/// Reads the header lines a script printed, up to the blank line that
+/// starts its body.
+async fn read_header_lines<R: tokio::io::AsyncBufRead + Unpin>(
+    stdout: &mut R,
+) -> Result<Vec<String>, String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        match stdout.read_line(&mut line).await {
+            Ok(0) => return Ok(lines),
+            Ok(_) => {}
+            Err(err) => return Err(err.to_string()),
+        }
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return Ok(lines);
+        }
+        if lines.len() == MAX_HEADER_LINES {
+            return Err("too many header lines".to_string());
+        }
+        lines.push(line.to_string());
+    }
+}
+
+/// Turns the header lines a script printed into a response. `Status`
+/// sets the status; a `Location` without one redirects.
+fn parse_headers(lines: &[String]) -> Result<hyper::http::response::Builder, String> {
+    let mut builder = Response::builder();
+    let mut status = None;
+    let mut has_location = false;
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| format!("malformed header line {:?}", line))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("status") {
+            let code = value.split_whitespace().next().unwrap_or("");
+            status = Some(
+                code.parse::<StatusCode>()
+                    .map_err(|_| format!("invalid status {:?}", value))?,
+            );
+            continue;
+        }
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|_| format!("invalid header name {:?}", name))?;
+        let value =
+            HeaderValue::from_str(value).map_err(|_| format!("invalid value for {}", name))?;
+        has_location |= name == header::LOCATION;
+        builder = builder.header(name, value);
+    }
+    if lines.is_empty() {
+        return Err("no headers".to_string());
+    }
+    let status = status.unwrap_or(if has_location {
+        StatusCode::FOUND
+    } else {
+        StatusCode::OK
+    });
+    Ok(builder.status(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn parses_script_headers() {
+        let response = parse_headers(&lines("Content-Type: text/plain\nX-Id: 7"))
+            .unwrap()
+            .body(())
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-id"], "7");
+
+        let response = parse_headers(&lines("Status: 404 Not Found"))
+            .unwrap()
+            .body(())
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = parse_headers(&lines("Location: /elsewhere"))
+            .unwrap()
+            .body(())
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+
+        assert!(parse_headers(&lines("not a header")).is_err());
+        assert!(parse_headers(&[]).is_err());
+    }
+
+    #[test]
+    fn finds_paths_overlapping_the_cgi_directory() {
+        assert!(overlaps("/srv/cgi-bin", "/srv/cgi-bin"));
+        assert!(overlaps("/srv/cgi-bin/run.sh", "/srv/cgi-bin"));
+        assert!(overlaps("/srv", "/srv/cgi-bin"));
+        assert!(!overlaps("/srv/cgi-bin2", "/srv/cgi-bin"));
+        assert!(!overlaps("/sr", "/srv/cgi-bin"));
+        assert!(!overlaps("/other", "/srv/cgi-bin"));
+    }
+}
@@ -0,0 +1,609 @@
+use crate::archive::{self, ArchiveStorage};
+use crate::args::Args;
+use crate::bandwidth::Bandwidth;
+use crate::cache::CacheRule;
+use crate::digest_auth::DigestAuth;
+use crate::embed::{self, EmbeddedStorage};
+use crate::lockout::Lockout;
+use crate::memory::MemoryStorage;
+use crate::pathfilter;
+use crate::plugin::Plugin;
+use crate::proxy::ProxyRule;
+use crate::ratelimit::RateLimiter;
+use crate::s3::S3Storage;
+use crate::session::Sessions;
+use crate::storage::{LocalStorage, Storage};
+use crate::symlinks::FollowSymlinks;
+use crate::timefmt::Timezone;
+use crate::users::{self, Users};
+use base64::{Engine as _, engine::general_purpose};
+use hyper::header::{HeaderName, HeaderValue};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Settings the request handlers run with. Built from the command line by
+/// `Config::from_args`, or directly (starting from `Config::default()`) in
+/// tests. Process-level options such as daemonizing or the listen address
+/// stay on `Args`.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub root: String,
+    /// Where the files below `root` are read from and written to.
+    pub storage: Arc<dyn Storage>,
+    /// URL prefix every route lives under, without a trailing `/`; empty
+    /// when serving from `/`.
+    pub base_path: String,
+    /// `--mount` directories by URL prefix, longest prefix first. Each has
+    /// its own settings, with `base_path` ending in the prefix.
+    pub mounts: Vec<(String, Arc<Config>)>,
+    /// `--vhost` settings by lowercase host name; requests for any other
+    /// host are served from `root`.
+    pub vhosts: Vec<(String, Arc<Config>)>,
+    /// `--proxy` rules, longest prefix first.
+    pub proxies: Vec<ProxyRule>,
+    /// URL path of the `--cgi` directory, whose files are run as scripts.
+    pub cgi: Option<String>,
+    /// `--plugin` modules, asked in command-line order.
+    pub plugins: Vec<Arc<Plugin>>,
+    /// Expected `Authorization: Basic` token (base64 of `user:password`).
+    pub auth: Option<String>,
+    /// `--auth-file` accounts and a hashed `--auth`, accepted besides
+    /// `auth`.
+    pub users: Option<Arc<Users>>,
+    /// `--token` secrets accepted as `Authorization: Bearer` tokens.
+    pub tokens: Vec<String>,
+    /// Challenge for Digest auth instead of Basic; Basic credentials are
+    /// refused then.
+    pub digest_auth: Option<Arc<DigestAuth>>,
+    /// Log browsers in with a form and a session cookie.
+    pub sessions: Option<Arc<Sessions>>,
+    /// `--protect` patterns; when there are any, only matching paths need
+    /// authentication.
+    pub protect: Vec<String>,
+    /// Let anyone read; only requests that change files need
+    /// authentication.
+    pub protect_writes: bool,
+    /// `--write-auth-file` accounts, the only ones accepted for requests
+    /// that change files.
+    pub write_users: Option<Arc<Users>>,
+    /// Failed logins by client, for `--auth-max-failures`.
+    pub lockout: Arc<Lockout>,
+    /// `--rate-limit` buckets by client.
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// `--max-bandwidth` schedule, shared by all responses.
+    pub bandwidth: Option<Arc<Bandwidth>>,
+    pub auth_realm: String,
+    pub auth_page: Option<PathBuf>,
+    pub welcome_page: Option<PathBuf>,
+    /// Serve the root `index.html` for paths that do not exist.
+    pub spa: bool,
+    /// Files served in place of a directory listing, first match wins.
+    pub index_files: Vec<String>,
+    /// Generate listings for directories without an index file.
+    pub listing: bool,
+    /// Serve and list dotfiles; otherwise only those matching
+    /// `dotfile_allow` are visible.
+    pub hidden: bool,
+    pub dotfile_allow: Vec<String>,
+    /// `--exclude` patterns followed by those of the root's `.samserveignore`.
+    pub exclude: Vec<String>,
+    pub follow_symlinks: FollowSymlinks,
+    /// Template variables, in command-line order.
+    pub vars: Vec<(String, String)>,
+    pub upload: bool,
+    /// Answer WebDAV methods; the ones that modify files also need `upload`.
+    pub webdav: bool,
+    pub upload_field: String,
+    pub upload_strip_prefix: Option<String>,
+    pub dedupe: bool,
+    pub max_fields: usize,
+    pub max_field_name_size: usize,
+    pub max_path_length: usize,
+    pub digest: bool,
+    /// Compress file responses on the fly; files smaller than
+    /// `compress_min_size` bytes are sent as they are.
+    pub compress: bool,
+    pub compress_min_size: u64,
+    /// Extensions, without the dot, added to
+    /// `encoding::NO_COMPRESS_EXTENSIONS`.
+    pub no_compress_ext: Vec<String>,
+    /// Serve `name.br`/`.zst`/`.gz` siblings in place of `name`.
+    pub precompressed: bool,
+    /// `Cache-Control` rules for files, `--no-cache` ones first.
+    pub cache_rules: Vec<CacheRule>,
+    pub mime_sniff: bool,
+    pub lang_negotiation: bool,
+    pub allow_method_override: bool,
+    pub download_stats: bool,
+    pub thumbnails: bool,
+    pub dir_sizes: bool,
+    pub max_depth: Option<usize>,
+    pub date_format: String,
+    pub timezone: Timezone,
+    /// Security headers added to every response; `csp` and
+    /// `referrer_policy` replace the directory listing defaults.
+    pub hsts: Option<HeaderValue>,
+    pub csp: Option<HeaderValue>,
+    pub referrer_policy: Option<HeaderValue>,
+    pub nosniff: bool,
+    /// Origins allowed to make cross-origin requests; `*` allows any.
+    pub cors: Vec<String>,
+    /// `--header` values, added to every response after the handler's own.
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    /// Pages sent as the body of error responses, relative to the root.
+    /// Missing ones fall back to the built-in bodies.
+    pub error_pages: Vec<(u16, PathBuf)>,
+    /// Path of the metrics endpoint on the main listener; `None` when
+    /// metrics are disabled or served on their own port.
+    pub metrics_path: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            root: ".".to_string(),
+            storage: Arc::new(LocalStorage),
+            base_path: String::new(),
+            mounts: Vec::new(),
+            vhosts: Vec::new(),
+            proxies: Vec::new(),
+            cgi: None,
+            plugins: Vec::new(),
+            auth: None,
+            users: None,
+            tokens: Vec::new(),
+            digest_auth: None,
+            sessions: None,
+            protect: Vec::new(),
+            protect_writes: false,
+            write_users: None,
+            lockout: Arc::new(Lockout::default()),
+            rate_limiter: None,
+            bandwidth: None,
+            auth_realm: "Restricted".to_string(),
+            auth_page: None,
+            welcome_page: None,
+            spa: false,
+            index_files: vec!["index.html".to_string()],
+            listing: true,
+            hidden: false,
+            dotfile_allow: vec![".well-known".to_string()],
+            exclude: Vec::new(),
+            follow_symlinks: FollowSymlinks::Always,
+            vars: Vec::new(),
+            upload: false,
+            webdav: false,
+            upload_field: "file".to_string(),
+            upload_strip_prefix: None,
+            dedupe: false,
+            max_fields: 64,
+            max_field_name_size: 256,
+            max_path_length: 4096,
+            digest: false,
+            compress: false,
+            compress_min_size: 1024,
+            no_compress_ext: Vec::new(),
+            precompressed: false,
+            cache_rules: Vec::new(),
+            mime_sniff: false,
+            lang_negotiation: false,
+            allow_method_override: false,
+            download_stats: false,
+            thumbnails: false,
+            dir_sizes: false,
+            max_depth: None,
+            date_format: "%Y-%m-%d %H:%M".to_string(),
+            timezone: Timezone::Local,
+            hsts: None,
+            csp: None,
+            referrer_policy: None,
+            nosniff: true,
+            cors: Vec::new(),
+            headers: Vec::new(),
+            error_pages: default_error_pages(),
+            metrics_path: None,
+        }
+    }
+}
+
+impl Config {
+    /// Resolves and validates the handler settings from the command line.
+    pub fn from_args(args: &Args) -> Result<Self, String> {
+        let mut users = match &args.auth_file {
+            Some(path) => Some(Users::load(path)?),
+            None => None,
+        };
+        let auth = match &args.auth {
+            Some(credentials) if !credentials.contains(':') => {
+                return Err("--auth must be given as user:password".to_string());
+            }
+            // A hashed password is checked like an `--auth-file` account.
+            Some(credentials)
+                if credentials
+                    .split_once(':')
+                    .is_some_and(|(_, p)| users::is_hash(p)) =>
+            {
+                users
+                    .get_or_insert_with(Users::default)
+                    .add(credentials)
+                    .map_err(|e| format!("--auth: {}", e))?;
+                None
+            }
+            Some(credentials) => Some(general_purpose::STANDARD.encode(credentials.as_bytes())),
+            None => None,
+        };
+        let digest_auth = if args.digest_auth {
+            if auth.is_none() && users.is_none() {
+                return Err("--digest-auth needs --auth or --auth-file".to_string());
+            }
+            // Digest auth is computed from the password itself.
+            if users.as_ref().is_some_and(|users| !users.all_plain()) {
+                return Err(
+                    "--digest-auth needs plain-text passwords; hashed ones cannot be checked"
+                        .to_string(),
+                );
+            }
+            Some(Arc::new(DigestAuth::new()?))
+        } else {
+            None
+        };
+        if !args.protect.is_empty() && auth.is_none() && users.is_none() && args.tokens.is_empty() {
+            return Err("--protect needs --auth, --auth-file or --token".to_string());
+        }
+        if args.protect_writes
+            && auth.is_none()
+            && users.is_none()
+            && args.tokens.is_empty()
+            && args.write_auth_file.is_none()
+        {
+            return Err(
+                "--protect-writes needs --auth, --auth-file, --token or --write-auth-file"
+                    .to_string(),
+            );
+        }
+        let sessions = if args.login_page {
+            if auth.is_none() && users.is_none() {
+                return Err("--login-page needs --auth or --auth-file".to_string());
+            }
+            let lifetime = Duration::from_secs(args.session_lifetime);
+            Some(Arc::new(Sessions::new(lifetime, args.serves_https())?))
+        } else {
+            None
+        };
+        if args.metrics && !args.metrics_path.starts_with('/') {
+            return Err(format!(
+                "--metrics-path must start with '/', got {:?}",
+                args.metrics_path
+            ));
+        }
+        if args.tokens.iter().any(|token| token.trim().is_empty()) {
+            return Err("--token must not be empty".to_string());
+        }
+        if args.upload_field.is_empty() {
+            return Err("--upload-field must not be empty".to_string());
+        }
+        let hsts = args.hsts.map(|max_age| {
+            let value = if args.hsts_subdomains {
+                format!("max-age={}; includeSubDomains", max_age)
+            } else {
+                format!("max-age={}", max_age)
+            };
+            HeaderValue::try_from(value).unwrap()
+        });
+        let header_value = |flag: &str, value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|v| {
+                    HeaderValue::from_str(v)
+                        .map_err(|_| format!("{} is not a valid header value", flag))
+                })
+                .transpose()
+        };
+        let archive = archive::is_archive(Path::new(&args.root));
+        if archive && (args.upload || args.webdav || args.cgi.is_some() || args.sandbox) {
+            return Err(
+                "an archive --root is read-only and cannot be used with --upload, --webdav, --cgi or --sandbox"
+                    .to_string(),
+            );
+        }
+        if args.embedded && !embed::available() {
+            return Err(
+                "--embedded: this binary has no embedded files (build it with SAMSERVE_EMBED_DIR set)"
+                    .to_string(),
+            );
+        }
+        // Embedded and in-memory files have no ignore file next to them.
+        let exclude = if args.embedded || args.tmpfs {
+            args.exclude.clone()
+        } else {
+            exclude_patterns(args, &args.root)?
+        };
+        let mut error_pages = args.error_pages.clone();
+        for (status, page) in default_error_pages() {
+            if !error_pages.iter().any(|(given, _)| *given == status) {
+                error_pages.push((status, page));
+            }
+        }
+        let mut proxies = args.proxies.clone();
+        proxies.sort_by_key(|rule| std::cmp::Reverse(rule.prefix.len()));
+        let mut config = Config {
+            root: args.root.clone(),
+            storage: match &args.backend {
+                Some(bucket) => S3Storage::from_env(bucket, &args.root)?,
+                None if args.tmpfs => Arc::new(MemoryStorage::new(Path::new(&args.root))),
+                None if args.embedded => EmbeddedStorage::new(Path::new(&args.root)),
+                None if archive => ArchiveStorage::open(Path::new(&args.root))?,
+                None => Arc::new(LocalStorage),
+            },
+            base_path: args.base_path.clone().unwrap_or_default(),
+            mounts: Vec::new(),
+            vhosts: Vec::new(),
+            proxies,
+            cgi: args.cgi.clone(),
+            plugins: args
+                .plugins
+                .iter()
+                .map(|path| Plugin::load(path))
+                .collect::<Result<_, _>>()?,
+            auth,
+            users: users.map(Arc::new),
+            tokens: args.tokens.clone(),
+            digest_auth,
+            sessions,
+            protect: args.protect.clone(),
+            protect_writes: args.protect_writes,
+            lockout: Arc::new(Lockout::new(
+                args.auth_max_failures,
+                Duration::from_secs(args.auth_ban_time),
+            )),
+            bandwidth: args
+                .max_bandwidth
+                .map(|rate| Arc::new(Bandwidth::new(rate))),
+            rate_limiter: args
+                .rate_limit
+                .map(|limit| Arc::new(RateLimiter::new(limit))),
+            write_users: match &args.write_auth_file {
+                Some(path) => Some(Arc::new(Users::load(path)?)),
+                None => None,
+            },
+            auth_realm: args.auth_realm.clone(),
+            auth_page: args.auth_page.clone(),
+            welcome_page: args.welcome_page.clone(),
+            spa: args.spa,
+            index_files: if args.no_index {
+                Vec::new()
+            } else {
+                args.index.clone()
+            },
+            listing: !args.no_listing,
+            hidden: args.hidden,
+            dotfile_allow: args.dotfile_allow.clone(),
+            exclude,
+            follow_symlinks: args.follow_symlinks,
+            vars: args.vars.clone(),
+            upload: args.upload || args.tmpfs,
+            webdav: args.webdav,
+            upload_field: args.upload_field.clone(),
+            upload_strip_prefix: args.upload_strip_prefix.clone(),
+            dedupe: args.dedupe,
+            max_fields: args.max_fields,
+            max_field_name_size: args.max_field_name_size,
+            max_path_length: args.max_path_length,
+            digest: args.digest,
+            compress: args.compress,
+            compress_min_size: args.compress_min_size,
+            no_compress_ext: args
+                .no_compress_ext
+                .iter()
+                .map(|ext| ext.trim().trim_start_matches('.').to_string())
+                .filter(|ext| !ext.is_empty())
+                .collect(),
+            precompressed: args.precompressed,
+            cache_rules: args.no_cache.iter().chain(&args.cache).cloned().collect(),
+            mime_sniff: args.mime_sniff,
+            lang_negotiation: args.lang_negotiation,
+            allow_method_override: args.allow_method_override,
+            download_stats: args.download_stats,
+            thumbnails: args.thumbnails,
+            dir_sizes: args.dir_sizes,
+            max_depth: args.max_depth,
+            date_format: args.date_format.clone(),
+            timezone: args.timezone,
+            hsts,
+            csp: header_value("--csp", &args.csp)?,
+            referrer_policy: header_value("--referrer-policy", &args.referrer_policy)?,
+            nosniff: !args.no_nosniff,
+            cors: args.cors.clone(),
+            headers: args.headers.clone(),
+            error_pages,
+            metrics_path: (args.metrics && args.metrics_port.is_none())
+                .then(|| args.metrics_path.clone()),
+        };
+        let mut mounts = Vec::new();
+        for mount in &args.mounts {
+            if !Path::new(&mount.dir).is_dir() {
+                return Err(format!("--mount directory {:?} does not exist", mount.dir));
+            }
+            let mounted = Config {
+                root: mount.dir.clone(),
+                storage: Arc::new(LocalStorage),
+                base_path: format!("{}{}", config.base_path, mount.prefix),
+                upload: mount.upload.unwrap_or(args.upload),
+                exclude: exclude_patterns(args, &mount.dir)?,
+                // `--cgi` names a directory of the main root only.
+                cgi: None,
+                ..config.clone()
+            };
+            mounts.push((mount.prefix.clone(), Arc::new(mounted)));
+        }
+        // Nested prefixes go to the innermost mount.
+        mounts.sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+        config.mounts = mounts;
+        let mut vhosts = Vec::new();
+        for (host, dir) in &args.vhosts {
+            if !Path::new(dir).is_dir() {
+                return Err(format!("--vhost directory {:?} does not exist", dir));
+            }
+            let hosted = Config {
+                root: dir.clone(),
+                storage: Arc::new(LocalStorage),
+                exclude: exclude_patterns(args, dir)?,
+                cgi: None,
+                ..config.clone()
+            };
+            vhosts.push((host.clone(), Arc::new(hosted)));
+        }
+        config.vhosts = vhosts;
+        Ok(config)
+    }
+}
+
+/// `--exclude` patterns followed by those of `root`'s ignore file.
+fn exclude_patterns(args: &Args, root: &str) -> Result<Vec<String>, String> {
+    let mut exclude = args.exclude.clone();
+    let ignore_file = Path::new(root).join(IGNORE_FILE);
+    match std::fs::read_to_string(&ignore_file) {
+        Ok(text) => exclude.extend(pathfilter::parse_ignore_file(&text)),
+        // A root that is an archive file has no ignore file.
+        Err(e)
+            if matches!(
+                e.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory
+            ) => {}
+        Err(e) => return Err(format!("failed to read {:?}: {}", ignore_file, e)),
+    }
+    Ok(exclude)
+}
+
+/// Parses a `--cgi DIR` argument, a directory below the root.
+pub fn parse_cgi_dir(s: &str) -> Result<String, String> {
+    match parse_base_path(s)? {
+        dir if dir.is_empty() => Err("--cgi must name a directory below the root".to_string()),
+        dir => Ok(dir),
+    }
+}
+
+/// Parses a `--vhost HOST=DIR` argument.
+pub fn parse_vhost(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((host, dir))
+            if !dir.is_empty()
+                && crate::redirect::host_without_port(host.trim()) == Some(host.trim()) =>
+        {
+            let host = host.trim().trim_end_matches('.').to_ascii_lowercase();
+            Ok((host, dir.to_string()))
+        }
+        _ => Err(format!("expected HOST=DIR, got {:?}", s)),
+    }
+}
+
+/// A `--mount PREFIX=DIR[:ro|:upload]` argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub prefix: String,
+    pub dir: String,
+    /// `:upload` or `:ro`; `None` follows `--upload`.
+    pub upload: Option<bool>,
+}
+
+/// Parses a `--mount PREFIX=DIR[:ro|:upload]` argument.
+pub fn parse_mount(s: &str) -> Result<Mount, String> {
+    let (prefix, dir) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected PREFIX=DIR, got {:?}", s))?;
+    let prefix = parse_base_path(prefix)?;
+    if prefix.is_empty() {
+        return Err("--mount prefix must not be /; use --root for that".to_string());
+    }
+    let (dir, upload) = match dir.rsplit_once(':') {
+        Some((dir, "ro")) => (dir, Some(false)),
+        Some((dir, "upload")) => (dir, Some(true)),
+        _ => (dir, None),
+    };
+    if dir.is_empty() {
+        return Err(format!("expected PREFIX=DIR, got {:?}", s));
+    }
+    Ok(Mount {
+        prefix,
+        dir: dir.to_string(),
+        upload,
+    })
+}
+
+/// Ignore file read from the root, with one `--exclude` pattern per line.
+pub const IGNORE_FILE: &str = ".samserveignore";
+
+/// `403.html` and `404.html` in the root, used when they exist.
+fn default_error_pages() -> Vec<(u16, PathBuf)> {
+    vec![
+        (403, PathBuf::from("403.html")),
+        (404, PathBuf::from("404.html")),
+    ]
+}
+
+/// Parses a `--base-path` argument into `/prefix` form; `/` means no prefix.
+pub fn parse_base_path(s: &str) -> Result<String, String> {
+    let trimmed = s.trim().trim_matches('/');
+    if trimmed.is_empty() {
+        return Ok(String::new());
+    }
+    let base = format!("/{}", trimmed);
+    if trimmed
+        .split('/')
+        .any(|part| matches!(part, "" | "." | ".."))
+        || base.contains(['?', '#'])
+        || base.parse::<hyper::Uri>().is_err()
+    {
+        return Err(format!("expected a URL path like /files, got {:?}", s));
+    }
+    Ok(base)
+}
+
+/// Parses a `--error-page STATUS=FILE` argument.
+pub fn parse_error_page(s: &str) -> Result<(u16, PathBuf), String> {
+    let (status, file) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected STATUS=FILE, got {:?}", s))?;
+    match status.trim().parse::<u16>() {
+        Ok(status) if (400..600).contains(&status) && !file.is_empty() => {
+            Ok((status, PathBuf::from(file)))
+        }
+        _ => Err(format!(
+            "expected an error status (400-599) and a file, got {:?}",
+            s
+        )),
+    }
+}
+
+/// Parses a `--header "Name: value"` argument.
+pub fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"Name: value\", got {:?}", s))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("invalid header name {:?}", name.trim()))?;
+    let value = HeaderValue::from_str(value.trim())
+        .map_err(|_| format!("invalid value for header {}", name))?;
+    Ok((name, value))
+}
+
+/// The configuration requests are served with. Each request takes the
+/// `Arc` current when it starts, so `replace` (on `SIGHUP`) never changes
+/// settings under a request in flight.
+#[derive(Clone)]
+pub struct SharedConfig(Arc<RwLock<Arc<Config>>>);
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        SharedConfig(Arc::new(RwLock::new(Arc::new(config))))
+    }
+
+    pub fn current(&self) -> Arc<Config> {
+        Arc::clone(&self.0.read().unwrap())
+    }
+
+    pub fn replace(&self, config: Config) {
+        *self.0.write().unwrap() = Arc::new(config);
+    }
+}
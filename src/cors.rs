@@ -0,0 +1,110 @@
+//! `--cors`: lets pages served from other origins fetch files and listings.
+
+use hyper::header::{self, HeaderMap, HeaderValue};
+use hyper::{Body, Method, Request, Response, StatusCode};
+
+/// Response headers scripts may read besides the CORS-safelisted ones;
+/// range and compression handling need these.
+const EXPOSE_HEADERS: &str =
+    "Content-Length, Content-Range, Accept-Ranges, Content-Encoding, Last-Modified";
+
+/// How long browsers may cache a preflight answer, in seconds.
+const PREFLIGHT_MAX_AGE: &str = "86400";
+
+/// Parses a `--cors` origin: `*` or `scheme://host[:port]`.
+pub fn parse_origin(s: &str) -> Result<String, String> {
+    let origin = s.trim().trim_end_matches('/');
+    let valid = origin == "*"
+        || origin.split_once("://").is_some_and(|(scheme, host)| {
+            !scheme.is_empty() && !host.is_empty() && !host.contains('/')
+        });
+    if !valid || HeaderValue::from_str(origin).is_err() {
+        return Err(format!(
+            "expected * or an origin like https://example.com, got {:?}",
+            s
+        ));
+    }
+    Ok(origin.to_string())
+}
+
+/// The `Access-Control-Allow-Origin` value for a request from `origin`, or
+/// `None` when that origin is not allowed.
+fn allowed_origin(origins: &[String], origin: &HeaderValue) -> Option<HeaderValue> {
+    if origins.iter().any(|allowed| allowed == "*") {
+        return Some(HeaderValue::from_static("*"));
+    }
+    let origin_str = origin.to_str().ok()?;
+    origins
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(origin_str))
+        .then(|| origin.clone())
+}
+
+/// Answers a preflight `OPTIONS` request, which browsers send without
+/// credentials, so it is handled before authentication. `allow` lists the
+/// methods the server accepts.
+pub fn preflight(req: &Request<Body>, origins: &[String], allow: &str) -> Option<Response<Body>> {
+    if origins.is_empty()
+        || req.method() != Method::OPTIONS
+        || !req
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+    {
+        return None;
+    }
+    let origin = req.headers().get(header::ORIGIN)?;
+    // `Vary` and the origin itself are added by `apply_headers`, like on
+    // any other response.
+    let mut builder = Response::builder().status(StatusCode::NO_CONTENT);
+    if allowed_origin(origins, origin).is_some() {
+        builder = builder
+            .header(header::ACCESS_CONTROL_ALLOW_METHODS, allow)
+            .header(header::ACCESS_CONTROL_MAX_AGE, PREFLIGHT_MAX_AGE);
+        if let Some(requested) = req.headers().get(header::ACCESS_CONTROL_REQUEST_HEADERS) {
+            builder = builder.header(header::ACCESS_CONTROL_ALLOW_HEADERS, requested);
+        }
+    }
+    Some(builder.body(Body::empty()).unwrap())
+}
+
+/// Adds the CORS headers for a request that carried `origin`.
+pub fn apply_headers(headers: &mut HeaderMap, origins: &[String], origin: Option<&HeaderValue>) {
+    if origins.is_empty() {
+        return;
+    }
+    // Caches must not hand one origin's answer to another.
+    if !origins.iter().any(|allowed| allowed == "*") {
+        headers.append(header::VARY, HeaderValue::from_static("Origin"));
+    }
+    if let Some(allowed) = origin.and_then(|origin| allowed_origin(origins, origin)) {
+        // Origins named explicitly are trusted with the user's credentials,
+        // which Basic Auth needs; `*` never is.
+        if allowed != "*" {
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, allowed);
+        headers.insert(
+            header::ACCESS_CONTROL_EXPOSE_HEADERS,
+            HeaderValue::from_static(EXPOSE_HEADERS),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_origins() {
+        assert_eq!(parse_origin("*"), Ok("*".to_string()));
+        assert_eq!(
+            parse_origin("http://localhost:3000/"),
+            Ok("http://localhost:3000".to_string())
+        );
+        assert!(parse_origin("localhost:3000").is_err());
+        assert!(parse_origin("https://example.com/app").is_err());
+    }
+}
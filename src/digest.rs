@@ -0,0 +1,75 @@
+use crate::storage::{Metadata, Storage};
+use base64::{Engine as _, engine::general_purpose};
+use futures_util::TryStreamExt;
+use log::{error, info};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+/// Files up to this size are hashed before the response is sent; larger
+/// files are hashed in the background and get the header once cached.
+const INLINE_LIMIT: u64 = 16 * 1024 * 1024;
+
+enum Entry {
+    Ready(SystemTime, String),
+    Pending(SystemTime),
+}
+
+static CACHE: LazyLock<Mutex<HashMap<PathBuf, Entry>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the `Digest` header value (`sha-256=<base64>`) for `path`, if known.
+pub async fn file_digest(
+    storage: &Arc<dyn Storage>,
+    path: &Path,
+    metadata: &Metadata,
+) -> Option<String> {
+    let modified = metadata.modified?;
+    {
+        let mut cache = CACHE.lock().unwrap();
+        match cache.get(path) {
+            Some(Entry::Ready(at, digest)) if *at == modified => return Some(digest.clone()),
+            Some(Entry::Pending(at)) if *at == modified => return None,
+            _ => {}
+        }
+        if metadata.len > INLINE_LIMIT {
+            cache.insert(path.to_path_buf(), Entry::Pending(modified));
+            let (storage, path) = (Arc::clone(storage), path.to_path_buf());
+            tokio::spawn(async move {
+                compute(storage.as_ref(), &path, modified).await;
+            });
+            return None;
+        }
+    }
+    compute(storage.as_ref(), path, modified).await
+}
+
+async fn compute(storage: &dyn Storage, path: &Path, modified: SystemTime) -> Option<String> {
+    match sha256_file(storage, path).await {
+        Ok(hash) => {
+            let digest = format!("sha-256={}", general_purpose::STANDARD.encode(hash));
+            info!("Digest computed | path: {:?} | digest: {}", path, digest);
+            CACHE
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), Entry::Ready(modified, digest.clone()));
+            Some(digest)
+        }
+        Err(err) => {
+            error!("Digest failed | path: {:?} | error: {}", path, err);
+            CACHE.lock().unwrap().remove(path);
+            None
+        }
+    }
+}
+
+pub async fn sha256_file(storage: &dyn Storage, path: &Path) -> std::io::Result<[u8; 32]> {
+    let mut data = storage.open(path).await?;
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = data.try_next().await? {
+        hasher.update(&chunk);
+    }
+    Ok(hasher.finalize().into())
+}
@@ -0,0 +1,245 @@
+//! `--digest-auth`: RFC 7616 Digest authentication, so passwords never
+//! cross the wire, even over plain HTTP. Only `algorithm=SHA-256` with
+//! `qop=auth` is offered.
+//!
+//! Nonces are `time.mac`, signed with a secret drawn at startup, so they
+//! need no storage until used and expire after `NONCE_LIFETIME` (the client
+//! is then told `stale=true` and retries without asking the user again).
+//! Each use must raise the nonce count, which stops replayed requests.
+
+use crate::users::constant_time_eq;
+use hmac::{Hmac, Mac};
+use sha2::{Digest as _, Sha256};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a nonce is accepted after it was issued.
+const NONCE_LIFETIME: Duration = Duration::from_secs(300);
+
+/// Why a Digest `Authorization` header was not accepted.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Rejection {
+    /// Right credentials on an expired nonce; the client should retry with
+    /// a fresh one.
+    Stale,
+    Invalid(&'static str),
+}
+
+/// Issues and checks Digest nonces.
+#[derive(Debug)]
+pub struct DigestAuth {
+    secret: [u8; 32],
+    opaque: String,
+    /// Highest nonce count seen per nonce, with the time the nonce was
+    /// issued, so expired ones can be dropped.
+    counts: Mutex<HashMap<String, (u32, u64)>>,
+}
+
+impl DigestAuth {
+    pub fn new() -> Result<DigestAuth, String> {
+        let mut random = [0; 48];
+        getrandom::fill(&mut random).map_err(|e| format!("no random source: {}", e))?;
+        let mut secret = [0; 32];
+        secret.copy_from_slice(&random[..32]);
+        Ok(DigestAuth {
+            secret,
+            opaque: hex(&random[32..]),
+            counts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The `WWW-Authenticate` value asking for credentials in `realm`.
+    pub fn challenge(&self, realm: &str, stale: bool) -> String {
+        format!(
+            r#"Digest realm="{}", qop="auth", algorithm=SHA-256, nonce="{}", opaque="{}"{}"#,
+            realm,
+            self.nonce(now()),
+            self.opaque,
+            if stale { ", stale=true" } else { "" }
+        )
+    }
+
+    fn nonce(&self, issued: u64) -> String {
+        format!("{}.{}", issued, hex(&self.mac(issued)))
+    }
+
+    fn mac(&self, issued: u64) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("any key size");
+        mac.update(issued.to_string().as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    /// Checks the parameters of a `Digest` `Authorization` header for a
+    /// request with `method` and `uri` in `realm`, returning the user.
+    /// `password` looks up a user's password.
+    pub fn verify(
+        &self,
+        authorization: &str,
+        method: &str,
+        uri: &str,
+        realm: &str,
+        password: impl Fn(&str) -> Option<String>,
+    ) -> Result<String, Rejection> {
+        let params = authorization
+            .strip_prefix("Digest ")
+            .map(parse_params)
+            .ok_or(Rejection::Invalid("not a Digest header"))?;
+        let param = |name: &'static str| {
+            params
+                .get(name)
+                .map(String::as_str)
+                .ok_or(Rejection::Invalid(name))
+        };
+        let (user, nonce, response) = (param("username")?, param("nonce")?, param("response")?);
+        if param("algorithm")? != "SHA-256" {
+            return Err(Rejection::Invalid("algorithm"));
+        }
+        if param("qop")? != "auth" {
+            return Err(Rejection::Invalid("qop"));
+        }
+        if param("realm")? != realm {
+            return Err(Rejection::Invalid("realm"));
+        }
+        if param("opaque")? != self.opaque {
+            return Err(Rejection::Invalid("opaque"));
+        }
+        if param("uri")? != uri {
+            return Err(Rejection::Invalid("uri"));
+        }
+        let (cnonce, nc) = (param("cnonce")?, param("nc")?);
+        let count = u32::from_str_radix(nc, 16).map_err(|_| Rejection::Invalid("nc"))?;
+        let issued = nonce
+            .split_once('.')
+            .and_then(|(issued, _)| issued.parse::<u64>().ok())
+            .filter(|issued| self.nonce(*issued) == nonce)
+            .ok_or(Rejection::Invalid("nonce"))?;
+
+        let password = password(user).ok_or(Rejection::Invalid("username"))?;
+        let ha1 = sha256_hex(&format!("{}:{}:{}", user, realm, password));
+        let ha2 = sha256_hex(&format!("{}:{}", method, uri));
+        let expected = sha256_hex(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2));
+        if !constant_time_eq(
+            expected.as_bytes(),
+            response.to_ascii_lowercase().as_bytes(),
+        ) {
+            return Err(Rejection::Invalid("response"));
+        }
+        let now = now();
+        if now.saturating_sub(issued) > NONCE_LIFETIME.as_secs() {
+            return Err(Rejection::Stale);
+        }
+        let mut counts = self.counts.lock().unwrap();
+        counts.retain(|_, (_, issued)| now.saturating_sub(*issued) <= NONCE_LIFETIME.as_secs());
+        let last = counts.entry(nonce.to_string()).or_insert((0, issued));
+        if count <= last.0 {
+            return Err(Rejection::Invalid("nc (replayed request)"));
+        }
+        last.0 = count;
+        Ok(user.to_string())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// Parses `name=value, name="quoted value", ...`.
+fn parse_params(text: &str) -> HashMap<String, String> {
+    let mut params = HashMap::new();
+    let mut rest = text.trim_start();
+    while let Some((name, after)) = rest.split_once('=') {
+        let name = name.trim().to_ascii_lowercase();
+        let after = after.trim_start();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let mut end = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            end = i + 1;
+                            break;
+                        }
+                        c => value.push(c),
+                    }
+                }
+                (value, &quoted[end..])
+            }
+            None => {
+                let end = after.find(',').unwrap_or(after.len());
+                (after[..end].trim().to_string(), &after[end..])
+            }
+        };
+        params.insert(name, value);
+        rest = after.trim_start().trim_start_matches(',').trim_start();
+    }
+    params
+}
+
+fn sha256_hex(text: &str) -> String {
+    hex(&Sha256::digest(text.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The `Authorization` header a client answering `challenge` sends.
+    fn answer(challenge: &str, password: &str, nc: &str) -> String {
+        let params = parse_params(challenge.strip_prefix("Digest ").unwrap());
+        let (realm, nonce) = (&params["realm"], &params["nonce"]);
+        let ha1 = sha256_hex(&format!("Mufasa:{}:{}", realm, password));
+        let ha2 = sha256_hex("GET:/dir/index.html");
+        let response = sha256_hex(&format!(
+            "{}:{}:{}:f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ:auth:{}",
+            ha1, nonce, nc, ha2
+        ));
+        format!(
+            r#"Digest username="Mufasa", realm="{}", uri="/dir/index.html", algorithm=SHA-256, nonce="{}", nc={}, cnonce="f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ", qop=auth, response="{}", opaque="{}""#,
+            realm, nonce, nc, response, params["opaque"]
+        )
+    }
+
+    #[test]
+    fn verifies_responses_once() {
+        let auth = DigestAuth::new().unwrap();
+        let challenge = auth.challenge("http-auth@example.org", false);
+        let password = |user: &str| (user == "Mufasa").then(|| "Circle of Life".to_string());
+        let verify = |header: &str| {
+            auth.verify(
+                header,
+                "GET",
+                "/dir/index.html",
+                "http-auth@example.org",
+                password,
+            )
+        };
+
+        let header = answer(&challenge, "Circle of Life", "00000001");
+        assert_eq!(verify(&header), Ok("Mufasa".to_string()));
+        assert!(verify(&header).is_err(), "replayed nonce count");
+        let header = answer(&challenge, "Circle of Life", "00000002");
+        assert_eq!(verify(&header), Ok("Mufasa".to_string()));
+        let header = answer(&challenge, "circle of life", "00000003");
+        assert_eq!(verify(&header), Err(Rejection::Invalid("response")));
+
+        let expired = format!(
+            r#"Digest realm="http-auth@example.org", nonce="{}", opaque="{}""#,
+            auth.nonce(now() - NONCE_LIFETIME.as_secs() - 1),
+            auth.opaque
+        );
+        let header = answer(&expired, "Circle of Life", "00000001");
+        assert_eq!(verify(&header), Err(Rejection::Stale));
+        let forged = header.replace("nonce=\"", "nonce=\"1");
+        assert!(verify(&forged).is_err());
+    }
+}
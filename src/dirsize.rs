@@ -0,0 +1,53 @@
+use crate::storage::Storage;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+use tokio::time::{Instant, timeout_at};
+
+/// Upper bound on cached directory sizes before the cache is reset.
+const CACHE_CAPACITY: usize = 4096;
+
+enum Entry {
+    Ready(SystemTime, u64),
+    Pending(SystemTime),
+}
+
+static CACHE: LazyLock<Mutex<HashMap<PathBuf, Entry>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the total size of the files below `path` in `storage`, walking at
+/// most `max_depth` levels. Results are cached against the directory's
+/// modification time. A walk that does not finish by `deadline` keeps running
+/// in the background and `None` is returned until it is cached.
+pub async fn dir_size(
+    storage: &Arc<dyn Storage>,
+    path: &Path,
+    modified: SystemTime,
+    max_depth: Option<usize>,
+    deadline: Instant,
+) -> Option<u64> {
+    {
+        let mut cache = CACHE.lock().unwrap();
+        match cache.get(path) {
+            Some(Entry::Ready(at, size)) if *at == modified => return Some(*size),
+            Some(Entry::Pending(at)) if *at == modified => return None,
+            _ => {}
+        }
+        if cache.len() >= CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(path.to_path_buf(), Entry::Pending(modified));
+    }
+    let dir = path.to_path_buf();
+    let storage = Arc::clone(storage);
+    let walk = tokio::spawn(async move {
+        let size = storage.dir_size(&dir, max_depth).await;
+        CACHE
+            .lock()
+            .unwrap()
+            .insert(dir, Entry::Ready(modified, size));
+        size
+    });
+    timeout_at(deadline, walk).await.ok()?.ok()
+}
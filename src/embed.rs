@@ -0,0 +1,195 @@
+//! `--embedded`: serves a directory baked into the binary at build time, so
+//! a site ships as one executable. Build with
+//! `SAMSERVE_EMBED_DIR=path/to/site cargo build --release`; see `build.rs`.
+
+use crate::archive::entry_name;
+use crate::storage::{ByteStream, DirEntry, Metadata, Staged, Storage};
+use futures_util::StreamExt;
+use futures_util::future::BoxFuture;
+use hyper::body::Bytes;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+mod files {
+    include!(concat!(env!("OUT_DIR"), "/embedded.rs"));
+}
+
+/// Whether this binary was built with files to serve.
+pub fn available() -> bool {
+    !files::FILES.is_empty()
+}
+
+/// The embedded files, served as if they were below `root`.
+#[derive(Debug)]
+pub struct EmbeddedStorage {
+    root: PathBuf,
+    modified: SystemTime,
+    /// Files and directories by their `/`-separated path; directories have
+    /// no contents and the top level is `""`.
+    entries: BTreeMap<String, Option<&'static [u8]>>,
+}
+
+impl EmbeddedStorage {
+    /// The files baked into this binary.
+    pub fn new(root: &Path) -> Arc<EmbeddedStorage> {
+        Arc::new(Self::from_files(
+            root,
+            files::FILES,
+            UNIX_EPOCH + Duration::from_secs(files::BUILT),
+        ))
+    }
+
+    fn from_files(
+        root: &Path,
+        files: &'static [(&'static str, &'static [u8])],
+        modified: SystemTime,
+    ) -> EmbeddedStorage {
+        let mut entries = BTreeMap::new();
+        entries.insert(String::new(), None);
+        for (name, data) in files {
+            let mut parent = *name;
+            while let Some((dir, _)) = parent.rsplit_once('/') {
+                entries.insert(dir.to_string(), None);
+                parent = dir;
+            }
+            entries.insert(name.to_string(), Some(*data));
+        }
+        EmbeddedStorage {
+            root: root.to_path_buf(),
+            modified,
+            entries,
+        }
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<(String, Option<&'static [u8]>)> {
+        let relative = path.strip_prefix(&self.root).map_err(|_| not_found())?;
+        let name = entry_name(relative).ok_or_else(not_found)?;
+        let data = *self.entries.get(&name).ok_or_else(not_found)?;
+        Ok((name, data))
+    }
+
+    fn metadata_of(&self, data: Option<&[u8]>) -> Metadata {
+        Metadata {
+            is_dir: data.is_none(),
+            len: data.map_or(0, |data| data.len() as u64),
+            modified: Some(self.modified),
+        }
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "not an embedded file")
+}
+
+impl Storage for EmbeddedStorage {
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Metadata>> {
+        Box::pin(async move { self.entry(path).map(|(_, data)| self.metadata_of(data)) })
+    }
+
+    fn list<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<DirEntry>>> {
+        Box::pin(async move {
+            let (name, data) = self.entry(path)?;
+            if data.is_some() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    "not a directory",
+                ));
+            }
+            let prefix = if name.is_empty() {
+                name
+            } else {
+                format!("{}/", name)
+            };
+            Ok(self
+                .entries
+                .range(prefix.clone()..)
+                .take_while(|(child, _)| child.starts_with(&prefix))
+                .filter_map(|(child, data)| {
+                    let child = &child[prefix.len()..];
+                    (!child.is_empty() && !child.contains('/')).then(|| DirEntry {
+                        name: child.into(),
+                        metadata: Some(self.metadata_of(*data)),
+                    })
+                })
+                .collect())
+        })
+    }
+
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<ByteStream>> {
+        self.read_range(path, 0, u64::MAX)
+    }
+
+    fn read_range<'a>(
+        &'a self,
+        path: &'a Path,
+        start: u64,
+        len: u64,
+    ) -> BoxFuture<'a, io::Result<ByteStream>> {
+        Box::pin(async move {
+            let data = self
+                .entry(path)?
+                .1
+                .ok_or_else(|| io::Error::new(io::ErrorKind::IsADirectory, "is a directory"))?;
+            let start = data.len().min(start as usize);
+            let end = data.len().min(start.saturating_add(len as usize));
+            let chunk = Bytes::from_static(&data[start..end]);
+            Ok(futures_util::stream::iter([Ok(chunk)]).boxed())
+        })
+    }
+
+    fn write<'a>(&'a self, _path: &'a Path, _data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        Box::pin(async move {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "embedded files are read-only",
+            ))
+        })
+    }
+
+    fn persist<'a>(&'a self, staged: Staged, _path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            staged.discard().await;
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "embedded files are read-only",
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::collect;
+
+    #[tokio::test]
+    async fn serves_embedded_files() {
+        static FILES: &[(&str, &[u8])] =
+            &[("index.html", b"<h1>hi</h1>"), ("css/site.css", b"body {}")];
+        let root = Path::new("/site");
+        let storage = EmbeddedStorage::from_files(root, FILES, UNIX_EPOCH);
+        assert!(storage.metadata(root).await.unwrap().is_dir);
+        assert!(storage.metadata(&root.join("css")).await.unwrap().is_dir);
+        let mut names = storage
+            .list(root)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name.into_string().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["css", "index.html"]);
+        let body = storage.open(&root.join("css/site.css")).await.unwrap();
+        assert_eq!(collect(body).await.unwrap(), b"body {}");
+        let body = storage
+            .read_range(&root.join("index.html"), 4, 2)
+            .await
+            .unwrap();
+        assert_eq!(collect(body).await.unwrap(), b"hi");
+        assert!(storage.metadata(&root.join("missing")).await.is_err());
+        assert!(storage.metadata(&root.join("../etc")).await.is_err());
+    }
+}
@@ -0,0 +1,393 @@
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use futures_util::{Stream, StreamExt};
+use hyper::body::Bytes;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// A content coding samserve can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    /// Token used in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Identity => "identity",
+            Encoding::Gzip => "gzip",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+}
+
+/// An encoder writing into a buffer that is drained as output appears, so
+/// bodies can be compressed while they stream.
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    /// `None` for identity.
+    fn new(encoding: Encoding) -> io::Result<Option<Encoder>> {
+        Ok(match encoding {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some(Encoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                Compression::default(),
+            ))),
+            Encoding::Brotli => Some(Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                4096,
+                5,
+                22,
+            )))),
+            Encoding::Zstd => Some(Encoder::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                3,
+            )?)),
+        })
+    }
+
+    /// Feeds `data` in and returns whatever compressed output is ready.
+    fn write(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let output = match self {
+            Encoder::Gzip(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Encoder::Brotli(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+            Encoder::Zstd(encoder) => {
+                encoder.write_all(data)?;
+                encoder.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(output)))
+    }
+
+    /// Ends the stream and returns the remaining output.
+    fn finish(self) -> io::Result<Bytes> {
+        let output = match self {
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Brotli(encoder) => encoder.into_inner(),
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(output))
+    }
+}
+
+/// Compresses an in-memory body; `None` for identity.
+pub fn compress(data: &[u8], encoding: Encoding) -> io::Result<Option<Vec<u8>>> {
+    let Some(mut encoder) = Encoder::new(encoding)? else {
+        return Ok(None);
+    };
+    let mut output = encoder.write(data)?.to_vec();
+    output.extend_from_slice(&encoder.finish()?);
+    Ok(Some(output))
+}
+
+/// Compresses `body` as it is read. Identity passes it through untouched.
+pub fn compress_stream<S>(
+    body: S,
+    encoding: Encoding,
+) -> io::Result<impl Stream<Item = io::Result<Bytes>> + Send + 'static>
+where
+    S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+{
+    let encoder = Encoder::new(encoding)?;
+    let state = Some((body.boxed(), encoder));
+    Ok(futures_util::stream::unfold(state, |state| async move {
+        let (mut body, encoder) = state?;
+        let Some(mut encoder) = encoder else {
+            let chunk = body.next().await?;
+            return Some((chunk, Some((body, None))));
+        };
+        loop {
+            match body.next().await {
+                Some(Ok(chunk)) => match encoder.write(&chunk) {
+                    Ok(output) if output.is_empty() => continue,
+                    Ok(output) => return Some((Ok(output), Some((body, Some(encoder))))),
+                    Err(err) => return Some((Err(err), None)),
+                },
+                Some(Err(err)) => return Some((Err(err), None)),
+                None => return Some((encoder.finish(), None)),
+            }
+        }
+    }))
+}
+
+/// Extensions of files that are compressed already, whatever their MIME
+/// type says; `--no-compress-ext` adds to them.
+pub const NO_COMPRESS_EXTENSIONS: [&str; 27] = [
+    "jpg", "jpeg", "png", "gif", "webp", "avif", "heic", "ico", "mp4", "m4v", "mkv", "webm", "mov",
+    "mp3", "m4a", "ogg", "opus", "flac", "zip", "gz", "tgz", "bz2", "xz", "zst", "br", "7z", "rar",
+];
+
+/// Whether `path` has one of the default extensions or of `extra` (given
+/// without the dot), compared case-insensitively.
+pub fn has_no_compress_ext(path: &Path, extra: &[String]) -> bool {
+    let Some(ext) = path.extension().and_then(|ext| ext.to_str()) else {
+        return false;
+    };
+    NO_COMPRESS_EXTENSIONS
+        .iter()
+        .copied()
+        .chain(extra.iter().map(String::as_str))
+        .any(|skip| skip.eq_ignore_ascii_case(ext))
+}
+
+/// Whether responses of this MIME type are worth compressing. Media and
+/// archive formats are compressed already.
+pub fn is_compressible(mime: &str) -> bool {
+    let essence = mime
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    let Some((kind, subtype)) = essence.split_once('/') else {
+        return false;
+    };
+    kind == "text"
+        || subtype.ends_with("+json")
+        || subtype.ends_with("+xml")
+        || matches!(
+            subtype,
+            "json"
+                | "javascript"
+                | "x-javascript"
+                | "ecmascript"
+                | "xml"
+                | "wasm"
+                | "x-ndjson"
+                | "yaml"
+                | "x-yaml"
+                | "toml"
+                | "sql"
+                | "x-sh"
+                | "rtf"
+                | "x-tar"
+                | "vnd.ms-fontobject"
+                | "x-font-ttf"
+        )
+        || essence == "font/ttf"
+        || essence == "font/otf"
+        || essence == "image/bmp"
+        || essence == "image/x-icon"
+}
+
+/// Parses an `Accept-Encoding` value into lowercased codings and their
+/// quality values. Entries with a malformed `q` are dropped.
+pub fn parse(header: &str) -> Vec<(String, f32)> {
+    header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';').map(str::trim);
+            let coding = parts.next()?.to_ascii_lowercase();
+            if coding.is_empty() {
+                return None;
+            }
+            let mut q = 1.0;
+            for param in parts {
+                let Some((name, value)) = param.split_once('=') else {
+                    continue;
+                };
+                if name.trim().eq_ignore_ascii_case("q") {
+                    q = value.trim().parse::<f32>().ok()?;
+                    if !(0.0..=1.0).contains(&q) {
+                        return None;
+                    }
+                }
+            }
+            Some((coding, q))
+        })
+        .collect()
+}
+
+/// Picks the coding to respond with. `supported` lists what the caller can
+/// produce in order of preference, which breaks ties between equal quality
+/// values. Returns `None` when nothing supported is acceptable (e.g. only
+/// unsupported codings with `identity;q=0`).
+pub fn negotiate(header: Option<&str>, supported: &[Encoding]) -> Option<Encoding> {
+    // Without the header any coding is acceptable; identity is the safe pick.
+    let Some(header) = header else {
+        return supported
+            .contains(&Encoding::Identity)
+            .then_some(Encoding::Identity);
+    };
+    let accepted = parse(header);
+    let listed = |name: &str| {
+        accepted
+            .iter()
+            .find(|(coding, _)| coding == name)
+            .map(|&(_, q)| q)
+    };
+    let wildcard = listed("*");
+    let quality = |encoding: Encoding| -> f32 {
+        let explicit = match encoding {
+            // `x-gzip` is the legacy spelling of gzip.
+            Encoding::Gzip => listed("gzip").or_else(|| listed("x-gzip")),
+            _ => listed(encoding.name()),
+        };
+        match (explicit, wildcard) {
+            (Some(q), _) => q,
+            (None, Some(q)) => q,
+            // Identity stays acceptable unless excluded explicitly.
+            (None, None) if encoding == Encoding::Identity => 1.0,
+            (None, None) => 0.0,
+        }
+    };
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in supported {
+        let q = quality(encoding);
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((encoding, q));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Identity];
+
+    #[test]
+    fn parses_quality_values() {
+        assert_eq!(
+            parse("gzip;q=0.5, BR ; q=1.0, identity"),
+            vec![
+                ("gzip".to_string(), 0.5),
+                ("br".to_string(), 1.0),
+                ("identity".to_string(), 1.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_malformed_quality_values() {
+        assert_eq!(
+            parse("gzip;q=abc, br;q=2, deflate"),
+            vec![("deflate".to_string(), 1.0)]
+        );
+    }
+
+    #[test]
+    fn missing_header_means_identity() {
+        assert_eq!(negotiate(None, &ALL), Some(Encoding::Identity));
+    }
+
+    #[test]
+    fn empty_header_means_identity() {
+        assert_eq!(negotiate(Some(""), &ALL), Some(Encoding::Identity));
+    }
+
+    #[test]
+    fn highest_quality_wins() {
+        assert_eq!(
+            negotiate(Some("gzip;q=0.5, br;q=1.0"), &ALL),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(
+            negotiate(Some("gzip;q=1.0, br;q=0.5"), &ALL),
+            Some(Encoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn ties_follow_server_preference() {
+        assert_eq!(negotiate(Some("gzip, br"), &ALL), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate(
+                Some("gzip, br"),
+                &[Encoding::Gzip, Encoding::Brotli, Encoding::Identity]
+            ),
+            Some(Encoding::Gzip)
+        );
+    }
+
+    #[test]
+    fn zero_quality_excludes_a_coding() {
+        assert_eq!(negotiate(Some("br;q=0, gzip"), &ALL), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate(Some("gzip;q=0"), &[Encoding::Gzip, Encoding::Identity]),
+            Some(Encoding::Identity)
+        );
+    }
+
+    #[test]
+    fn identity_can_be_refused() {
+        assert_eq!(
+            negotiate(Some("identity;q=0, gzip"), &ALL),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate(Some("identity;q=0, zstd"), &ALL), None);
+        assert_eq!(negotiate(Some("*;q=0"), &ALL), None);
+    }
+
+    #[test]
+    fn wildcard_covers_unlisted_codings() {
+        assert_eq!(negotiate(Some("*"), &ALL), Some(Encoding::Brotli));
+        assert_eq!(
+            negotiate(Some("br;q=0, *;q=0.5"), &ALL),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            negotiate(Some("*;q=0, identity"), &ALL),
+            Some(Encoding::Identity)
+        );
+    }
+
+    #[tokio::test]
+    async fn compresses_streams() {
+        let data = "line of a log file\n".repeat(1000);
+        let chunks = data
+            .as_bytes()
+            .chunks(1000)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        let stream = compress_stream(futures_util::stream::iter(chunks), Encoding::Zstd).unwrap();
+        let compressed = stream.map(|chunk| chunk.unwrap().to_vec()).concat().await;
+        assert!(compressed.len() < data.len() / 10);
+        assert_eq!(zstd::decode_all(&compressed[..]).unwrap(), data.as_bytes());
+
+        let gzipped = compress(data.as_bytes(), Encoding::Gzip).unwrap().unwrap();
+        let mut gunzip = flate2::read::GzDecoder::new(&gzipped[..]);
+        let mut decoded = String::new();
+        io::Read::read_to_string(&mut gunzip, &mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn compresses_only_compressible_types() {
+        assert!(is_compressible("text/plain; charset=utf-8"));
+        assert!(is_compressible("application/json"));
+        assert!(is_compressible("image/svg+xml"));
+        assert!(!is_compressible("image/png"));
+        assert!(!is_compressible("application/zip"));
+        assert!(!is_compressible("application/octet-stream"));
+
+        let extra = ["LOG".to_string()];
+        assert!(has_no_compress_ext(Path::new("/srv/photo.JPG"), &[]));
+        assert!(has_no_compress_ext(Path::new("/srv/backup.tar.gz"), &[]));
+        assert!(has_no_compress_ext(Path::new("/srv/old.log"), &extra));
+        assert!(!has_no_compress_ext(Path::new("/srv/old.log"), &[]));
+        assert!(!has_no_compress_ext(Path::new("/srv/README"), &extra));
+    }
+
+    #[test]
+    fn accepts_legacy_x_gzip() {
+        assert_eq!(negotiate(Some("x-gzip"), &ALL), Some(Encoding::Gzip));
+    }
+}
@@ -1,37 +1,326 @@
 use base64::{Engine as _, engine::general_purpose};
-use futures_util::TryStreamExt;
-use html_escape::encode_text;
-use hyper::{Body, Method, Request, Response, StatusCode, header};
+use futures_util::{StreamExt, TryStreamExt, future, stream};
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use hyper::body::Bytes;
+use hyper::body::HttpBody;
+use hyper::{Body, Method, Request, Response, StatusCode, Version, header};
 use log::{error, info, warn};
 use mime_guess::from_path;
 use multer::Multipart;
-use percent_encoding::percent_decode_str;
+use percent_encoding::{AsciiSet, CONTROLS, percent_decode_str, utf8_percent_encode};
+use sha2::{Digest as _, Sha256};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use std::{
     convert::Infallible,
     path::{Path, PathBuf},
 };
 use tokio::fs;
-use tokio::fs::{File, read_dir};
-use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
-use tokio_util::io::ReaderStream;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::bandwidth;
+use crate::cache;
+use crate::cgi;
+use crate::config::Config;
+use crate::cors;
+use crate::digest;
+use crate::digest_auth::{DigestAuth, Rejection};
+use crate::dirsize;
+use crate::encoding::{self, Encoding};
+use crate::metrics::{METRICS, metrics_response};
+use crate::pathfilter;
+use crate::plugin;
+use crate::proxy;
+use crate::redirect;
+use crate::session::{self, Sessions};
+use crate::stats;
+use crate::storage::{self, Staged, Storage};
+use crate::symlinks::{self, FollowSymlinks};
+use crate::template;
+use crate::thumbnail;
+use crate::timefmt;
+use crate::transfer::{TransferInfo, log_transfer};
+use crate::users::{self, constant_time_eq};
+use crate::webdav;
+
+/// Number of leading bytes inspected by `--mime-sniff`.
+const SNIFF_LEN: u64 = 512;
+
+/// Most ranges honoured in one request; more are answered with the whole
+/// file rather than a multipart body of many tiny parts.
+const MAX_RANGES: usize = 16;
+
+/// Methods a POST may be rewritten to via `X-HTTP-Method-Override` or `?_method=`.
+const OVERRIDABLE_METHODS: [Method; 4] = [Method::GET, Method::HEAD, Method::PUT, Method::DELETE];
 
 pub async fn handle_requests(
     req: Request<Body>,
     remote_addr: std::net::SocketAddr,
-    root_dir: Arc<String>,
-    auth: Arc<Option<String>>,
-    upload: bool,
+    client: Option<Arc<str>>,
+    config: Arc<Config>,
+) -> Result<Response<Body>, Infallible> {
+    let config = select_vhost(&req, config);
+    if let Some(subject) = &client {
+        info!(
+            "Client certificate | subject: {} | method: {} | uri: {} | remote: {}",
+            subject,
+            req.method(),
+            req.uri(),
+            remote_addr
+        );
+    }
+    let req = match apply_base_path(req, &config.base_path, remote_addr) {
+        Ok(req) => req,
+        Err(response) => return Ok(response),
+    };
+    if config.metrics_path.as_deref() == Some(req.uri().path()) {
+        return Ok(metrics_response());
+    }
+    if let Some(limiter) = &config.rate_limiter
+        && let Err(wait) = limiter.check(remote_addr.ip())
+    {
+        return Ok(rate_limited(&req, wait, remote_addr));
+    }
+    let (req, config) = match select_mount(req, config) {
+        Ok(selected) => selected,
+        Err(response) => return Ok(response),
+    };
+    let is_head = req.method() == Method::HEAD;
+    let version = req.version();
+    let origin = req.headers().get(header::ORIGIN).cloned();
+    let mut transfer = TransferInfo {
+        method: req.method().clone(),
+        uri: req.uri().clone(),
+        remote_addr,
+        user: None,
+        start: Instant::now(),
+    };
+    let mut response = route_request(
+        req,
+        remote_addr,
+        client.is_some(),
+        Arc::clone(&config),
+        &mut transfer.user,
+    )
+    .await?;
+    apply_security_headers(response.headers_mut(), &config);
+    cors::apply_headers(response.headers_mut(), &config.cors, origin.as_ref());
+    apply_custom_headers(response.headers_mut(), &config);
+    if let Some(bandwidth) = &config.bandwidth {
+        response = bandwidth::throttle(response, bandwidth);
+    }
+    let bytes = response
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .filter(|_| !is_head)
+        .unwrap_or(0);
+    METRICS.record_response(response.status(), bytes);
+    let mut response = log_transfer(response, transfer);
+    // HTTP/1.0 clients cannot parse chunked encoding, so a body of unknown
+    // length has to be delimited by closing the connection.
+    if version == Version::HTTP_10
+        && !is_head
+        && !response.headers().contains_key(header::CONTENT_LENGTH)
+        && !response.body().is_end_stream()
+    {
+        response.headers_mut().insert(
+            header::CONNECTION,
+            header::HeaderValue::from_static("close"),
+        );
+    }
+    Ok(response)
+}
+
+/// `client_verified` is set when the connection presented a client
+/// certificate accepted by `--tls-client-ca`, which stands in for Basic Auth.
+/// `user` is set to the Basic Auth user once their credentials are checked.
+async fn route_request(
+    mut req: Request<Body>,
+    remote_addr: std::net::SocketAddr,
+    client_verified: bool,
+    config: Arc<Config>,
+    user: &mut Option<String>,
 ) -> Result<Response<Body>, Infallible> {
-    if let Some(base64_auth) = &*auth {
-        if let Err(unauthorize) = check_basic_auth(&req, base64_auth, remote_addr) {
-            return Ok(unauthorize);
+    if let Some(response) = cors::preflight(&req, &config.cors, allowed_methods(&config)) {
+        info!(
+            "CORS preflight | origin: {:?} | uri: {} | status: {} | remote: {}",
+            req.headers().get(header::ORIGIN),
+            req.uri(),
+            response.status(),
+            remote_addr
+        );
+        return Ok(response);
+    }
+    if checks_credentials(&config) && !client_verified {
+        if let Some(sessions) = &config.sessions {
+            match req.uri().path() {
+                session::LOGIN_PATH => {
+                    return Ok(handle_login(req, sessions, &config, remote_addr, user).await);
+                }
+                session::LOGOUT_PATH => return Ok(handle_logout(sessions, &config, remote_addr)),
+                _ => {}
+            }
         }
+        if requires_auth(&req, &config) {
+            if let Some(left) = config.lockout.banned(remote_addr.ip()) {
+                return Ok(locked_out(&req, left, remote_addr));
+            }
+            let mut stale = false;
+            let session_user = config
+                .sessions
+                .as_ref()
+                .and_then(|sessions| sessions.user(req.headers()))
+                .filter(|name| account_exists(&config, name));
+            let authorized = match (bearer_token(&req), &config.digest_auth) {
+                // Only the writers' own credentials will do.
+                _ if config.write_users.is_some() && !is_read(req.method()) => {
+                    *user = check_basic_auth(&req, &config, remote_addr).await;
+                    user.is_some()
+                }
+                _ if session_user.is_some() => {
+                    *user = session_user;
+                    true
+                }
+                (Some(token), _) => check_bearer_token(&req, token, &config.tokens, remote_addr),
+                (None, Some(digest)) => match check_digest_auth(&req, digest, &config, remote_addr)
+                {
+                    Ok(name) => {
+                        *user = Some(name);
+                        true
+                    }
+                    Err(rejection) => {
+                        stale = rejection == Rejection::Stale;
+                        false
+                    }
+                },
+                (None, None) => {
+                    *user = check_basic_auth(&req, &config, remote_addr).await;
+                    user.is_some()
+                }
+            };
+            if !authorized {
+                METRICS.record_auth_failure();
+                // A stale Digest nonce is not a wrong password.
+                if req.headers().contains_key(header::AUTHORIZATION) && !stale {
+                    count_auth_failure(&config, remote_addr);
+                }
+                if config.sessions.is_some()
+                    && !req.headers().contains_key(header::AUTHORIZATION)
+                    && wants_login_page(&req)
+                {
+                    return Ok(login_redirect(&req, &config, remote_addr));
+                }
+                return Ok(unauthorized_response(&config, stale, remote_addr).await);
+            }
+            if req.headers().contains_key(header::AUTHORIZATION) {
+                config.lockout.record_success(remote_addr.ip());
+            }
+        }
+    }
+    if let Some(status) = plugin::on_request(&config.plugins, &req, remote_addr) {
+        warn!(
+            "Request refused by plugin | uri: {} | status: {} | remote: {}",
+            req.uri(),
+            status,
+            remote_addr
+        );
+        let response = Response::builder()
+            .status(status)
+            .body(Body::from("Request refused"))
+            .unwrap();
+        return Ok(with_error_page(response, &config, remote_addr).await);
     }
-    let uri_path = (&req).uri().path();
+    if let Some(rule) = proxy::route(&config.proxies, req.uri().path()) {
+        return Ok(proxy::forward(req, rule, checks_credentials(&config), remote_addr).await);
+    }
+    if config.allow_method_override
+        && req.method() == Method::POST
+        && let Some(requested) = method_override(&req)
+    {
+        match Method::from_bytes(requested.to_ascii_uppercase().as_bytes()) {
+            Ok(method) if OVERRIDABLE_METHODS.contains(&method) => {
+                info!(
+                    "Method override | from: {} | to: {} | uri: {} | remote: {}",
+                    req.method(),
+                    method,
+                    req.uri(),
+                    remote_addr
+                );
+                *req.method_mut() = method;
+            }
+            _ => {
+                warn!(
+                    "Method override rejected | requested: {:?} | uri: {} | status: {} | remote: {}",
+                    requested,
+                    req.uri(),
+                    StatusCode::BAD_REQUEST,
+                    remote_addr
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Method override not allowed"))
+                    .unwrap());
+            }
+        }
+    }
+    if let Ok(decoded_path) = percent_decode_str(req.uri().path()).decode_utf8()
+        && pathfilter::is_hidden(&decoded_path, &config)
+    {
+        warn!(
+            "Hidden path requested | path: {:?} | status: {} | remote: {}",
+            decoded_path,
+            StatusCode::NOT_FOUND,
+            remote_addr
+        );
+        let response = Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("File not found"))
+            .unwrap();
+        return Ok(with_error_page(response, &config, remote_addr).await);
+    }
+    if let Ok(path) = resolve_path(&config.root, req.uri().path(), remote_addr)
+        && !symlinks::allowed(&path, &config.root, config.follow_symlinks).await
+    {
+        return Ok(symlink_refused(&path, &config, remote_addr).await);
+    }
+    if let Some(dir) = &config.cgi
+        && let Ok(decoded_path) = percent_decode_str(req.uri().path()).decode_utf8()
+        && cgi::handles(&decoded_path, dir)
+    {
+        let decoded_path = decoded_path.into_owned();
+        return Ok(cgi::run(req, &decoded_path, user.as_deref(), &config, remote_addr).await);
+    }
+    if config.webdav && webdav::handles(req.method()) {
+        return Ok(webdav::handle(req, &config, remote_addr).await);
+    }
+    let is_move = req.method().as_str() == "MOVE"
+        || (req.method() == Method::POST
+            && query_param(req.uri().query(), "action").as_deref() == Some("move"));
+    if is_move {
+        if !config.upload {
+            error!(
+                "Move attempted but uploads are disabled | uri: {} | status: {} | remote: {}",
+                req.uri(),
+                StatusCode::FORBIDDEN,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("File modifications are disabled on this server"))
+                .unwrap());
+        }
+        return Ok(handle_move(&req, &config, remote_addr)
+            .await
+            .unwrap_or_else(|resp| resp));
+    }
+    let uri_path = req.uri().path();
     if req.method() == Method::POST {
-        if upload {
-            return handle_upload(req, PathBuf::from(root_dir.as_str()), remote_addr).await;
+        if config.upload {
+            return handle_upload(req, &config.root, remote_addr, &config).await;
         } else {
             error!(
                 "Upload attempted but uploads are disabled | path: {:?} | version: {:?} | status: {} | remote: {}",
@@ -46,25 +335,579 @@ pub async fn handle_requests(
                 .unwrap());
         }
     }
-    let range_header = req
-        .headers()
-        .get(header::RANGE)
-        .and_then(|h| h.to_str().ok());
-    let response = match serve_file(uri_path, &root_dir, remote_addr, range_header).await {
+    if req.method() == Method::OPTIONS {
+        info!(
+            "Options | path: {:?} | status: {} | remote: {}",
+            uri_path,
+            StatusCode::NO_CONTENT,
+            remote_addr
+        );
+        return Ok(Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(header::ALLOW, allowed_methods(&config))
+            .body(Body::empty())
+            .unwrap());
+    }
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        warn!(
+            "Method not allowed | method: {} | path: {:?} | status: {} | remote: {}",
+            req.method(),
+            uri_path,
+            StatusCode::METHOD_NOT_ALLOWED,
+            remote_addr
+        );
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, allowed_methods(&config))
+            .body(Body::from("Method not allowed"))
+            .unwrap());
+    }
+    let mut response = match serve_file(&req, &config.root, remote_addr, &config).await {
         Ok(resp) => resp,
-        Err(resp) => resp,
+        Err(resp) => with_error_page(resp, &config, remote_addr).await,
     };
+    if config.lang_negotiation {
+        response.headers_mut().append(
+            header::VARY,
+            header::HeaderValue::from_static("Accept-Language"),
+        );
+    }
     Ok(response)
 }
 
 async fn serve_file(
-    request_path: &str,
+    req: &Request<Body>,
     root: &str,
     remote_addr: std::net::SocketAddr,
-    range_header: Option<&str>,
+    config: &Config,
 ) -> Result<Response<Body>, Response<Body>> {
-    let decoded_path = match percent_decode_str(request_path).decode_utf8() {
-        Ok(path) => path,
+    let request_path = req.uri().path();
+    let head_only = req.method() == Method::HEAD;
+    if request_path.contains("//") {
+        let mut canonical = format!("{}{}", config.base_path, collapse_slashes(request_path));
+        if let Some(query) = req.uri().query() {
+            canonical.push('?');
+            canonical.push_str(query);
+        }
+        info!(
+            "Redirecting to canonical path | requested: {:?} | location: {:?} | status: {} | remote: {}",
+            request_path,
+            canonical,
+            StatusCode::MOVED_PERMANENTLY,
+            remote_addr
+        );
+        return Ok(Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header(header::LOCATION, canonical)
+            .body(Body::empty())
+            .unwrap());
+    }
+    let decoded_path = decode_path(request_path, remote_addr)?;
+    if decoded_path.len() > config.max_path_length {
+        warn!(
+            "Request path too long | length: {} | limit: {} | status: {} | remote: {}",
+            decoded_path.len(),
+            config.max_path_length,
+            StatusCode::URI_TOO_LONG,
+            remote_addr
+        );
+        return Err(Response::builder()
+            .status(StatusCode::URI_TOO_LONG)
+            .body(Body::from("URI too long"))
+            .unwrap());
+    }
+    let mut path = join_under_root(root, &decoded_path, remote_addr)?;
+    if config.lang_negotiation
+        && let Some(variant) = language_variant(&path, req, config).await
+    {
+        path = variant;
+    }
+
+    let metadata = match config.storage.metadata(&path).await {
+        Ok(meta) => meta,
+        // Client-side routes have no file of their own: the app's entry
+        // page handles them.
+        Err(err)
+            if config.spa
+                && err.kind() == std::io::ErrorKind::NotFound
+                && let Some(index_path) = index_file(Path::new(root), req, config).await =>
+        {
+            info!(
+                "SPA fallback | path: {:?} | requested: {:?} | status: {} | remote: {}",
+                index_path,
+                request_path,
+                StatusCode::OK,
+                remote_addr
+            );
+            return stream_file(&index_path, req.headers(), remote_addr, head_only, config).await;
+        }
+        Err(err) => {
+            error!(
+                "Failed to read metadata | path: {:?} | error: {} | status: {} | remote: {}",
+                path,
+                err,
+                StatusCode::NOT_FOUND,
+                remote_addr
+            );
+            return Err(Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .body(Body::from("File not found"))
+                .unwrap());
+        }
+    };
+
+    if metadata.is_dir {
+        if let Some(index_path) = index_file(&path, req, config).await {
+            info!(
+                "Serving index | path: {:?} | requested: {:?} | status: {} | remote: {}",
+                index_path,
+                request_path,
+                StatusCode::OK,
+                remote_addr
+            );
+            return stream_file(&index_path, req.headers(), remote_addr, head_only, config).await;
+        }
+        let format = ListingFormat::from_request(req);
+        if let Some(welcome_page) = &config.welcome_page
+            && format == ListingFormat::Html
+            && path == Path::new(root)
+        {
+            info!(
+                "Serving welcome page | path: {:?} | requested: {:?} | status: {} | remote: {}",
+                welcome_page,
+                request_path,
+                StatusCode::OK,
+                remote_addr
+            );
+            return match read_page(welcome_page, config).await {
+                Ok(html) => Ok(generated_response(
+                    req,
+                    "text/html; charset=utf-8",
+                    template::render(&html, config),
+                )),
+                Err(err) => {
+                    error!(
+                        "Failed to read welcome page | path: {:?} | error: {} | status: {} | remote: {}",
+                        welcome_page,
+                        err,
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        remote_addr
+                    );
+                    Err(Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Error reading welcome page"))
+                        .unwrap())
+                }
+            };
+        }
+        if !config.listing {
+            warn!(
+                "Directory listing disabled | path: {:?} | requested: {:?} | status: {} | remote: {}",
+                path,
+                request_path,
+                StatusCode::FORBIDDEN,
+                remote_addr
+            );
+            return Err(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("Directory listing is disabled"))
+                .unwrap());
+        }
+        let sort = ListingSort::from_query(req.uri().query());
+        let listing = render_directory_listing(&path, request_path, format, sort, config).await;
+        match listing {
+            Ok(body) => {
+                info!(
+                    "Directory listing | path: {:?} | requested: {:?} | format: {:?} | status: {} | remote: {}",
+                    path,
+                    request_path,
+                    format,
+                    StatusCode::OK,
+                    remote_addr
+                );
+                let mut response = generated_response(req, format.content_type(), body);
+                response.headers_mut().insert(
+                    header::CACHE_CONTROL,
+                    header::HeaderValue::from_static(cache::LISTING_CACHE_CONTROL),
+                );
+                if format == ListingFormat::Html {
+                    let headers = response.headers_mut();
+                    headers.insert(
+                        header::CONTENT_SECURITY_POLICY,
+                        header::HeaderValue::from_static(LISTING_CSP),
+                    );
+                    headers.insert(
+                        header::REFERRER_POLICY,
+                        header::HeaderValue::from_static("same-origin"),
+                    );
+                }
+                return Ok(response);
+            }
+            Err(err) => {
+                error!(
+                    "Error rendering directory listing | path: {:?} | error: {} | status: {} | remote: {}",
+                    path,
+                    err,
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    remote_addr
+                );
+                return Err(Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from("Error rendering directory listing"))
+                    .unwrap());
+            }
+        }
+    }
+    if config.thumbnails
+        && let Some(size) = query_param(req.uri().query(), "thumb")
+    {
+        return serve_thumbnail(&path, &size, config, remote_addr).await;
+    }
+    stream_file(&path, req.headers(), remote_addr, head_only, config).await
+}
+
+/// The `Allow` header value for the features enabled in `config`.
+fn allowed_methods(config: &Config) -> &'static str {
+    if config.webdav {
+        webdav::ALLOW
+    } else if config.upload {
+        "OPTIONS, GET, HEAD, POST, MOVE"
+    } else {
+        "OPTIONS, GET, HEAD"
+    }
+}
+
+/// The settings for the host a request is addressed to: its `--vhost`
+/// root, or the default one for any other host.
+fn select_vhost(req: &Request<Body>, config: Arc<Config>) -> Arc<Config> {
+    if config.vhosts.is_empty() {
+        return config;
+    }
+    // HTTP/2 requests carry the host in the URI instead of a Host header.
+    let host = req.uri().host().or_else(|| {
+        req.headers()
+            .get(header::HOST)
+            .and_then(|h| h.to_str().ok())
+            .and_then(redirect::host_without_port)
+    });
+    let hosted = host.and_then(|host| {
+        let host = host.trim_end_matches('.');
+        config
+            .vhosts
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+    });
+    match hosted {
+        Some((_, hosted)) => Arc::clone(hosted),
+        None => config,
+    }
+}
+
+/// Takes `--base-path` off the request URI. The base itself redirects to
+/// its directory form; anything outside it is not found.
+#[allow(clippy::result_large_err)]
+fn apply_base_path(
+    req: Request<Body>,
+    base_path: &str,
+    remote_addr: std::net::SocketAddr,
+) -> Result<Request<Body>, Response<Body>> {
+    if base_path.is_empty() {
+        return Ok(req);
+    }
+    if req.uri().path() == base_path {
+        return Err(directory_redirect(&req, base_path));
+    }
+    if strip_base_path(req.uri().path(), base_path).is_none() {
+        warn!(
+            "Request outside the base path | uri: {} | base: {:?} | status: {} | remote: {}",
+            req.uri(),
+            base_path,
+            StatusCode::NOT_FOUND,
+            remote_addr
+        );
+        return Err(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("Not found"))
+            .unwrap());
+    }
+    Ok(strip_uri_prefix(req, base_path))
+}
+
+/// Picks the `--mount` a request falls under, taking its prefix off the
+/// URI; other requests stay with the root's settings.
+#[allow(clippy::result_large_err)]
+fn select_mount(
+    req: Request<Body>,
+    config: Arc<Config>,
+) -> Result<(Request<Body>, Arc<Config>), Response<Body>> {
+    let path = req.uri().path();
+    let Some((prefix, mounted)) = config
+        .mounts
+        .iter()
+        .find(|(prefix, _)| path == prefix || strip_base_path(path, prefix).is_some())
+    else {
+        return Ok((req, config));
+    };
+    if path == prefix {
+        return Err(directory_redirect(&req, &mounted.base_path));
+    }
+    let mounted = Arc::clone(mounted);
+    Ok((strip_uri_prefix(req, prefix), mounted))
+}
+
+/// Redirects a request for a prefix without its trailing `/` to `public`,
+/// the prefix as clients see it, in directory form.
+fn directory_redirect(req: &Request<Body>, public: &str) -> Response<Body> {
+    let mut location = format!("{}/", public);
+    if let Some(query) = req.uri().query() {
+        location.push('?');
+        location.push_str(query);
+    }
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header(header::LOCATION, location)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// The request with `prefix`, which its path starts with, taken off the URI.
+fn strip_uri_prefix(req: Request<Body>, prefix: &str) -> Request<Body> {
+    let stripped = &req.uri().path()[prefix.len()..];
+    let path_and_query = match req.uri().query() {
+        Some(query) => format!("{}?{}", stripped, query),
+        None => stripped.to_string(),
+    };
+    let (mut parts, body) = req.into_parts();
+    let mut uri_parts = parts.uri.into_parts();
+    // The remainder of a valid path is itself valid.
+    uri_parts.path_and_query = Some(path_and_query.parse().unwrap());
+    parts.uri = hyper::Uri::from_parts(uri_parts).unwrap();
+    Request::from_parts(parts, body)
+}
+
+/// `path` with `prefix` taken off, or `None` when it lies outside it.
+pub fn strip_base_path<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    if prefix.is_empty() {
+        return Some(path);
+    }
+    path.strip_prefix(prefix)
+        .filter(|rest| rest.starts_with('/'))
+}
+
+/// Collapses runs of `/` into one, so `//a///b/` becomes `/a/b/`.
+fn collapse_slashes(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for c in path.chars() {
+        if c == '/' && out.ends_with('/') {
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// The first `--index` file present in `dir`, or a language variant of it
+/// with `--lang-negotiation`.
+async fn index_file(dir: &Path, req: &Request<Body>, config: &Config) -> Option<PathBuf> {
+    for name in &config.index_files {
+        let path = dir.join(name);
+        if config.lang_negotiation
+            && let Some(variant) = language_variant(&path, req, config).await
+            && symlinks::allowed(&variant, &config.root, config.follow_symlinks).await
+        {
+            return Some(variant);
+        }
+        if config
+            .storage
+            .metadata(&path)
+            .await
+            .is_ok_and(|m| !m.is_dir)
+            && symlinks::allowed(&path, &config.root, config.follow_symlinks).await
+        {
+            return Some(path);
+        }
+    }
+    None
+}
+
+/// Finds a language variant of `path` (`page.html` -> `page.fr.html`) matching
+/// the client's `Accept-Language` preferences, trying each full tag before its
+/// primary subtag.
+async fn language_variant(path: &Path, req: &Request<Body>, config: &Config) -> Option<PathBuf> {
+    let accept_language = req.headers().get(header::ACCEPT_LANGUAGE)?.to_str().ok()?;
+    let stem = path.file_stem()?.to_str()?;
+    let ext = path.extension()?.to_str()?;
+    for lang in accepted_languages(accept_language) {
+        let primary = lang.split('-').next().unwrap_or(&lang).to_string();
+        for tag in [lang.as_str(), primary.as_str()] {
+            let variant = path.with_file_name(format!("{}.{}.{}", stem, tag, ext));
+            if config
+                .storage
+                .metadata(&variant)
+                .await
+                .is_ok_and(|m| !m.is_dir)
+            {
+                return Some(variant);
+            }
+        }
+    }
+    None
+}
+
+/// Language tags from an `Accept-Language` header, most preferred first.
+fn accepted_languages(header: &str) -> Vec<String> {
+    let mut langs: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim().to_ascii_lowercase();
+            let q = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            let valid = !tag.is_empty()
+                && tag != "*"
+                && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+            (valid && q > 0.0).then_some((tag, q))
+        })
+        .collect();
+    langs.sort_by(|a, b| b.1.total_cmp(&a.1));
+    langs.into_iter().map(|(tag, _)| tag).collect()
+}
+
+/// Bodies shorter than this are sent uncompressed unless the client
+/// refuses identity; compression would not pay off.
+const COMPRESS_MIN_LEN: usize = 256;
+
+/// Builds the response for a page generated in memory (listings, welcome
+/// page). Such pages are produced per request, so any Range header is
+/// ignored and clients are told not to try; the buffer is compressed with
+/// the best coding the client accepts.
+fn generated_response(req: &Request<Body>, content_type: &str, body: String) -> Response<Body> {
+    let preference: &[Encoding] = if body.len() < COMPRESS_MIN_LEN {
+        &[
+            Encoding::Identity,
+            Encoding::Brotli,
+            Encoding::Zstd,
+            Encoding::Gzip,
+        ]
+    } else {
+        &[
+            Encoding::Brotli,
+            Encoding::Zstd,
+            Encoding::Gzip,
+            Encoding::Identity,
+        ]
+    };
+    let accept_encoding = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .map(|v| v.to_str().unwrap_or(""));
+    let builder = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::ACCEPT_RANGES, "none")
+        .header(header::VARY, "Accept-Encoding");
+    let Some(encoding) = encoding::negotiate(accept_encoding, preference) else {
+        return builder
+            .status(StatusCode::NOT_ACCEPTABLE)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body(Body::from("No acceptable content coding"))
+            .unwrap();
+    };
+    match encoding::compress(body.as_bytes(), encoding) {
+        Ok(Some(compressed)) => builder
+            .header(header::CONTENT_ENCODING, encoding.name())
+            .body(Body::from(compressed))
+            .unwrap(),
+        Ok(None) => builder.body(Body::from(body)).unwrap(),
+        Err(err) => {
+            error!(
+                "Compression failed | encoding: {} | error: {}",
+                encoding.name(),
+                err
+            );
+            builder.body(Body::from(body)).unwrap()
+        }
+    }
+}
+
+/// Policy for generated listing pages: inline styles, thumbnails and the
+/// upload form, all same-origin, and nothing else.
+const LISTING_CSP: &str = "default-src 'none'; style-src 'unsafe-inline'; img-src 'self'; \
+    form-action 'self'; base-uri 'none'; frame-ancestors 'none'";
+
+/// Adds the configured security headers. Explicit `--csp` and
+/// `--referrer-policy` values replace whatever the handler set.
+fn apply_security_headers(headers: &mut header::HeaderMap, config: &Config) {
+    if config.nosniff {
+        headers.insert(
+            header::X_CONTENT_TYPE_OPTIONS,
+            header::HeaderValue::from_static("nosniff"),
+        );
+    }
+    if let Some(hsts) = &config.hsts {
+        headers.insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+    }
+    if let Some(csp) = &config.csp {
+        headers.insert(header::CONTENT_SECURITY_POLICY, csp.clone());
+    }
+    if let Some(referrer_policy) = &config.referrer_policy {
+        headers.insert(header::REFERRER_POLICY, referrer_policy.clone());
+    }
+}
+
+/// Answers a request whose path leads through a symlink `--follow-symlinks`
+/// does not allow.
+pub async fn symlink_refused(
+    path: &Path,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    warn!(
+        "Symlink not followed | path: {:?} | policy: {:?} | status: {} | remote: {}",
+        path,
+        config.follow_symlinks,
+        StatusCode::FORBIDDEN,
+        remote_addr
+    );
+    let response = Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(Body::from("Forbidden"))
+        .unwrap();
+    with_error_page(response, config, remote_addr).await
+}
+
+/// Adds the `--header` values. A name given several times is sent with
+/// every value given, replacing whatever the handler set for it.
+fn apply_custom_headers(headers: &mut header::HeaderMap, config: &Config) {
+    for (name, _) in &config.headers {
+        headers.remove(name);
+    }
+    for (name, value) in &config.headers {
+        headers.append(name.clone(), value.clone());
+    }
+}
+
+/// Maps a raw request path onto the filesystem below `root`, percent-decoding
+/// it and rejecting any component that could escape the root.
+#[allow(clippy::result_large_err)]
+pub fn resolve_path(
+    root: &str,
+    request_path: &str,
+    remote_addr: std::net::SocketAddr,
+) -> Result<PathBuf, Response<Body>> {
+    let decoded_path = decode_path(request_path, remote_addr)?;
+    join_under_root(root, &decoded_path, remote_addr)
+}
+
+/// Percent-decodes a raw request path, answering 400 for invalid UTF-8.
+#[allow(clippy::result_large_err)]
+fn decode_path(
+    request_path: &str,
+    remote_addr: std::net::SocketAddr,
+) -> Result<std::borrow::Cow<'_, str>, Response<Body>> {
+    match percent_decode_str(request_path).decode_utf8() {
+        Ok(path) => Ok(path),
         Err(err) => {
             error!(
                 "Invalid URL path decoding | raw: {:?} | error: {} | status: {} | remote: {}",
@@ -73,14 +916,37 @@ async fn serve_file(
                 StatusCode::BAD_REQUEST,
                 remote_addr
             );
-            return Err(Response::builder()
+            Err(Response::builder()
                 .status(StatusCode::BAD_REQUEST)
                 .body(Body::from("Invalid path"))
-                .unwrap());
+                .unwrap())
         }
-    };
+    }
+}
+
+/// Removes `prefix` from a decoded upload path when it matches whole
+/// leading segments; other paths are returned unchanged.
+fn strip_upload_prefix<'a>(decoded_path: &'a str, prefix: &str) -> &'a str {
+    let prefix = prefix.trim_end_matches('/');
+    if prefix.trim_start_matches('/').is_empty() {
+        return decoded_path;
+    }
+    let prefix = format!("/{}", prefix.trim_start_matches('/'));
+    match decoded_path.strip_prefix(prefix.as_str()) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => rest,
+        _ => decoded_path,
+    }
+}
+
+/// Appends an already-decoded path to `root`, rejecting `..` and prefixes.
+#[allow(clippy::result_large_err)]
+fn join_under_root(
+    root: &str,
+    decoded_path: &str,
+    remote_addr: std::net::SocketAddr,
+) -> Result<PathBuf, Response<Body>> {
     let mut path = PathBuf::from(root);
-    for part in Path::new(&*decoded_path).components() {
+    for part in Path::new(decoded_path).components() {
         use std::path::Component::*;
         match part {
             Normal(comp) => path.push(comp),
@@ -101,93 +967,254 @@ async fn serve_file(
             }
         }
     }
+    Ok(path)
+}
 
-    let metadata = match fs::metadata(&path).await {
-        Ok(meta) => meta,
-        Err(err) => {
+/// Renames a file or directory within the root, either through a WebDAV-style
+/// `MOVE` with a `Destination` header or a `POST ?action=move&to=<path>`.
+async fn handle_move(
+    req: &Request<Body>,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    let root = config.root.as_str();
+    let source = resolve_path(root, req.uri().path(), remote_addr)?;
+    let (destination, overwrite) = if req.method() == Method::POST {
+        let query = req.uri().query();
+        let overwrite = matches!(
+            query_param(query, "overwrite").as_deref(),
+            Some("true" | "1" | "T")
+        );
+        match query_param(query, "to") {
+            Some(to) => (join_under_root(root, &to, remote_addr)?, overwrite),
+            None => return Err(bad_move_request("Missing destination", req, remote_addr)),
+        }
+    } else {
+        let overwrite = req
+            .headers()
+            .get("overwrite")
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"T"));
+        // Destination is an absolute URI or an absolute path; only the path matters.
+        let target = req
+            .headers()
+            .get("destination")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<hyper::Uri>().ok());
+        match target {
+            Some(uri) => match strip_base_path(uri.path(), &config.base_path) {
+                Some(path) => (resolve_path(root, path, remote_addr)?, overwrite),
+                None => {
+                    return Err(bad_move_request(
+                        "Destination outside the base path",
+                        req,
+                        remote_addr,
+                    ));
+                }
+            },
+            None => return Err(bad_move_request("Missing destination", req, remote_addr)),
+        }
+    };
+
+    if source == Path::new(root) || destination == Path::new(root) {
+        return Err(bad_move_request(
+            "Cannot move the root directory",
+            req,
+            remote_addr,
+        ));
+    }
+    if pathfilter::relative_path(&destination, root)
+        .is_some_and(|relative| pathfilter::is_hidden(&relative, config))
+    {
+        return Err(bad_move_request("Destination is hidden", req, remote_addr));
+    }
+    if !symlinks::allowed(&destination, root, config.follow_symlinks).await {
+        return Err(symlink_refused(&destination, config, remote_addr).await);
+    }
+    if let Some(dir) = &config.cgi
+        && [&source, &destination].into_iter().any(|path| {
+            pathfilter::relative_path(path, root)
+                .is_some_and(|relative| cgi::overlaps(&relative, dir))
+        })
+    {
+        warn!(
+            "Move refused for the CGI directory | source: {:?} | destination: {:?} | status: {} | remote: {}",
+            source,
+            destination,
+            StatusCode::FORBIDDEN,
+            remote_addr
+        );
+        return Err(Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body(Body::from(
+                "Moves into or out of the CGI directory are not allowed",
+            ))
+            .unwrap());
+    }
+    if !config.storage.exists(&source).await {
+        error!(
+            "Move failed: source not found | source: {:?} | status: {} | remote: {}",
+            source,
+            StatusCode::NOT_FOUND,
+            remote_addr
+        );
+        return Err(Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::from("File not found"))
+            .unwrap());
+    }
+    let replaced = config.storage.exists(&destination).await;
+    if replaced && !overwrite {
+        error!(
+            "Move failed: destination exists | source: {:?} | destination: {:?} | status: {} | remote: {}",
+            source,
+            destination,
+            StatusCode::CONFLICT,
+            remote_addr
+        );
+        return Err(Response::builder()
+            .status(StatusCode::CONFLICT)
+            .body(Body::from("Destination already exists"))
+            .unwrap());
+    }
+    if let Err(err) = config.storage.rename(&source, &destination).await {
+        let status = match err.kind() {
+            std::io::ErrorKind::NotFound => StatusCode::CONFLICT,
+            std::io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        error!(
+            "Move failed | source: {:?} | destination: {:?} | error: {} | status: {} | remote: {}",
+            source, destination, err, status, remote_addr
+        );
+        return Err(Response::builder()
+            .status(status)
+            .body(Body::from("Move failed"))
+            .unwrap());
+    }
+    let status = if replaced {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    };
+    info!(
+        "Move complete | source: {:?} | destination: {:?} | status: {} | remote: {}",
+        source, destination, status, remote_addr
+    );
+    Ok(Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap())
+}
+
+fn bad_move_request(
+    reason: &'static str,
+    req: &Request<Body>,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    error!(
+        "Move rejected: {} | uri: {} | status: {} | remote: {}",
+        reason,
+        req.uri(),
+        StatusCode::BAD_REQUEST,
+        remote_addr
+    );
+    Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .body(Body::from(reason))
+        .unwrap()
+}
+
+async fn serve_thumbnail(
+    path: &Path,
+    size: &str,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    let size = match size.parse::<u32>() {
+        Ok(size) => size.clamp(thumbnail::MIN_SIZE, thumbnail::MAX_SIZE),
+        Err(_) => {
             error!(
-                "Failed to read metadata | path: {:?} | error: {} | status: {} | remote: {}",
+                "Invalid thumbnail size | path: {:?} | size: {:?} | status: {} | remote: {}",
                 path,
-                err,
-                StatusCode::NOT_FOUND,
+                size,
+                StatusCode::BAD_REQUEST,
                 remote_addr
             );
             return Err(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Body::from("File not found"))
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Invalid thumbnail size"))
                 .unwrap());
         }
     };
-
-    if metadata.is_dir() {
-        let index_path = path.join("index.html");
-        if index_path.exists() {
+    if !thumbnail::is_image(path) {
+        error!(
+            "Thumbnail requested for non-image | path: {:?} | status: {} | remote: {}",
+            path,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            remote_addr
+        );
+        return Err(Response::builder()
+            .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+            .body(Body::from("Not an image"))
+            .unwrap());
+    }
+    match thumbnail::thumbnail(config.storage.as_ref(), path, size).await {
+        Ok(png) => {
             info!(
-                "Serving index.html | path: {:?} | requested: {:?} | status: {} | remote: {}",
-                index_path,
-                request_path,
+                "Thumbnail | path: {:?} | size: {} | status: {} | remote: {}",
+                path,
+                size,
                 StatusCode::OK,
                 remote_addr
             );
-            return stream_file(&index_path, remote_addr, range_header).await;
-        } else {
-            let listing = render_directory_listing(&path, request_path).await;
-            match listing {
-                Ok(html) => {
-                    info!(
-                        "Directory listing | path: {:?} | requested: {:?} | status: {} | remote: {}",
-                        path,
-                        request_path,
-                        StatusCode::OK,
-                        remote_addr
-                    );
-                    return Ok(Response::builder()
-                        .header("Content-Type", "text/html")
-                        .body(Body::from(html))
-                        .unwrap());
-                }
-                Err(err) => {
-                    error!(
-                        "Error rendering directory listing | path: {:?} | error: {} | status: {} | remote: {}",
-                        path,
-                        err,
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        remote_addr
-                    );
-                    return Err(Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from("Error rendering directory listing"))
-                        .unwrap());
-                }
-            }
+            Ok(Response::builder()
+                .header(header::CONTENT_TYPE, "image/png")
+                .header(header::ACCEPT_RANGES, "none")
+                .body(Body::from(png))
+                .unwrap())
         }
-    }
-    stream_file(&path, remote_addr, range_header).await
-}
-
-async fn stream_file(
-    path: &Path,
-    remote_addr: std::net::SocketAddr,
-    range_header: Option<&str>,
-) -> Result<Response<Body>, Response<Body>> {
-    let mut file = match File::open(path).await {
-        Ok(f) => f,
         Err(err) => {
             error!(
-                "File open error | path: {:?} | error: {} | status: {} | remote: {}",
+                "Thumbnail generation failed | path: {:?} | error: {} | status: {} | remote: {}",
                 path,
                 err,
-                StatusCode::NOT_FOUND,
+                StatusCode::INTERNAL_SERVER_ERROR,
                 remote_addr
             );
-            return Err(Response::builder()
-                .status(StatusCode::NOT_FOUND)
-                .body(Body::from("File not found"))
-                .unwrap());
+            Err(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Error generating thumbnail"))
+                .unwrap())
         }
-    };
+    }
+}
 
-    let metadata = match tokio::fs::metadata(path).await {
+/// Streams a file, honouring `Range`, `If-Range` and `If-Modified-Since`. With `--precompressed` or
+/// `--compress`, full responses are sent from a precompressed sibling or
+/// compressed on the fly for clients that accept it; ranges are always
+/// served from the uncompressed file.
+async fn stream_file(
+    path: &Path,
+    headers: &header::HeaderMap,
+    remote_addr: std::net::SocketAddr,
+    head_only: bool,
+    config: &Config,
+) -> Result<Response<Body>, Response<Body>> {
+    let header_str = |name| headers.get(name).and_then(|h| h.to_str().ok());
+    let range_header = header_str(header::RANGE);
+    let accept_encoding = header_str(header::ACCEPT_ENCODING);
+    // A precompressed sibling (`app.js.br`) stands in for the file when the
+    // client accepts its coding; ranges always come from the file itself.
+    let variant = if config.precompressed && range_header.is_none() {
+        precompressed_variant(path, accept_encoding, config).await
+    } else {
+        None
+    };
+    let (source, stored_encoding) = match &variant {
+        Some((variant, encoding)) => (variant.as_path(), *encoding),
+        None => (path, Encoding::Identity),
+    };
+    let metadata = match config.storage.metadata(source).await {
         Ok(m) => m,
         Err(err) => {
             error!(
@@ -203,127 +1230,750 @@ async fn stream_file(
                 .unwrap());
         }
     };
-    let file_size = metadata.len();
-    let mime = from_path(path).first_or_octet_stream();
-    if let Some(range_header) = range_header {
-        if let Some((start, end)) = parse_range_header(range_header, file_size) {
-            if start >= file_size || end >= file_size || start > end {
+    let file_size = metadata.len;
+    // A precompressed sibling is dated like the file it stands in for.
+    let modified = match &variant {
+        Some(_) => config
+            .storage
+            .metadata(path)
+            .await
+            .ok()
+            .and_then(|m| m.modified),
+        None => metadata.modified,
+    };
+    let vary = config.compress || config.precompressed;
+    let cache_control = pathfilter::relative_path(path, &config.root)
+        .and_then(|relative| cache::cache_control(&config.cache_rules, &relative));
+    let mut mime = from_path(path).first_or_octet_stream().to_string();
+    // Bytes read for content sniffing; replayed in front of the file stream.
+    // Never more than `file_size`, which the rest is read against, even if
+    // the file has grown since.
+    let mut sniffed = Vec::new();
+    if config.mime_sniff
+        && stored_encoding == Encoding::Identity
+        && mime == mime_guess::mime::APPLICATION_OCTET_STREAM.as_ref()
+    {
+        let head = match config
+            .storage
+            .read_range(source, 0, file_size.min(SNIFF_LEN))
+            .await
+        {
+            Ok(head) => head,
+            Err(err) => return Err(open_failed(path, err, remote_addr)),
+        };
+        sniffed = match storage::collect(head).await {
+            Ok(sniffed) => sniffed,
+            Err(err) => {
                 error!(
-                    "Invalid range | range: {} | file_size: {} | status: {} | remote: {}",
-                    range_header,
-                    file_size,
-                    StatusCode::RANGE_NOT_SATISFIABLE,
+                    "Read failed while sniffing | path: {:?} | error: {} | status: {} | remote: {}",
+                    path,
+                    err,
+                    StatusCode::INTERNAL_SERVER_ERROR,
                     remote_addr
                 );
                 return Err(Response::builder()
-                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
-                    .header(header::CONTENT_RANGE, format!("bytes */{}", file_size))
-                    .body(Body::empty())
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::from("Read error"))
                     .unwrap());
             }
+        };
+        if let Some(kind) = infer::get(&sniffed) {
+            mime = kind.mime_type().to_string();
+        }
+    }
+    // Headers shared by full and partial responses.
+    let mut builder = Response::builder()
+        .header(header::CONTENT_TYPE, mime.as_str())
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(modified) = modified {
+        builder = builder.header(header::LAST_MODIFIED, timefmt::format_http_date(modified));
+    }
+    if let Some(cache_control) = cache_control {
+        builder = builder.header(header::CACHE_CONTROL, cache_control);
+    }
+    let compressible = config.compress
+        && file_size >= config.compress_min_size
+        && encoding::is_compressible(&mime)
+        && !encoding::has_no_compress_ext(path, &config.no_compress_ext);
+    let encoding = if stored_encoding != Encoding::Identity {
+        stored_encoding
+    } else if compressible && range_header.is_none() {
+        encoding::negotiate(
+            accept_encoding,
+            &[
+                Encoding::Zstd,
+                Encoding::Brotli,
+                Encoding::Gzip,
+                Encoding::Identity,
+            ],
+        )
+        .unwrap_or(Encoding::Identity)
+    } else {
+        Encoding::Identity
+    };
+    if vary {
+        builder = builder.header(header::VARY, "Accept-Encoding");
+    }
+    // The tag stands for the bytes as stored, so a body compressed here
+    // only gets a weak one.
+    let etag = modified.map(|modified| {
+        let tag = entity_tag(modified, file_size);
+        if encoding == stored_encoding {
+            tag
+        } else {
+            format!("W/{}", tag)
+        }
+    });
+    if let Some(etag) = &etag {
+        builder = builder.header(header::ETAG, etag.as_str());
+    }
+    // `If-None-Match` takes precedence over `If-Modified-Since`.
+    let not_modified = match header_str(header::IF_NONE_MATCH) {
+        Some(tags) => none_match_fails(tags, etag.as_deref()),
+        None => modified
+            .zip(header_str(header::IF_MODIFIED_SINCE).and_then(timefmt::parse_http_date))
+            .is_some_and(|(modified, since)| !modified_since(modified, since)),
+    };
+    if not_modified {
+        info!(
+            "Not modified | path: {:?} | status: {} | remote: {}",
+            path,
+            StatusCode::NOT_MODIFIED,
+            remote_addr
+        );
+        let mut builder = Response::builder().status(StatusCode::NOT_MODIFIED);
+        if let Some(modified) = modified {
+            builder = builder.header(header::LAST_MODIFIED, timefmt::format_http_date(modified));
+        }
+        if let Some(etag) = &etag {
+            builder = builder.header(header::ETAG, etag.as_str());
+        }
+        if vary {
+            builder = builder.header(header::VARY, "Accept-Encoding");
+        }
+        if let Some(cache_control) = cache_control {
+            builder = builder.header(header::CACHE_CONTROL, cache_control);
+        }
+        return Ok(builder.body(Body::empty()).unwrap());
+    }
+    // The digest covers the file as stored, so it is left off compressed
+    // responses.
+    if config.digest
+        && encoding == Encoding::Identity
+        && let Some(digest) = digest::file_digest(&config.storage, path, &metadata).await
+    {
+        builder = builder.header("digest", digest);
+    }
+    // A stale `If-Range` validator means the client's partial copy is of an
+    // older version, so it gets the whole file instead.
+    let range_header = range_header.filter(|_| match header_str(header::IF_RANGE) {
+        Some(validator) => if_range_matches(validator, modified, etag.as_deref()),
+        None => true,
+    });
+    if let Some(range_header) = range_header
+        && let Some(ranges) = parse_range_header(range_header, file_size)
+    {
+        let ranges = merge_ranges(
+            ranges
+                .into_iter()
+                .filter(|&(start, end)| start <= end && end < file_size)
+                .collect(),
+        );
+        if ranges.is_empty() {
+            error!(
+                "Invalid range | range: {} | file_size: {} | status: {} | remote: {}",
+                range_header,
+                file_size,
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                remote_addr
+            );
+            return Err(Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", file_size))
+                .header(header::ACCEPT_RANGES, "bytes")
+                .body(Body::empty())
+                .unwrap());
+        }
+        if ranges.len() > 1 {
+            return Ok(multipart_ranges(
+                config.storage.clone(),
+                path,
+                &ranges,
+                file_size,
+                &mime,
+                builder,
+                head_only,
+                remote_addr,
+            ));
+        }
+
+        let (start, end) = ranges[0];
+        let chunk_size = end - start + 1;
+        // A HEAD probe gets the same 206 headers without touching the file contents.
+        let body = if head_only {
+            Body::empty()
+        } else {
+            match config.storage.read_range(path, start, chunk_size).await {
+                Ok(chunk) => Body::wrap_stream(chunk),
+                Err(err) => return Err(open_failed(path, err, remote_addr)),
+            }
+        };
+        info!(
+            "Partial content | {:?} | range: {}-{} | status: {} | remote: {}",
+            path,
+            start,
+            end,
+            StatusCode::PARTIAL_CONTENT,
+            remote_addr
+        );
+        return Ok(builder
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, file_size),
+            )
+            .header(header::CONTENT_LENGTH, chunk_size.to_string())
+            .body(body)
+            .unwrap());
+    }
 
-            if let Err(err) = file.seek(SeekFrom::Start(start)).await {
+    let body = if head_only {
+        Body::empty()
+    } else {
+        let offset = sniffed.len() as u64;
+        let file = if offset == 0 {
+            config.storage.open(source).await
+        } else {
+            config
+                .storage
+                .read_range(source, offset, file_size - offset)
+                .await
+        };
+        let file = file.map_err(|err| open_failed(path, err, remote_addr))?;
+        let prefix = stream::iter(Some(Ok::<_, std::io::Error>(Bytes::from(sniffed))));
+        let stream = prefix.chain(file);
+        // Downloads are counted on the file's bytes, before compression.
+        let stream = if !config.download_stats {
+            stream.boxed()
+        } else if file_size == 0 {
+            stats::record_download(path);
+            stream.boxed()
+        } else {
+            stats::count_on_completion(stream, path.to_path_buf(), file_size).boxed()
+        };
+        let on_the_fly = if stored_encoding == Encoding::Identity {
+            encoding
+        } else {
+            Encoding::Identity
+        };
+        match encoding::compress_stream(stream, on_the_fly) {
+            Ok(stream) => Body::wrap_stream(stream),
+            Err(err) => {
                 error!(
-                    "Seek failed | path: {:?} | error: {} | status: {} | remote: {}",
+                    "Compression failed | path: {:?} | encoding: {} | error: {} | status: {} | remote: {}",
                     path,
+                    encoding.name(),
                     err,
                     StatusCode::INTERNAL_SERVER_ERROR,
                     remote_addr
                 );
                 return Err(Response::builder()
                     .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(Body::from("Seek error"))
+                    .body(Body::from("Compression error"))
                     .unwrap());
             }
-            let chunk_size = end - start + 1;
-            let stream = ReaderStream::new(file.take(chunk_size));
-            let body = Body::wrap_stream(stream);
-            info!(
-                "Partial content | {:?} | range: {}-{} | status: {} | remote: {}",
-                path,
-                start,
-                end,
-                StatusCode::PARTIAL_CONTENT,
-                remote_addr
-            );
-            return Ok(Response::builder()
-                .status(StatusCode::PARTIAL_CONTENT)
-                .header(header::CONTENT_TYPE, mime.to_string())
-                .header(
-                    header::CONTENT_RANGE,
-                    format!("bytes {}-{}/{}", start, end, file_size),
-                )
-                .header(header::ACCEPT_RANGES, "bytes")
-                .header(header::CONTENT_LENGTH, chunk_size.to_string())
-                .body(body)
-                .unwrap());
         }
-    }
-
-    let stream = ReaderStream::new(file);
-    let body = Body::wrap_stream(stream);
+    };
 
+    if stored_encoding != Encoding::Identity {
+        info!(
+            "Precompressed content | path: {:?} | encoding: {} | status: {} | remote: {}",
+            source,
+            encoding.name(),
+            StatusCode::OK,
+            remote_addr
+        );
+        return Ok(builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_ENCODING, encoding.name())
+            .header(header::CONTENT_LENGTH, file_size.to_string())
+            .body(body)
+            .unwrap());
+    }
+    if encoding != Encoding::Identity {
+        info!(
+            "Compressed content | path: {:?} | encoding: {} | status: {} | remote: {}",
+            path,
+            encoding.name(),
+            StatusCode::OK,
+            remote_addr
+        );
+        return Ok(builder
+            .status(StatusCode::OK)
+            .header(header::CONTENT_ENCODING, encoding.name())
+            .body(body)
+            .unwrap());
+    }
     info!(
         "Full content | path: {:?} | status: {} | remote: {}",
         path,
         StatusCode::OK,
         remote_addr
     );
-    Ok(Response::builder()
+    Ok(builder
         .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, mime.to_string())
         .header(header::CONTENT_LENGTH, file_size.to_string())
-        .header(header::ACCEPT_RANGES, "bytes")
         .body(body)
         .unwrap())
 }
 
+/// The response when a file that was just found cannot be read.
+fn open_failed(
+    path: &Path,
+    err: std::io::Error,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    error!(
+        "File open error | path: {:?} | error: {} | status: {} | remote: {}",
+        path,
+        err,
+        StatusCode::NOT_FOUND,
+        remote_addr
+    );
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("File not found"))
+        .unwrap()
+}
+
+/// Whether a file last modified at `modified` changed after `since`.
+fn modified_since(modified: SystemTime, since: SystemTime) -> bool {
+    unix_seconds(modified) > unix_seconds(since)
+}
+
+/// HTTP dates have whole-second precision, so files are compared on whole
+/// seconds too.
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+/// The entity tag of a file, made of its modification time and size.
+fn entity_tag(modified: SystemTime, len: u64) -> String {
+    format!("\"{:x}-{:x}\"", unix_seconds(modified), len)
+}
+
+/// Whether an `If-None-Match` list names the response's tag, so the
+/// client's copy is current. Tags are compared weakly, ignoring `W/`.
+fn none_match_fails(tags: &str, etag: Option<&str>) -> bool {
+    let opaque = |tag: &str| tag.strip_prefix("W/").unwrap_or(tag).to_string();
+    tags.trim() == "*"
+        || etag.is_some_and(|etag| {
+            tags.split(',')
+                .any(|tag| opaque(tag.trim()) == opaque(etag))
+        })
+}
+
+/// Whether an `If-Range` validator still matches the file. A date must
+/// equal `Last-Modified`; an entity tag must equal the `ETag` and be
+/// strong, so weak tags never match.
+fn if_range_matches(validator: &str, modified: Option<SystemTime>, etag: Option<&str>) -> bool {
+    if validator.starts_with('"') || validator.starts_with("W/") {
+        return !validator.starts_with("W/") && etag == Some(validator);
+    }
+    match (timefmt::parse_http_date(validator), modified) {
+        (Some(date), Some(modified)) => unix_seconds(date) == unix_seconds(modified),
+        _ => false,
+    }
+}
+
+/// Finds a sibling of `path` precompressed as `.br`, `.zst` or `.gz` in a
+/// coding the client accepts. Ties go to the smallest format.
+async fn precompressed_variant(
+    path: &Path,
+    accept_encoding: Option<&str>,
+    config: &Config,
+) -> Option<(PathBuf, Encoding)> {
+    accept_encoding?;
+    let mut available = Vec::new();
+    for (encoding, extension) in [
+        (Encoding::Brotli, "br"),
+        (Encoding::Zstd, "zst"),
+        (Encoding::Gzip, "gz"),
+    ] {
+        let mut variant = path.as_os_str().to_owned();
+        variant.push(".");
+        variant.push(extension);
+        let variant = PathBuf::from(variant);
+        if config
+            .storage
+            .metadata(&variant)
+            .await
+            .is_ok_and(|m| !m.is_dir)
+        {
+            available.push((variant, encoding));
+        }
+    }
+    if available.is_empty() {
+        return None;
+    }
+    let mut supported = available.iter().map(|&(_, e)| e).collect::<Vec<_>>();
+    supported.push(Encoding::Identity);
+    let chosen = encoding::negotiate(accept_encoding, &supported)?;
+    available.into_iter().find(|&(_, e)| e == chosen)
+}
+
+/// Returns the method requested through the `X-HTTP-Method-Override` header,
+/// or the `_method` query parameter when the header is absent.
+fn method_override(req: &Request<Body>) -> Option<String> {
+    req.headers()
+        .get("x-http-method-override")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .or_else(|| query_param(req.uri().query(), "_method"))
+        .filter(|m| !m.is_empty())
+}
+
+/// Output format of a generated directory listing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListingFormat {
+    Html,
+    /// One name per line, directories with a trailing slash.
+    Text,
+}
+
+impl ListingFormat {
+    /// Picks the format from `?format=text` or an `Accept: text/plain` header,
+    /// falling back to HTML for browsers and everything else.
+    fn from_request(req: &Request<Body>) -> Self {
+        if let Some(format) = query_param(req.uri().query(), "format") {
+            return match format.as_str() {
+                "text" | "txt" | "plain" => ListingFormat::Text,
+                _ => ListingFormat::Html,
+            };
+        }
+        let accept = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        if accept.contains("text/plain") && !accept.contains("text/html") {
+            ListingFormat::Text
+        } else {
+            ListingFormat::Html
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            ListingFormat::Html => "text/html",
+            ListingFormat::Text => "text/plain; charset=utf-8",
+        }
+    }
+}
+
+/// Column a directory listing is ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortKey {
+    const ALL: [SortKey; 3] = [SortKey::Name, SortKey::Size, SortKey::Modified];
+
+    fn as_str(self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "modified",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            SortKey::Name => "Name",
+            SortKey::Size => "Size",
+            SortKey::Modified => "Modified",
+        }
+    }
+}
+
+/// Listing order from `?sort=name|size|modified&order=asc|desc`. Sorting
+/// happens on the server so the header links work without JavaScript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ListingSort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl ListingSort {
+    fn from_query(query: Option<&str>) -> Self {
+        let key = match query_param(query, "sort").as_deref() {
+            Some("size") => SortKey::Size,
+            Some("modified" | "mtime" | "date") => SortKey::Modified,
+            _ => SortKey::Name,
+        };
+        let descending = query_param(query, "order").as_deref() == Some("desc");
+        ListingSort { key, descending }
+    }
+
+    /// Orders directories before files, then by the selected column.
+    fn apply(self, entries: &mut [ListingEntry]) {
+        entries.sort_by(|a, b| {
+            let ordering = match self.key {
+                SortKey::Name => a.name.cmp(&b.name),
+                SortKey::Size => a.size().cmp(&b.size()),
+                SortKey::Modified => a.modified().cmp(&b.modified()),
+            }
+            .then_with(|| a.name.cmp(&b.name));
+            let ordering = if self.descending {
+                ordering.reverse()
+            } else {
+                ordering
+            };
+            b.is_dir().cmp(&a.is_dir()).then(ordering)
+        });
+    }
+
+    /// Query string a column header links to: the active column toggles its
+    /// direction, other columns start ascending.
+    fn link_for(self, key: SortKey) -> String {
+        let order = if key == self.key && !self.descending {
+            "desc"
+        } else {
+            "asc"
+        };
+        format!("?sort={}&amp;order={}", key.as_str(), order)
+    }
+}
+
+/// Returns the percent-decoded value of `key` from a raw query string.
+fn query_param(query: Option<&str>, key: &str) -> Option<String> {
+    query?.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        if k != key {
+            return None;
+        }
+        let v = v.replace('+', " ");
+        Some(percent_decode_str(&v).decode_utf8_lossy().into_owned())
+    })
+}
+
+/// A directory entry as shown in a listing.
+type ListingEntry = storage::DirEntry;
+
+impl ListingEntry {
+    fn is_dir(&self) -> bool {
+        self.metadata.as_ref().is_some_and(|m| m.is_dir)
+    }
+
+    fn size(&self) -> u64 {
+        self.metadata.as_ref().map_or(0, |m| m.len)
+    }
+
+    fn modified(&self) -> Option<std::time::SystemTime> {
+        self.metadata.as_ref().and_then(|m| m.modified)
+    }
+
+    /// The name as text listings show it, with `/` after directories.
+    fn label(&self) -> String {
+        let mut label = self.name.to_string_lossy().into_owned();
+        if self.is_dir() {
+            label.push('/');
+        }
+        label
+    }
+}
+
+/// How long a listing waits in total for `--dir-sizes` walks to finish.
+const DIR_SIZE_WAIT: std::time::Duration = std::time::Duration::from_millis(250);
+
 pub async fn render_directory_listing(
     path: &Path,
     request_path: &str,
+    format: ListingFormat,
+    sort: ListingSort,
+    config: &Config,
 ) -> Result<String, std::io::Error> {
-    let mut entries = read_dir(path).await?;
+    let mut entries = config.storage.list(path).await?;
+    let dir = percent_decode_str(request_path).decode_utf8_lossy();
+    entries.retain(|entry| {
+        let entry_path = format!(
+            "{}/{}",
+            dir.trim_end_matches('/'),
+            entry.name.to_string_lossy()
+        );
+        !pathfilter::is_hidden(&entry_path, config)
+    });
+    if config.follow_symlinks != FollowSymlinks::Always {
+        let mut allowed = Vec::with_capacity(entries.len());
+        for entry in entries {
+            if symlinks::allowed(
+                &path.join(&entry.name),
+                &config.root,
+                config.follow_symlinks,
+            )
+            .await
+            {
+                allowed.push(entry);
+            }
+        }
+        entries = allowed;
+    }
+    sort.apply(&mut entries);
+    if !config.plugins.is_empty() {
+        let labels = entries.iter().map(ListingEntry::label).collect();
+        let shown = plugin::on_listing(&config.plugins, &dir, labels);
+        let mut remaining: Vec<Option<ListingEntry>> = entries.into_iter().map(Some).collect();
+        entries = shown
+            .iter()
+            .filter_map(|label| {
+                remaining
+                    .iter_mut()
+                    .find(|entry| entry.as_ref().is_some_and(|e| e.label() == *label))?
+                    .take()
+            })
+            .collect();
+    }
+    if format == ListingFormat::Text {
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&entry.label());
+            lines.push('\n');
+        }
+        return Ok(lines);
+    }
+    // Directory sizes not computed by then are shown on a later request.
+    let deadline = tokio::time::Instant::now() + DIR_SIZE_WAIT;
     let mut list_items = Vec::new();
-    while let Some(entry) = entries.next_entry().await? {
-        let name = entry.file_name();
+    for entry in &entries {
+        let name = &entry.name;
         let name_str = name.to_string_lossy();
         let encoded_name = encode_text(&name_str);
+        let is_dir = entry.is_dir();
 
-        let metadata = entry.metadata().await?;
-        let is_dir = metadata.is_dir();
-
-        let icon = if is_dir { "📁" } else { "📄" };
         let href = if is_dir {
             format!("{}/", encoded_name)
         } else {
             encoded_name.to_string()
         };
+        let icon = if entry.metadata.is_none() {
+            "⚠️".to_string()
+        } else if is_dir {
+            "📁".to_string()
+        } else if config.thumbnails && thumbnail::is_image(Path::new(name)) {
+            let src = format!(
+                "{}?thumb={}",
+                utf8_percent_encode(&name_str, URL_PATH),
+                thumbnail::LISTING_SIZE
+            );
+            format!(
+                r#"<img class="thumb" src="{}" alt="" loading="lazy">"#,
+                encode_double_quoted_attribute(&src)
+            )
+        } else {
+            "📄".to_string()
+        };
 
-        let item = format!(
-            r#"<li><span class="icon">{}</span><a href="{}">{}</a></li>"#,
-            icon, href, encoded_name
-        );
+        let downloads = if config.download_stats && !is_dir && entry.metadata.is_some() {
+            let count = stats::download_count(&path.join(name));
+            format!(
+                r#" <span class="downloads">{} download{}</span>"#,
+                count,
+                if count == 1 { "" } else { "s" }
+            )
+        } else {
+            String::new()
+        };
+        let meta = match &entry.metadata {
+            Some(metadata) => {
+                let size = if !is_dir {
+                    format_size(metadata.len)
+                } else if config.dir_sizes
+                    && let Some(modified) = metadata.modified
+                    && let Some(size) = dirsize::dir_size(
+                        &config.storage,
+                        &path.join(name),
+                        modified,
+                        config.max_depth,
+                        deadline,
+                    )
+                    .await
+                {
+                    format_size(size)
+                } else {
+                    "-".to_string()
+                };
+                let modified = metadata.modified.map_or_else(
+                    || "-".to_string(),
+                    |time| timefmt::format_time(time, &config.date_format, config.timezone),
+                );
+                format!(
+                    r#" <span class="meta">{} · {}</span>"#,
+                    size,
+                    encode_text(&modified)
+                )
+            }
+            None => String::new(),
+        };
+        let item = if entry.metadata.is_none() {
+            format!(
+                r#"<li class="inaccessible"><span class="icon">{}</span>{} <em>(inaccessible)</em></li>"#,
+                icon, encoded_name
+            )
+        } else {
+            format!(
+                r#"<li><span class="icon">{}</span><a href="{}">{}</a>{}{}</li>"#,
+                icon, href, encoded_name, meta, downloads
+            )
+        };
         list_items.push(item);
     }
 
     // Upload form as last list item
-    list_items.push(
+    let field_name = if config.upload_field == "*" {
+        "file"
+    } else {
+        config.upload_field.as_str()
+    };
+    list_items.push(format!(
         r#"
     <li>
         <form class="upload" action="." method="POST" enctype="multipart/form-data">
             <label style="display: block; margin-bottom: 0.3rem;">
                 <span class="icon">📤</span> Upload a file:
             </label>
-            <input type="file" name="file" required style="margin-bottom: 0.5rem;">
+            <input type="file" name="{}" required style="margin-bottom: 0.5rem;">
             <input type="submit" value="Upload">
         </form>
     </li>
-    "#
-        .to_string(),
-    );
+    "#,
+        encode_double_quoted_attribute(field_name)
+    ));
 
     let entries_html = list_items.join("\n");
 
+    let sort_links = SortKey::ALL
+        .iter()
+        .map(|&key| {
+            let arrow = match (key == sort.key, sort.descending) {
+                (false, _) => "",
+                (true, false) => " ▲",
+                (true, true) => " ▼",
+            };
+            format!(
+                r#"<a href="{}">{}{}</a>"#,
+                sort.link_for(key),
+                key.label(),
+                arrow
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" · ");
+
+    let title = format!("{}{}", config.base_path, request_path);
     let html = format!(
         r#"<!DOCTYPE html>
 <html lang="en">
@@ -355,10 +2005,36 @@ pub async fn render_directory_listing(
         li {{
             margin: 0.25rem 0;
         }}
+        nav.sort {{
+            font-size: 0.9em;
+            margin-bottom: 0.5rem;
+        }}
+        li.inaccessible {{
+            color: #999;
+        }}
+        .meta {{
+            color: #888;
+            font-size: 0.85em;
+            margin-left: 0.5rem;
+        }}
+        .downloads {{
+            color: #888;
+            font-size: 0.85em;
+            margin-left: 0.5rem;
+        }}
         .icon {{
             display: inline-block;
             width: 1.5em;
         }}
+        .icon img.thumb {{
+            max-width: 64px;
+            max-height: 64px;
+            vertical-align: middle;
+            margin-right: 0.5rem;
+        }}
+        .icon:has(img.thumb) {{
+            width: auto;
+        }}
         form.upload {{
             display: flex;
             flex-direction: column;
@@ -388,85 +2064,647 @@ pub async fn render_directory_listing(
 </head>
 <body>
     <h1>Index of {}</h1>
+    <nav class="sort">Sort by: {}</nav>
     <ul>
         {}
     </ul>
 </body>
 </html>"#,
-        encode_text(request_path),
-        encode_text(request_path),
+        encode_text(&title),
+        encode_text(&title),
+        sort_links,
         entries_html
     );
 
-    Ok(html)
+    Ok(html)
+}
+
+/// Human-readable size with binary units, e.g. `1.5 KiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// The user `req` authenticates as with Basic Auth, against `--auth` or
+/// an `--auth-file` account.
+async fn check_basic_auth(
+    req: &Request<Body>,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Option<String> {
+    let Some(auth_header) = req.headers().get(header::AUTHORIZATION) else {
+        warn!(
+            "Missing Authorization header | method: {:?} | uri: {:?} | status: {} | remote: {:?}",
+            req.method(),
+            req.uri(),
+            StatusCode::UNAUTHORIZED,
+            remote_addr
+        );
+        return None;
+    };
+
+    // Neither the header nor the password is logged: both hold secrets.
+    let Some((_, encoded)) = auth_header
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("Basic"))
+    else {
+        warn!(
+            "Invalid auth scheme | got: {:?} | method: {} | status: {} | uri: {} | remote: {}",
+            auth_header
+                .to_str()
+                .ok()
+                .and_then(|v| v.split_whitespace().next())
+                .unwrap_or(""),
+            req.method(),
+            StatusCode::UNAUTHORIZED,
+            req.uri(),
+            remote_addr
+        );
+        return None;
+    };
+    let credentials = general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+    let Some((user, password)) = credentials.as_deref().and_then(|c| c.split_once(':')) else {
+        warn!(
+            "Malformed Basic Auth credentials | method: {} | uri: {} | status: {} | remote: {}",
+            req.method(),
+            req.uri(),
+            StatusCode::UNAUTHORIZED,
+            remote_addr
+        );
+        return None;
+    };
+    let valid = match &config.write_users {
+        Some(writers) if !is_read(req.method()) => writers.verify_async(user, password).await,
+        _ => credentials_valid(config, user, password).await,
+    };
+    if valid {
+        Some(user.to_string())
+    } else {
+        warn!(
+            "Auth failed | user: {:?} | method: {} | uri: {} | status: {} | remote: {}",
+            user,
+            req.method(),
+            req.uri(),
+            StatusCode::UNAUTHORIZED,
+            remote_addr
+        );
+        None
+    }
+}
+
+/// Whether `password` is that of `user` under `--auth` or `--auth-file`.
+/// Both are checked, so the time taken does not tell which one matched.
+async fn credentials_valid(config: &Config, user: &str, password: &str) -> bool {
+    let encoded = general_purpose::STANDARD.encode(format!("{}:{}", user, password));
+    let single = config
+        .auth
+        .as_ref()
+        .is_some_and(|auth| users::secrets_match(auth, &encoded));
+    let account = match &config.users {
+        Some(users) => users.verify_async(user, password).await,
+        None => false,
+    };
+    single | account
+}
+
+/// Whether requests may carry credentials for this server to check.
+fn checks_credentials(config: &Config) -> bool {
+    config.auth.is_some()
+        || config.users.is_some()
+        || !config.tokens.is_empty()
+        || config.write_users.is_some()
+}
+
+/// Whether `name` is still an account, so a session does not outlive a
+/// user removed from the configuration.
+fn account_exists(config: &Config, name: &str) -> bool {
+    let single = config
+        .auth
+        .as_ref()
+        .and_then(|auth| general_purpose::STANDARD.decode(auth).ok())
+        .and_then(|decoded| String::from_utf8(decoded).ok())
+        .is_some_and(|decoded| {
+            decoded
+                .split_once(':')
+                .is_some_and(|(user, _)| user == name)
+        });
+    single
+        || config
+            .users
+            .as_ref()
+            .is_some_and(|users| users.contains(name))
+}
+
+/// Whether `req` needs authentication. Without `--protect-writes` or
+/// `--write-auth-file` that is when it touches a `--protect`ed path: its
+/// own, or the destination it moves or copies to.
+fn requires_auth(req: &Request<Body>, config: &Config) -> bool {
+    let read = is_read(req.method());
+    if !read && (config.protect_writes || config.write_users.is_some()) {
+        return true;
+    }
+    let readers = config.auth.is_some() || config.users.is_some() || !config.tokens.is_empty();
+    if !readers || (read && config.protect_writes && config.protect.is_empty()) {
+        return false;
+    }
+    let protected = |path: &str| {
+        percent_decode_str(path)
+            .decode_utf8()
+            .map_or(true, |path| pathfilter::is_protected(&path, config))
+    };
+    if config.protect.is_empty() || protected(req.uri().path()) {
+        return true;
+    }
+    let destination = req
+        .headers()
+        .get("destination")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<hyper::Uri>().ok());
+    if let Some(uri) = destination {
+        let path = strip_base_path(uri.path(), &config.base_path).unwrap_or(uri.path());
+        if protected(path) {
+            return true;
+        }
+    }
+    query_param(req.uri().query(), "to").is_some_and(|to| pathfilter::is_protected(&to, config))
+}
+
+/// Counts a failed login from `remote_addr` towards `--auth-max-failures`.
+fn count_auth_failure(config: &Config, remote_addr: std::net::SocketAddr) {
+    let lockout = &config.lockout;
+    let failures = lockout.record_failure(remote_addr.ip());
+    if failures == 0 {
+        return;
+    }
+    warn!(
+        "Auth failure counted | failures: {}/{} | remote: {}",
+        failures,
+        lockout.max_failures(),
+        remote_addr
+    );
+    if failures == lockout.max_failures() {
+        warn!(
+            "Client locked out | failures: {} | ban: {}s | remote: {}",
+            failures,
+            lockout.ban_time().as_secs(),
+            remote_addr
+        );
+    }
+}
+
+/// Refuses a request over `--rate-limit`; a token is due after `wait`.
+fn rate_limited(
+    req: &Request<Body>,
+    wait: std::time::Duration,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    warn!(
+        "Rate limited | method: {} | uri: {} | retry after: {}s | status: {} | remote: {}",
+        req.method(),
+        req.uri(),
+        retry_after,
+        StatusCode::TOO_MANY_REQUESTS,
+        remote_addr
+    );
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, retry_after)
+        .body(Body::from("Too many requests, slow down"))
+        .unwrap()
 }
 
-fn check_basic_auth(
+/// Refuses a client banned by `--auth-max-failures` for `left` more.
+fn locked_out(
     req: &Request<Body>,
-    base64_auth: &String,
+    left: std::time::Duration,
     remote_addr: std::net::SocketAddr,
-) -> Result<(), Response<Body>> {
-    let Some(auth_header) = req.headers().get(header::AUTHORIZATION) else {
-        warn!(
-            " Missing Authorization header | method: {:?} | uri: {:?} | status: {} | remote: {:?}",
-            req.method(),
-            req.uri(),
-            StatusCode::UNAUTHORIZED,
-            remote_addr
-        );
-        return Err(unauthorized_response());
+) -> Response<Body> {
+    // Rounded up, so a client that waits as told is let in.
+    let retry_after = left.as_secs() + u64::from(left.subsec_nanos() > 0);
+    warn!(
+        "Request from locked out client | uri: {} | retry after: {}s | status: {} | remote: {}",
+        req.uri(),
+        retry_after,
+        StatusCode::TOO_MANY_REQUESTS,
+        remote_addr
+    );
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(header::RETRY_AFTER, retry_after)
+        .body(Body::from("Too many failed logins, try again later"))
+        .unwrap()
+}
+
+/// Whether `method` only reads, so `--protect-writes` lets it through.
+fn is_read(method: &Method) -> bool {
+    matches!(method.as_str(), "GET" | "HEAD" | "OPTIONS" | "PROPFIND")
+}
+
+/// Whether an unauthenticated request comes from a browser that should be
+/// sent to the login form rather than asked for Basic Auth.
+fn wants_login_page(req: &Request<Body>) -> bool {
+    (req.method() == Method::GET || req.method() == Method::HEAD)
+        && req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Sends a browser to the login form, to come back to `req` afterwards.
+fn login_redirect(
+    req: &Request<Body>,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    let next = req
+        .uri()
+        .path_and_query()
+        .map_or(req.uri().path(), |p| p.as_str());
+    let location = format!(
+        "{}{}?next={}",
+        config.base_path,
+        session::LOGIN_PATH,
+        utf8_percent_encode(next, FORM_VALUE)
+    );
+    info!(
+        "Login required | uri: {} | status: {} | remote: {}",
+        req.uri(),
+        StatusCode::SEE_OTHER,
+        remote_addr
+    );
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(header::LOCATION, location)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// `--login-page`: shows the login form, and on a POST of it checks the
+/// credentials and starts a session.
+async fn handle_login(
+    req: Request<Body>,
+    sessions: &Sessions,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+    user: &mut Option<String>,
+) -> Response<Body> {
+    const MAX_FORM_LEN: usize = 8 * 1024;
+    let action = format!("{}{}", config.base_path, session::LOGIN_PATH);
+    let page = |status: StatusCode, next: &str, failed: bool| {
+        Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Body::from(session::login_page(
+                &config.auth_realm,
+                &action,
+                next,
+                failed,
+            )))
+            .unwrap()
     };
+    match *req.method() {
+        Method::GET | Method::HEAD => {
+            let next = query_param(req.uri().query(), "next");
+            page(StatusCode::OK, session::return_path(next.as_deref()), false)
+        }
+        Method::POST => {
+            if let Some(left) = config.lockout.banned(remote_addr.ip()) {
+                return locked_out(&req, left, remote_addr);
+            }
+            let mut body = req.into_body();
+            let mut form = Vec::new();
+            while let Some(chunk) = body.data().await {
+                match chunk {
+                    Ok(chunk) if form.len() + chunk.len() <= MAX_FORM_LEN => {
+                        form.extend_from_slice(&chunk)
+                    }
+                    _ => {
+                        return Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from("Invalid login form"))
+                            .unwrap();
+                    }
+                }
+            }
+            let form = String::from_utf8_lossy(&form);
+            let field = |name| query_param(Some(&form), name).unwrap_or_default();
+            let (name, password, next) = (field("user"), field("password"), field("next"));
+            let next = session::return_path(Some(&next));
+            if !credentials_valid(config, &name, &password).await {
+                METRICS.record_auth_failure();
+                warn!(
+                    "Login failed | user: {:?} | status: {} | remote: {}",
+                    name,
+                    StatusCode::UNAUTHORIZED,
+                    remote_addr
+                );
+                count_auth_failure(config, remote_addr);
+                return page(StatusCode::UNAUTHORIZED, next, true);
+            }
+            config.lockout.record_success(remote_addr.ip());
+            info!(
+                "Logged in | user: {:?} | status: {} | remote: {}",
+                name,
+                StatusCode::SEE_OTHER,
+                remote_addr
+            );
+            let cookie = sessions.start(&name);
+            *user = Some(name);
+            let location = header::HeaderValue::from_str(&format!("{}{}", config.base_path, next))
+                .unwrap_or_else(|_| {
+                    header::HeaderValue::from_str(&format!("{}/", config.base_path))
+                        .unwrap_or(header::HeaderValue::from_static("/"))
+                });
+            Response::builder()
+                .status(StatusCode::SEE_OTHER)
+                .header(header::LOCATION, location)
+                .header(header::SET_COOKIE, cookie)
+                .body(Body::empty())
+                .unwrap()
+        }
+        _ => Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(header::ALLOW, "GET, HEAD, POST")
+            .body(Body::from("Method not allowed"))
+            .unwrap(),
+    }
+}
+
+/// `--login-page`: ends the session and returns to the login form.
+fn handle_logout(
+    sessions: &Sessions,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    info!(
+        "Logged out | status: {} | remote: {}",
+        StatusCode::SEE_OTHER,
+        remote_addr
+    );
+    Response::builder()
+        .status(StatusCode::SEE_OTHER)
+        .header(
+            header::LOCATION,
+            format!("{}{}", config.base_path, session::LOGIN_PATH),
+        )
+        .header(header::SET_COOKIE, sessions.end())
+        .body(Body::empty())
+        .unwrap()
+}
 
-    let auth_str = auth_header.to_str().unwrap_or("");
-    if !auth_str.starts_with("Basic ") {
+/// The user `req` authenticates as with Digest auth. Only accounts with a
+/// plain-text password can be checked this way.
+fn check_digest_auth(
+    req: &Request<Body>,
+    digest: &DigestAuth,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Result<String, Rejection> {
+    let authorization = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    let uri = req
+        .uri()
+        .path_and_query()
+        .map_or(req.uri().path(), |p| p.as_str());
+    let realm = config.auth_realm.replace(['"', '\\'], "");
+    let password = |user: &str| {
+        let single = config.auth.as_deref().and_then(|token| {
+            let decoded = general_purpose::STANDARD.decode(token).ok()?;
+            let decoded = String::from_utf8(decoded).ok()?;
+            let (name, password) = decoded.split_once(':')?;
+            (name == user).then(|| password.to_string())
+        });
+        single.or_else(|| {
+            config
+                .users
+                .as_ref()?
+                .plain_password(user)
+                .map(str::to_string)
+        })
+    };
+    let result = digest.verify(authorization, req.method().as_str(), uri, &realm, password);
+    if let Err(rejection) = &result {
         warn!(
-            "Invalid auth scheme | got: {:?} | method: {} | status: {} | uri: {} | remote: {}",
-            auth_str,
+            "Digest auth failed | reason: {:?} | method: {} | uri: {} | status: {} | remote: {}",
+            rejection,
             req.method(),
-            StatusCode::UNAUTHORIZED,
             req.uri(),
+            StatusCode::UNAUTHORIZED,
             remote_addr
         );
-        return Err(unauthorized_response());
     }
+    result
+}
 
-    let encoded = (&auth_str[6..]).to_string(); // remove "Basic "
+/// The token of an `Authorization: Bearer` header.
+fn bearer_token(req: &Request<Body>) -> Option<&str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix("Bearer ").map(str::trim)
+}
 
-    if *base64_auth == encoded {
-        Ok(())
-    } else {
-        let decoded = general_purpose::STANDARD
-            .decode(encoded.as_bytes())
-            .unwrap();
+/// Whether `token` is one of the `--token` secrets. Failures are logged
+/// without the token.
+fn check_bearer_token(
+    req: &Request<Body>,
+    token: &str,
+    tokens: &[String],
+    remote_addr: std::net::SocketAddr,
+) -> bool {
+    // Every secret is compared, in constant time, so timing tells nothing.
+    let matched = tokens.iter().fold(false, |matched, secret| {
+        constant_time_eq(secret.as_bytes(), token.as_bytes()) | matched
+    });
+    if !matched {
         warn!(
-            "Auth failed | method: {} | uri: {} | status: {} | provided: {:?} | remote: {}",
+            "Bearer token rejected | method: {} | uri: {} | status: {} | remote: {}",
             req.method(),
             req.uri(),
             StatusCode::UNAUTHORIZED,
-            String::from_utf8(decoded).unwrap(),
             remote_addr
         );
-        Err(unauthorized_response())
+    }
+    matched
+}
+
+/// Replaces the body of an error response with the `--error-page` for its
+/// status, when one exists.
+async fn with_error_page(
+    response: Response<Body>,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    let Some(html) = error_page(response.status(), config, remote_addr).await else {
+        return response;
+    };
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    Response::from_parts(parts, Body::from(html))
+}
+
+/// Reads a configured page. Pages below the root come from the storage, so
+/// they are the ones being served; others are read from disk.
+async fn read_page(path: &Path, config: &Config) -> std::io::Result<String> {
+    if !path.starts_with(&config.root) {
+        return fs::read_to_string(path).await;
+    }
+    let data = storage::collect(config.storage.open(path).await?).await?;
+    String::from_utf8(data).map_err(std::io::Error::other)
+}
+
+/// The rendered error page for `status`. A configured page that does not
+/// exist is silently skipped, so the `403.html`/`404.html` defaults are
+/// optional.
+async fn error_page(
+    status: StatusCode,
+    config: &Config,
+    remote_addr: std::net::SocketAddr,
+) -> Option<String> {
+    let (_, page) = config
+        .error_pages
+        .iter()
+        .find(|(code, _)| *code == status.as_u16())?;
+    let path = Path::new(&config.root).join(page);
+    match read_page(&path, config).await {
+        Ok(html) => Some(template::render(&html, config)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => {
+            error!(
+                "Failed to read error page | path: {:?} | error: {} | remote: {}",
+                path, err, remote_addr
+            );
+            None
+        }
     }
 }
 
-fn unauthorized_response() -> Response<Body> {
+/// `stale` tells a Digest client its credentials were right but the nonce
+/// expired.
+async fn unauthorized_response(
+    config: &Config,
+    stale: bool,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    let realm = config.auth_realm.replace(['"', '\\'], "");
+    let mut body = "<h1><center>Unauthorized</center></h1>".to_string();
+    if let Some(page) = &config.auth_page {
+        match read_page(page, config).await {
+            Ok(html) => body = template::render(&html, config),
+            Err(err) => error!(
+                "Failed to read auth page | path: {:?} | error: {} | remote: {}",
+                page, err, remote_addr
+            ),
+        }
+    } else if let Some(html) = error_page(StatusCode::UNAUTHORIZED, config, remote_addr).await {
+        body = html;
+    }
     Response::builder()
         .status(StatusCode::UNAUTHORIZED)
-        .header(header::WWW_AUTHENTICATE, r#"Basic realm="Restricted""#)
-        .body(Body::from("<h1><center>Unauthorized</center></h1>"))
+        .header(
+            header::WWW_AUTHENTICATE,
+            match &config.digest_auth {
+                Some(digest) => digest.challenge(&realm, stale),
+                None => format!(r#"Basic realm="{}""#, realm),
+            },
+        )
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(body))
         .unwrap()
 }
 
 pub async fn handle_upload(
     req: Request<Body>,
-    root_dir: PathBuf,
+    root_dir: &str,
     remote_addr: std::net::SocketAddr,
+    config: &Config,
 ) -> Result<Response<Body>, Infallible> {
-    let path_uri = req.uri().path().trim_start_matches("/");
-    let target_dir = root_dir.join(PathBuf::from(path_uri));
-    let version = (&req.version()).clone();
-    if target_dir.exists() && !target_dir.is_dir() {
+    let version = req.version();
+    let wants_json = accepts_json(req.headers());
+    let expected_sha256 = match req.headers().get("x-checksum-sha256") {
+        None => None,
+        Some(v) => match v.to_str().ok().and_then(parse_sha256) {
+            Some(hash) => Some(hash),
+            None => {
+                error!(
+                    "Invalid X-Checksum-Sha256 header | value: {:?} | version: {:?} | status: {} | remote: {}",
+                    v,
+                    version,
+                    StatusCode::BAD_REQUEST,
+                    remote_addr
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from(
+                        "X-Checksum-Sha256 must be a hex or base64 SHA-256",
+                    ))
+                    .unwrap());
+            }
+        },
+    };
+    let decoded_path = match decode_path(req.uri().path(), remote_addr) {
+        Ok(path) => path,
+        Err(response) => return Ok(response),
+    };
+    let upload_path = match &config.upload_strip_prefix {
+        Some(prefix) => strip_upload_prefix(&decoded_path, prefix),
+        None => &decoded_path,
+    };
+    let target_dir = match join_under_root(root_dir, upload_path, remote_addr) {
+        Ok(path) => path,
+        Err(response) => return Ok(response),
+    };
+    if !symlinks::allowed(&target_dir, root_dir, config.follow_symlinks).await {
+        return Ok(symlink_refused(&target_dir, config, remote_addr).await);
+    }
+    // Checked against `--exclude` and dotfile hiding with each file name.
+    let upload_dir = upload_path.trim_end_matches('/').to_string();
+    let expects_continue = match req.headers().get(header::EXPECT) {
+        None => false,
+        Some(v) if v.as_bytes().eq_ignore_ascii_case(b"100-continue") => true,
+        Some(v) => {
+            error!(
+                "Unsupported expectation | expect: {:?} | path: {:?} | version: {:?} | status: {} | remote: {}",
+                v,
+                target_dir,
+                version,
+                StatusCode::EXPECTATION_FAILED,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::EXPECTATION_FAILED)
+                .body(Body::from("Unsupported expectation"))
+                .unwrap());
+        }
+    };
+    if config
+        .storage
+        .metadata(&target_dir)
+        .await
+        .is_ok_and(|m| !m.is_dir)
+    {
         error!(
             "Upload failed: target path exists and is not a directory | path: {:?} | version: {:?} | status: {} | remote: {}",
             target_dir,
@@ -500,12 +2738,77 @@ pub async fn handle_upload(
             .unwrap());
     }
 
+    // Every rejection above is sent before the body is touched, so a client
+    // waiting on `Expect: 100-continue` gets the final error instead. hyper
+    // emits the interim `100 Continue` once the body is first polled below.
+    if expects_continue {
+        info!(
+            "Accepting upload body (100 Continue) | path: {:?} | version: {:?} | remote: {}",
+            target_dir, version, remote_addr
+        );
+    }
+
     // parse the multipart body
     let boundary = multer::parse_boundary(content_type).unwrap_or_default();
     let mut multipart = Multipart::new(req.into_body(), boundary);
 
-    while let Some(field) = multipart.next_field().await.unwrap_or(None) {
-        if field.name() != Some("file") {
+    let mut field_count = 0;
+    loop {
+        let field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(err) => {
+                error!(
+                    "Malformed multipart body | error: {} | target_dir: {:?} | version: {:?} | status: {} | remote: {}",
+                    err,
+                    target_dir,
+                    version,
+                    StatusCode::BAD_REQUEST,
+                    remote_addr
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Malformed multipart body"))
+                    .unwrap());
+            }
+        };
+        field_count += 1;
+        if field_count > config.max_fields {
+            error!(
+                "Too many multipart fields | limit: {} | target_dir: {:?} | version: {:?} | status: {} | remote: {}",
+                config.max_fields,
+                target_dir,
+                version,
+                StatusCode::BAD_REQUEST,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Too many multipart fields"))
+                .unwrap());
+        }
+        let name_len = field.name().map_or(0, str::len);
+        if name_len > config.max_field_name_size {
+            error!(
+                "Multipart field name too long | length: {} | limit: {} | target_dir: {:?} | version: {:?} | status: {} | remote: {}",
+                name_len,
+                config.max_field_name_size,
+                target_dir,
+                version,
+                StatusCode::BAD_REQUEST,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from("Multipart field name too long"))
+                .unwrap());
+        }
+        let accepted = if config.upload_field == "*" {
+            field.file_name().is_some()
+        } else {
+            field.name() == Some(config.upload_field.as_str())
+        };
+        if !accepted {
             continue;
         }
 
@@ -515,21 +2818,147 @@ pub async fn handle_upload(
             .unwrap_or("upload.bin".to_string());
 
         let safe_name = sanitize_filename::sanitize(&file_name);
+        let relative = format!("{}/{}", upload_dir, safe_name);
+        if pathfilter::is_hidden(&relative, config) {
+            warn!(
+                "Upload refused for hidden path | path: {:?} | version: {:?} | status: {} | remote: {}",
+                relative,
+                version,
+                StatusCode::FORBIDDEN,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("Uploads to this path are not allowed"))
+                .unwrap());
+        }
         let save_path = target_dir.join(safe_name);
-        let mut file = File::create(&save_path).await.unwrap();
-        let mut field_data = field.into_stream();
-
-        while let Ok(Some(chunk)) = field_data.try_next().await {
-            let data = chunk;
-            file.write_all(&data).await.unwrap();
+        if let Some(dir) = &config.cgi
+            && pathfilter::relative_path(&save_path, root_dir)
+                .is_some_and(|relative| cgi::overlaps(&relative, dir))
+        {
+            warn!(
+                "Upload refused for the CGI directory | path: {:?} | version: {:?} | status: {} | remote: {}",
+                save_path,
+                version,
+                StatusCode::FORBIDDEN,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from("Uploads to this path are not allowed"))
+                .unwrap());
+        }
+        let hash = config.dedupe || expected_sha256.is_some();
+        let upload = match receive_upload(
+            field,
+            config.storage.staging_dir(&target_dir).as_deref(),
+            hash,
+        )
+        .await
+        {
+            Ok(upload) => upload,
+            Err(err) => {
+                error!(
+                    "Upload failed while receiving | path: {:?} | error: {} | version: {:?} | status: {} | remote: {}",
+                    save_path,
+                    err,
+                    version,
+                    StatusCode::BAD_REQUEST,
+                    remote_addr
+                );
+                return Ok(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .body(Body::from("Upload failed"))
+                    .unwrap());
+            }
+        };
+        if let Some(expected) = expected_sha256
+            && upload.sha256 != Some(expected)
+        {
+            upload.staged.discard().await;
+            error!(
+                "Upload checksum mismatch | path: {:?} | version: {:?} | status: {} | remote: {}",
+                save_path,
+                version,
+                StatusCode::UNPROCESSABLE_ENTITY,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::UNPROCESSABLE_ENTITY)
+                .body(Body::from("Checksum mismatch"))
+                .unwrap());
+        }
+        if let Some(status) = plugin::on_upload(&config.plugins, &relative, upload.size) {
+            upload.staged.discard().await;
+            warn!(
+                "Upload refused by plugin | path: {:?} | version: {:?} | status: {} | remote: {}",
+                save_path, version, status, remote_addr
+            );
+            return Ok(Response::builder()
+                .status(status)
+                .body(Body::from("Upload refused"))
+                .unwrap());
+        }
+        if config.dedupe
+            && let Some(hash) = upload.sha256
+            && is_identical_file(config.storage.as_ref(), &save_path, upload.size, hash).await
+        {
+            upload.staged.discard().await;
+            info!(
+                "Upload skipped, identical file exists | path: {:?} | version: {:?} | status: {} | remote: {}",
+                save_path,
+                version,
+                StatusCode::OK,
+                remote_addr
+            );
+            if wants_json {
+                return Ok(upload_json_response(
+                    StatusCode::OK,
+                    root_dir,
+                    &config.base_path,
+                    &save_path,
+                    upload.size,
+                ));
+            }
+            return Ok(Response::builder()
+                .status(StatusCode::OK)
+                .body(Body::from("File already exists, identical content"))
+                .unwrap());
+        }
+        if let Err(err) = config.storage.persist(upload.staged, &save_path).await {
+            error!(
+                "Upload failed to store file | path: {:?} | error: {} | version: {:?} | status: {} | remote: {}",
+                save_path,
+                err,
+                version,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Upload failed"))
+                .unwrap());
         }
+        METRICS.record_upload();
+        let status = if wants_json {
+            StatusCode::CREATED
+        } else {
+            StatusCode::SEE_OTHER
+        };
         info!(
             "Upload complete | path: {:?} | version: {:?} | status: {} | remote: {}",
-            save_path,
-            version,
-            StatusCode::SEE_OTHER,
-            remote_addr
+            save_path, version, status, remote_addr
         );
+        if wants_json {
+            return Ok(upload_json_response(
+                status,
+                root_dir,
+                &config.base_path,
+                &save_path,
+                upload.size,
+            ));
+        }
         return Ok(Response::builder()
             .status(StatusCode::SEE_OTHER)
             .header("Location", ".")
@@ -549,25 +2978,278 @@ pub async fn handle_upload(
         .unwrap())
 }
 
-fn parse_range_header(header: &str, file_size: u64) -> Option<(u64, u64)> {
-    if !header.starts_with("bytes=") {
-        return None;
+/// Characters escaped when turning a stored path back into a URL path.
+pub const URL_PATH: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// Characters escaped in a query parameter value.
+const FORM_VALUE: &AsciiSet = &URL_PATH.add(b'&').add(b'+').add(b'=');
+
+/// Whether the client asked for JSON rather than a page to navigate to.
+fn accepts_json(headers: &hyper::HeaderMap) -> bool {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    accept.contains("application/json") && !accept.contains("text/html")
+}
+
+/// Describes a stored upload for API clients: its path below the root, its
+/// size and the URL it can be downloaded from.
+fn upload_json_response(
+    status: StatusCode,
+    root_dir: &str,
+    base_path: &str,
+    save_path: &Path,
+    size: u64,
+) -> Response<Body> {
+    let relative = save_path.strip_prefix(root_dir).unwrap_or(save_path);
+    let path = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/");
+    let url = format!("{}/{}", base_path, utf8_percent_encode(&path, URL_PATH));
+    let body = serde_json::json!({ "path": path, "size": size, "url": url });
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::LOCATION, url)
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// An upload received in full, before it is moved to its destination.
+pub struct ReceivedUpload {
+    pub staged: Staged,
+    pub size: u64,
+    /// Computed while writing when deduplication is enabled.
+    pub sha256: Option<[u8; 32]>,
+}
+
+/// Writes `data` (a form field or a PUT body) to a hidden temporary file in
+/// `dir`, so the destination only ever sees a complete file, or keeps it in
+/// memory when there is no `dir`. The temporary file is removed on error.
+pub async fn receive_upload<S, E>(
+    data: S,
+    dir: Option<&Path>,
+    hash: bool,
+) -> std::io::Result<ReceivedUpload>
+where
+    S: futures_util::Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let mut hasher = hash.then(Sha256::new);
+    let mut size = 0u64;
+    let mut data = data;
+    let Some(dir) = dir else {
+        let mut buffer = Vec::new();
+        while let Some(chunk) = data.try_next().await.map_err(std::io::Error::other)? {
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+            buffer.extend_from_slice(&chunk);
+        }
+        return Ok(ReceivedUpload {
+            size: buffer.len() as u64,
+            staged: Staged::Memory(buffer.into()),
+            sha256: hasher.map(|h| h.finalize().into()),
+        });
+    };
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let temp_path = dir.join(format!(
+        ".samserve-upload-{}-{}.part",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = File::create(&temp_path).await?;
+    let result = async {
+        while let Some(chunk) = data.try_next().await.map_err(std::io::Error::other)? {
+            file.write_all(&chunk).await?;
+            if let Some(hasher) = hasher.as_mut() {
+                hasher.update(&chunk);
+            }
+            size += chunk.len() as u64;
+        }
+        file.flush().await
+    }
+    .await;
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path).await;
+        return Err(err);
+    }
+    Ok(ReceivedUpload {
+        staged: Staged::File(temp_path),
+        size,
+        sha256: hasher.map(|h| h.finalize().into()),
+    })
+}
+
+/// Parses a SHA-256 given as 64 hex digits or as (standard) base64.
+fn parse_sha256(value: &str) -> Option<[u8; 32]> {
+    let value = value.trim();
+    let bytes = if value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        (0..32)
+            .map(|i| u8::from_str_radix(&value[2 * i..2 * i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .ok()?
+    } else {
+        general_purpose::STANDARD.decode(value).ok()?
+    };
+    bytes.try_into().ok()
+}
+
+/// Whether `path` is a file with the given size and SHA-256.
+async fn is_identical_file(
+    storage: &dyn Storage,
+    path: &Path,
+    size: u64,
+    sha256: [u8; 32],
+) -> bool {
+    match storage.metadata(path).await {
+        Ok(meta) if !meta.is_dir && meta.len == size => digest::sha256_file(storage, path)
+            .await
+            .is_ok_and(|h| h == sha256),
+        _ => false,
+    }
+}
+
+/// Answers a request for several ranges with a `multipart/byteranges` body,
+/// one part per range.
+#[allow(clippy::too_many_arguments)]
+fn multipart_ranges(
+    storage: Arc<dyn Storage>,
+    path: &Path,
+    ranges: &[(u64, u64)],
+    file_size: u64,
+    mime: &str,
+    mut builder: hyper::http::response::Builder,
+    head_only: bool,
+    remote_addr: std::net::SocketAddr,
+) -> Response<Body> {
+    let boundary = format!(
+        "samserve-{:x}",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_nanos())
+    );
+    let parts = ranges
+        .iter()
+        .map(|&(start, end)| {
+            let head = format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, mime, start, end, file_size
+            );
+            (head, start, end)
+        })
+        .collect::<Vec<_>>();
+    let closing = format!("\r\n--{}--\r\n", boundary);
+    let content_length = parts
+        .iter()
+        .map(|(head, start, end)| head.len() as u64 + end - start + 1)
+        .sum::<u64>()
+        + closing.len() as u64;
+    let body = if head_only {
+        Body::empty()
+    } else {
+        let path = path.to_path_buf();
+        let stream = stream::iter(parts)
+            .then(move |(head, start, end)| {
+                let path = path.clone();
+                let storage = storage.clone();
+                async move {
+                    let part = storage.read_range(&path, start, end - start + 1).await?;
+                    let head = stream::iter(Some(Ok(Bytes::from(head))));
+                    Ok::<_, std::io::Error>(head.chain(part))
+                }
+            })
+            .try_flatten()
+            .chain(stream::iter(Some(Ok(Bytes::from(closing)))))
+            // A failed part ends the body there, without the closing
+            // boundary, so the client cannot take it for a complete one.
+            .scan(false, |failed, item| {
+                if *failed {
+                    return future::ready(None);
+                }
+                *failed = item.is_err();
+                future::ready(Some(item))
+            });
+        Body::wrap_stream(stream)
+    };
+    info!(
+        "Partial content | {:?} | ranges: {} | status: {} | remote: {}",
+        path,
+        ranges
+            .iter()
+            .map(|(start, end)| format!("{}-{}", start, end))
+            .collect::<Vec<_>>()
+            .join(","),
+        StatusCode::PARTIAL_CONTENT,
+        remote_addr
+    );
+    if let Some(headers) = builder.headers_mut() {
+        headers.insert(
+            header::CONTENT_TYPE,
+            header::HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary))
+                .unwrap(),
+        );
     }
-    let range = &header[6..];
-    let parts: Vec<&str> = range.split('-').collect();
-    if parts.len() != 2 {
+    builder
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header(header::CONTENT_LENGTH, content_length.to_string())
+        .body(body)
+        .unwrap()
+}
+
+/// Parses `bytes=<range>[,<range>...]`. Malformed headers, and ones asking
+/// for more than `MAX_RANGES` ranges, are ignored so the whole file is sent.
+fn parse_range_header(header: &str, file_size: u64) -> Option<Vec<(u64, u64)>> {
+    let specs = header
+        .strip_prefix("bytes=")?
+        .split(',')
+        .collect::<Vec<_>>();
+    if specs.len() > MAX_RANGES {
         return None;
     }
-    let start = parts[0].parse::<u64>().ok();
-    let end = parts[1].parse::<u64>().ok();
+    specs
+        .into_iter()
+        .map(|spec| parse_range_spec(spec.trim(), file_size))
+        .collect()
+}
 
-    match (start, end) {
-        (Some(s), Some(e)) if s <= e => Some((s, e)),
-        (Some(s), None) if s < file_size => Some((s, file_size - 1)),
-        (None, Some(e)) if e != 0 => {
-            let size = file_size.min(e);
-            Some((file_size - size, file_size - 1))
-        }
+/// An end past the file is cut back to its last byte. A range starting
+/// past the end is returned as it is, for the caller to find
+/// unsatisfiable; on an empty file every range is.
+fn parse_range_spec(range: &str, file_size: u64) -> Option<(u64, u64)> {
+    let (start, end) = range.split_once('-')?;
+    let last = file_size.saturating_sub(1);
+    match (start.parse::<u64>().ok(), end.parse::<u64>().ok()) {
+        (Some(s), Some(e)) if s <= e => Some((s, e.min(last))),
+        (Some(s), None) if end.is_empty() => Some((s, last)),
+        (None, Some(e)) if start.is_empty() && e != 0 => Some((file_size - file_size.min(e), last)),
         _ => None,
     }
 }
+
+/// Sorts `ranges` and merges the ones that overlap or touch, so no byte is
+/// sent twice.
+fn merge_ranges(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort_unstable();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
@@ -0,0 +1,63 @@
+//! samserve as a library: the file server behind the `samserve` binary,
+//! for embedding in other tools and in integration tests.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+//! let server = samserve::Server::builder()
+//!     .root("./public")
+//!     .auth("user", "password")
+//!     .upload(true)
+//!     .bind("127.0.0.1:0".parse()?)?;
+//! println!("Serving on http://{}", server.local_addr());
+//! server.shutdown().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The modules the binary is built from are public as well, but only
+//! `Server` and `Config` are meant as a stable interface.
+
+pub mod acme;
+pub mod archive;
+pub mod args;
+pub mod bandwidth;
+mod cache;
+mod cgi;
+pub mod config;
+mod cors;
+mod digest;
+mod digest_auth;
+mod dirsize;
+pub mod embed;
+mod encoding;
+mod handler;
+pub mod listener;
+pub mod lockout;
+pub mod memory;
+pub mod metrics;
+mod pathfilter;
+mod plugin;
+pub mod privileges;
+mod proxy;
+pub mod ratelimit;
+pub mod redirect;
+pub mod s3;
+pub mod sandbox;
+pub mod server;
+pub mod session;
+pub mod stats;
+pub mod storage;
+mod symlinks;
+pub mod systemd;
+mod template;
+#[cfg(test)]
+mod tests;
+mod thumbnail;
+mod timefmt;
+pub mod tls;
+mod transfer;
+pub mod users;
+mod webdav;
+
+pub use config::Config;
+pub use server::{Server, ServerBuilder, ServerHandle};
@@ -0,0 +1,233 @@
+use hyper::server::conn::AddrStream;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::ops::RangeInclusive;
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+/// Binds a non-blocking TCP listener with `SO_REUSEADDR` set, an explicit
+/// accept backlog and, on Unix, optional `SO_REUSEPORT` so several
+/// processes can share the port. IPv6 listeners accept IPv4 connections
+/// only with `dual_stack`, whatever the system default is.
+pub fn bind_tcp(
+    addr: SocketAddr,
+    backlog: i32,
+    reuse_port: bool,
+    dual_stack: bool,
+) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    if reuse_port {
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "SO_REUSEPORT is only available on Unix",
+        ));
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(backlog)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Binds the first port in `ports` on `ip` that is not already in use.
+/// Other bind errors, such as a privileged port, are returned as they are.
+pub fn bind_tcp_in_range(
+    ip: IpAddr,
+    ports: RangeInclusive<u16>,
+    backlog: i32,
+    reuse_port: bool,
+    dual_stack: bool,
+) -> io::Result<TcpListener> {
+    for port in ports.clone() {
+        match bind_tcp(SocketAddr::new(ip, port), backlog, reuse_port, dual_stack) {
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+            result => return result,
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("every port in {}-{} is in use", ports.start(), ports.end()),
+    ))
+}
+
+/// A listening socket to serve on.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl From<TcpListener> for Listener {
+    fn from(listener: TcpListener) -> Self {
+        Listener::Tcp(listener)
+    }
+}
+
+/// Binds a Unix domain socket at `path` with permissions `mode`. A stale
+/// socket file left by a previous run is replaced; one a live server still
+/// accepts on is not.
+#[cfg(unix)]
+pub fn bind_unix(path: &Path, mode: u32) -> io::Result<UnixListener> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    if let Ok(meta) = std::fs::symlink_metadata(path)
+        && meta.file_type().is_socket()
+    {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another process is listening on this socket",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    listener.set_nonblocking(true)?;
+    Ok(listener)
+}
+
+/// Parses an IP address to bind, accepting IPv6 literals in brackets as
+/// they appear in URLs.
+pub fn parse_ip(ip: &str) -> Result<IpAddr, String> {
+    let unbracketed = ip
+        .strip_prefix('[')
+        .and_then(|ip| ip.strip_suffix(']'))
+        .unwrap_or(ip);
+    unbracketed
+        .parse()
+        .map_err(|_| format!("expected an IPv4 or IPv6 address, got {:?}", ip))
+}
+
+/// Parses an inclusive port range such as `8000-8100`.
+pub fn parse_port_range(range: &str) -> Result<RangeInclusive<u16>, String> {
+    let invalid = || format!("expected a port range like 8000-8100, got {:?}", range);
+    let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+    let start: u16 = start.trim().parse().map_err(|_| invalid())?;
+    let end: u16 = end.trim().parse().map_err(|_| invalid())?;
+    if start == 0 || start > end {
+        return Err(invalid());
+    }
+    Ok(start..=end)
+}
+
+/// Parses a socket file mode given in octal, e.g. `660`.
+pub fn parse_mode(mode: &str) -> Result<u32, String> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o777)
+        .ok_or_else(|| format!("expected an octal mode such as 660, got {:?}", mode))
+}
+
+/// Adopts an already-listening TCP or Unix socket inherited from the parent
+/// process (e.g. a service manager), instead of binding a new one.
+#[cfg(unix)]
+pub fn from_fd(fd: i32) -> io::Result<Listener> {
+    use std::os::fd::{FromRawFd, IntoRawFd};
+    // SAFETY: the caller hands over ownership of an inherited descriptor that
+    // nothing else in this process uses.
+    let socket = unsafe { Socket::from_raw_fd(fd) };
+    // Fails with ENOTSOCK / EINVAL when the fd is not a bound socket.
+    let local = socket.local_addr()?;
+    socket.set_nonblocking(true)?;
+    if local.is_unix() {
+        // SAFETY: ownership moves from `socket` to the listener.
+        Ok(Listener::Unix(unsafe {
+            UnixListener::from_raw_fd(socket.into_raw_fd())
+        }))
+    } else {
+        Ok(Listener::Tcp(socket.into()))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn from_fd(_fd: i32) -> io::Result<Listener> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "inherited sockets are only supported on Unix",
+    ))
+}
+
+/// A served connection that knows the client's address.
+pub trait RemoteAddr {
+    fn remote_addr(&self) -> SocketAddr;
+}
+
+impl RemoteAddr for AddrStream {
+    /// IPv4 clients of a dual-stack listener are reported as plain IPv4
+    /// rather than as IPv4-mapped IPv6 (`::ffff:a.b.c.d`).
+    fn remote_addr(&self) -> SocketAddr {
+        let addr = AddrStream::remote_addr(self);
+        SocketAddr::new(addr.ip().to_canonical(), addr.port())
+    }
+}
+
+/// Accepts connections on a Unix domain socket.
+#[cfg(unix)]
+pub struct UnixIncoming(tokio::net::UnixListener);
+
+#[cfg(unix)]
+impl UnixIncoming {
+    pub fn new(listener: UnixListener) -> io::Result<Self> {
+        Ok(UnixIncoming(tokio::net::UnixListener::from_std(listener)?))
+    }
+}
+
+#[cfg(unix)]
+impl hyper::server::accept::Accept for UnixIncoming {
+    type Conn = tokio::net::UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<io::Result<Self::Conn>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|result| Some(result.map(|(stream, _)| stream)))
+    }
+}
+
+/// Unix socket peers have no IP address; they are logged as `0.0.0.0:0`.
+#[cfg(unix)]
+impl RemoteAddr for tokio::net::UnixStream {
+    fn remote_addr(&self) -> SocketAddr {
+        SocketAddr::from(([0, 0, 0, 0], 0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_port_ranges() {
+        assert_eq!(parse_port_range("8000-8100"), Ok(8000..=8100));
+        assert_eq!(parse_port_range("8000-8000"), Ok(8000..=8000));
+        assert!(parse_port_range("8100-8000").is_err());
+        assert!(parse_port_range("0-10").is_err());
+        assert!(parse_port_range("8000").is_err());
+    }
+
+    #[test]
+    fn skips_ports_in_use() {
+        let ip = IpAddr::from([127, 0, 0, 1]);
+        let taken = bind_tcp(SocketAddr::new(ip, 0), 16, false, false).unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let Some(next) = port.checked_add(20) else {
+            return;
+        };
+        let bound = bind_tcp_in_range(ip, port..=next, 16, false, false).unwrap();
+        assert_ne!(bound.local_addr().unwrap().port(), port);
+
+        let err = bind_tcp_in_range(ip, port..=port, 16, false, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+    }
+}
@@ -0,0 +1,135 @@
+//! `--auth-max-failures`: clients that keep sending wrong credentials are
+//! refused for a while, so passwords cannot be guessed at full speed.
+//! Failures are counted per IP address and forgotten once a client has gone
+//! `ban_time` without one; a successful login clears them.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+struct Client {
+    failures: u32,
+    last_failure: Instant,
+    banned_until: Option<Instant>,
+}
+
+/// Failed authentication attempts by client address.
+#[derive(Debug)]
+pub struct Lockout {
+    max_failures: u32,
+    ban_time: Duration,
+    clients: Mutex<HashMap<IpAddr, Client>>,
+}
+
+impl Lockout {
+    /// Bans a client for `ban_time` after `max_failures` failures; 0 never
+    /// does.
+    pub fn new(max_failures: u32, ban_time: Duration) -> Lockout {
+        Lockout {
+            max_failures,
+            ban_time,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// These limits with the failures counted by `old`, so a reload does
+    /// not lift bans.
+    pub fn with_clients_of(&self, old: &Lockout) -> Lockout {
+        Lockout {
+            max_failures: self.max_failures,
+            ban_time: self.ban_time,
+            clients: Mutex::new(old.clients.lock().unwrap().clone()),
+        }
+    }
+
+    pub fn max_failures(&self) -> u32 {
+        self.max_failures
+    }
+
+    pub fn ban_time(&self) -> Duration {
+        self.ban_time
+    }
+
+    /// How much longer `ip` is banned for, if it is.
+    pub fn banned(&self, ip: IpAddr) -> Option<Duration> {
+        let clients = self.clients.lock().unwrap();
+        let until = clients.get(&ip)?.banned_until?;
+        until
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+    }
+
+    /// Counts a failure from `ip`, returning how many it has made.
+    pub fn record_failure(&self, ip: IpAddr) -> u32 {
+        if self.max_failures == 0 {
+            return 0;
+        }
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        clients.retain(|_, client| {
+            now.duration_since(client.last_failure) < self.ban_time
+                || client.banned_until.is_some_and(|until| until > now)
+        });
+        let client = clients.entry(ip).or_insert(Client {
+            failures: 0,
+            last_failure: now,
+            banned_until: None,
+        });
+        if client.banned_until.is_some_and(|until| until <= now) {
+            // A served ban starts the count afresh.
+            client.failures = 0;
+            client.banned_until = None;
+        }
+        client.failures += 1;
+        client.last_failure = now;
+        if client.failures >= self.max_failures {
+            client.banned_until = Some(now + self.ban_time);
+        }
+        client.failures
+    }
+
+    /// Forgets the failures of `ip` once it has authenticated.
+    pub fn record_success(&self, ip: IpAddr) {
+        if self.max_failures > 0 {
+            self.clients.lock().unwrap().remove(&ip);
+        }
+    }
+}
+
+impl Default for Lockout {
+    fn default() -> Lockout {
+        Lockout::new(0, Duration::ZERO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bans_after_repeated_failures() {
+        let lockout = Lockout::new(3, Duration::from_secs(60));
+        let (ip, other) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        assert_eq!(lockout.record_failure(ip), 1);
+        assert_eq!(lockout.record_failure(ip), 2);
+        assert_eq!(lockout.banned(ip), None);
+        lockout.record_success(ip);
+        assert_eq!(lockout.record_failure(ip), 1);
+        lockout.record_failure(ip);
+        assert_eq!(lockout.record_failure(ip), 3);
+        assert!(
+            lockout
+                .banned(ip)
+                .is_some_and(|left| left <= Duration::from_secs(60))
+        );
+        assert_eq!(lockout.banned(other), None);
+
+        let disabled = Lockout::default();
+        for _ in 0..10 {
+            disabled.record_failure(ip);
+        }
+        assert_eq!(disabled.banned(ip), None);
+    }
+}
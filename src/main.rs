@@ -1,61 +1,674 @@
-use clap::Parser;
-use hyper::Server;
-use hyper::server::conn::AddrStream;
-use hyper::service::{make_service_fn, service_fn};
+use hyper::server::conn::AddrIncoming;
 use log::{error, info, warn};
-use simple_logger;
-use std::{convert::Infallible, net::SocketAddr};
-mod args;
-mod handler;
-use args::Args;
-use base64::{Engine as _, engine::general_purpose};
+use samserve::args::{Args, ColorChoice};
+use samserve::config::{Config, SharedConfig};
+use samserve::listener::{self, Listener};
+use samserve::server::run_server;
+use samserve::{acme, archive, metrics, privileges, redirect, sandbox, stats, systemd, tls};
+use std::io::IsTerminal;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use tokio_rustls::TlsAcceptor;
 
-#[tokio::main]
-async fn main() {
-    simple_logger::SimpleLogger::new().init().unwrap();
-    let args = Args::parse();
+fn main() {
+    let mut args = Args::load();
+    init_logger(args.color);
+    if let Some(fd) = systemd::take_listen_fd()
+        && args.listen_fd.is_none()
+        && args.unix_socket.is_none()
+    {
+        info!("Using the socket passed by systemd | fd: {}", fd);
+        args.listen_fd = Some(fd);
+    }
+    // Forking has to happen before the runtime spawns its worker threads.
+    if args.daemon {
+        daemonize(&args);
+    }
+    // Looked up before the sandbox can hide /etc/passwd, and so a bad name
+    // fails before anything starts.
+    let account = args.user.as_deref().map(|user| {
+        privileges::Account::resolve(user, args.group.as_deref()).unwrap_or_else(|e| {
+            error!("Invalid --user/--group: {}", e);
+            std::process::exit(1);
+        })
+    });
+    // Landlock only confines the calling thread and the threads it starts
+    // later, so it has to go up before the runtime exists.
+    if args.sandbox {
+        enter_sandbox(&mut args);
+    }
+    let runtime = tokio::runtime::Runtime::new().unwrap_or_else(|e| {
+        error!("Failed to start runtime: {}", e);
+        std::process::exit(1);
+    });
+    runtime.block_on(run(args, account));
+}
+
+fn init_logger(color: ColorChoice) {
+    // The logger writes to stdout, which `colored` checks on its own; the
+    // override makes the choice explicit either way.
+    let colors = match color {
+        ColorChoice::Auto => std::io::stdout().is_terminal(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    colored::control::set_override(colors);
+    simple_logger::SimpleLogger::new()
+        .with_colors(colors)
+        .init()
+        .unwrap();
+}
+
+#[cfg(unix)]
+fn daemonize(args: &Args) {
+    let open_log = || -> std::io::Result<daemonize::Stdio> {
+        match &args.log_file {
+            Some(path) => std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map(daemonize::Stdio::from),
+            None => Ok(daemonize::Stdio::devnull()),
+        }
+    };
+    let (stdout, stderr) = match open_log().and_then(|out| Ok((out, open_log()?))) {
+        Ok(streams) => streams,
+        Err(e) => {
+            error!("Failed to open log file: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let cwd = std::env::current_dir().unwrap_or_else(|_| "/".into());
+    let mut daemon = daemonize::Daemonize::new()
+        .working_directory(cwd)
+        .umask(0o022)
+        .stdout(stdout)
+        .stderr(stderr);
+    if let Some(pidfile) = &args.pidfile {
+        daemon = daemon.pid_file(pidfile);
+    }
+    if let Err(e) = daemon.start() {
+        error!("Failed to daemonize: {}", e);
+        std::process::exit(1);
+    }
+    info!("Running in background | pid: {}", std::process::id());
+}
+
+#[cfg(not(unix))]
+fn daemonize(_args: &Args) {
+    error!("--daemon is only supported on Unix");
+    std::process::exit(1);
+}
+
+async fn run(args: Args, account: Option<privileges::Account>) {
     info!("Parsed arguments...");
     info!("Root directory: {}", args.root);
-    info!("Upload support: {}", args.upload);
-    let base64_auth: Option<String> = match args.auth {
-        Some(auth) => {
-            info!("Basic Auth enabled with credentials: {}", auth);
-            let encoded_string = general_purpose::STANDARD.encode(auth.as_bytes());
-            Some(encoded_string)
+    info!("Upload support: {}", args.upload || args.tmpfs);
+    match (&args.auth, &args.auth_file) {
+        (Some(auth), _) => info!(
+            "Basic Auth enabled | user: {:?}",
+            auth.split_once(':').map_or(auth.as_str(), |(user, _)| user)
+        ),
+        (None, Some(path)) => info!("Basic Auth enabled with the accounts in {:?}", path),
+        (None, None) if args.tokens.is_empty() => warn!("Basic Auth not enabled"),
+        (None, None) => {}
+    }
+    if !args.tokens.is_empty() {
+        info!("Bearer token auth enabled | tokens: {}", args.tokens.len());
+    }
+    if let Some(path) = &args.write_auth_file {
+        info!("Writes need one of the accounts in {:?}", path);
+    } else if args.protect_writes {
+        info!("Reads are public, writes need authentication");
+    }
+    let config = Config::from_args(&args).unwrap_or_else(|e| {
+        error!("Invalid configuration: {}", e);
+        std::process::exit(1);
+    });
+    let addrs = if let Some(ports) = &args.port_range {
+        vec![SocketAddr::new(args.ip, *ports.start())]
+    } else if args.listen.is_empty() {
+        vec![SocketAddr::new(args.ip, args.port)]
+    } else {
+        args.listen.clone()
+    };
+    // Side listeners (metrics, redirects, ACME) bind next to the first one.
+    let addr = addrs[0];
+    let https = args.serves_https();
+    if let Some(path) = &args.unix_socket {
+        if https {
+            error!(
+                "--unix-socket cannot be combined with HTTPS; terminate TLS in the proxy in front"
+            );
+            std::process::exit(1);
+        }
+        info!("Starting server on unix:{}", path.display());
+    }
+    // Side listeners are bound now and started once privileges are dropped.
+    let mut side_listeners: Vec<futures_util::future::BoxFuture<'static, ()>> = Vec::new();
+    if args.metrics {
+        match args.metrics_port {
+            Some(port) => {
+                let metrics_addr = SocketAddr::new(addr.ip(), port);
+                info!("Serving metrics on {}{}", metrics_addr, args.metrics_path);
+                let metrics_path = args.metrics_path.clone();
+                let incoming = bind_plain(metrics_addr);
+                side_listeners.push(Box::pin(async move {
+                    if let Err(e) = metrics::serve_admin(incoming, metrics_path).await {
+                        error!("Metrics server error: {}", e);
+                        std::process::exit(1);
+                    }
+                }));
+            }
+            None => info!("Serving metrics on {}", args.metrics_path),
+        }
+    }
+    // Counts are persisted when the server stops.
+    let stats_file = args
+        .download_stats_file
+        .clone()
+        .filter(|_| args.download_stats);
+    if let Some(stats_file) = &stats_file {
+        stats::load(stats_file);
+    }
+    if args.exit_on_root_loss {
+        tokio::spawn(watch_root(args.root.clone(), stats_file.clone()));
+    }
+    if args.redirect_http.is_some() && !https {
+        error!("--redirect-http needs HTTPS (--tls-cert, --tls-self-signed or --acme-domain)");
+        std::process::exit(1);
+    }
+    if args.hsts.is_some() && !https {
+        error!("--hsts needs HTTPS (--tls-cert, --tls-self-signed or --acme-domain)");
+        std::process::exit(1);
+    }
+    // With Basic Auth configured a client certificate is an alternative to
+    // the password, otherwise it is the only way in.
+    let client_auth = match &args.tls_client_ca {
+        Some(_) if !https => {
+            error!("--tls-client-ca needs HTTPS (--tls-cert, --tls-self-signed or --acme-domain)");
+            std::process::exit(1);
         }
-        None => {
-            warn!("Basic Auth not enabled");
-            None
+        Some(ca) => Some(
+            tls::client_verifier(
+                ca,
+                args.auth.is_none()
+                    && args.auth_file.is_none()
+                    && args.tokens.is_empty()
+                    && args.write_auth_file.is_none(),
+            )
+            .unwrap_or_else(|e| {
+                error!("TLS setup failed: {}", e);
+                std::process::exit(1);
+            }),
+        ),
+        None => None,
+    };
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => Some(
+            tls::acceptor_from_pem(cert, key, client_auth).unwrap_or_else(|e| {
+                error!("TLS setup failed: {}", e);
+                std::process::exit(1);
+            }),
+        ),
+        _ if args.tls_self_signed => Some(
+            tls::self_signed_acceptor(certificate_names(&addrs), client_auth).unwrap_or_else(|e| {
+                error!("TLS setup failed: {}", e);
+                std::process::exit(1);
+            }),
+        ),
+        _ if !args.acme_domain.is_empty() => Some(start_acme(&args, client_auth)),
+        _ => None,
+    };
+    let listeners = match (args.listen_fd, &args.unix_socket) {
+        (Some(fd), _) => {
+            let listener = listener::from_fd(fd).unwrap_or_else(|e| {
+                error!("Failed to use inherited socket fd {}: {}", fd, e);
+                std::process::exit(1);
+            });
+            match &listener {
+                Listener::Tcp(tcp) => match tcp.local_addr() {
+                    Ok(local) => info!("Listening on inherited fd {} ({})", fd, local),
+                    Err(_) => info!("Listening on inherited fd {}", fd),
+                },
+                #[cfg(unix)]
+                Listener::Unix(_) if https => {
+                    error!(
+                        "Inherited fd {} is a Unix socket, which cannot serve HTTPS",
+                        fd
+                    );
+                    std::process::exit(1);
+                }
+                #[cfg(unix)]
+                Listener::Unix(_) => info!("Listening on inherited fd {} (Unix socket)", fd),
+            }
+            vec![listener]
+        }
+        #[cfg(unix)]
+        (None, Some(path)) => vec![Listener::Unix(
+            listener::bind_unix(path, args.unix_socket_mode).unwrap_or_else(|e| {
+                error!("Failed to bind {}: {}", path.display(), e);
+                std::process::exit(1);
+            }),
+        )],
+        #[cfg(not(unix))]
+        (None, Some(_)) => {
+            error!("--unix-socket is only supported on Unix");
+            std::process::exit(1);
         }
+        (None, None) if let Some(ports) = &args.port_range => {
+            let tcp = listener::bind_tcp_in_range(
+                args.ip,
+                ports.clone(),
+                args.backlog,
+                args.reuse_port,
+                args.dual_stack,
+            )
+            .unwrap_or_else(|e| {
+                error!(
+                    "Failed to bind {} on ports {}-{}: {}",
+                    args.ip,
+                    ports.start(),
+                    ports.end(),
+                    e
+                );
+                std::process::exit(1);
+            });
+            if let Ok(local) = tcp.local_addr()
+                && local.port() != *ports.start()
+            {
+                warn!(
+                    "Port {} is in use, listening on port {} instead",
+                    ports.start(),
+                    local.port()
+                );
+            }
+            vec![Listener::Tcp(tcp)]
+        }
+        (None, None) => addrs
+            .iter()
+            .map(|&addr| {
+                Listener::Tcp(
+                    listener::bind_tcp(addr, args.backlog, args.reuse_port, args.dual_stack)
+                        .unwrap_or_else(|e| {
+                            error!("Failed to bind {}: {}", addr, e);
+                            std::process::exit(1);
+                        }),
+                )
+            })
+            .collect(),
     };
-    let bind_address = format!("{}:{}", args.ip, args.port);
-    let addr: SocketAddr = bind_address.parse().unwrap_or_else(|_| {
-        error!("Invalid address format: {}", bind_address);
+    // Logged once bound so the port actually chosen by --port-range or
+    // --port 0 is the one shown.
+    if args.listen_fd.is_none() && args.unix_socket.is_none() {
+        let scheme = if https { "https" } else { "http" };
+        for listener in &listeners {
+            if let Listener::Tcp(tcp) = listener
+                && let Ok(local) = tcp.local_addr()
+            {
+                info!("Starting server on {}://{}", scheme, local);
+            }
+        }
+    }
+    let https_port = match &listeners[0] {
+        Listener::Tcp(tcp) => tcp.local_addr().map_or(addr.port(), |local| local.port()),
+        #[cfg(unix)]
+        Listener::Unix(_) => addr.port(),
+    };
+    let acme = !args.acme_domain.is_empty();
+    if let Some(port) = args.redirect_http {
+        let redirect_addr = SocketAddr::new(addr.ip(), port);
+        info!("Redirecting http://{} to HTTPS", redirect_addr);
+        side_listeners.push(plain_listener(
+            redirect_addr,
+            acme && port == args.acme_http_port,
+            Some(https_port),
+        ));
+    }
+    if acme && args.redirect_http != Some(args.acme_http_port) {
+        let challenge_addr = SocketAddr::new(addr.ip(), args.acme_http_port);
+        info!("Answering ACME challenges on {}", challenge_addr);
+        side_listeners.push(plain_listener(challenge_addr, true, None));
+    }
+    // Every socket is bound by now; nothing after this needs root.
+    if let Some(account) = &account
+        && let Err(e) = account.switch()
+    {
+        error!("Failed to drop privileges: {}", e);
         std::process::exit(1);
+    }
+    for side_listener in side_listeners {
+        tokio::spawn(side_listener);
+    }
+    let config = SharedConfig::new(config);
+    if args.config.is_some() || args.auth_file.is_some() || args.write_auth_file.is_some() {
+        tokio::spawn(reload_on_hangup(config.clone(), args.tmpfs));
+    }
+    let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        systemd::notify("STOPPING=1");
+        info!(
+            "Shutting down, draining connections for up to {}s",
+            args.drain_timeout
+        );
+        let _ = stop_tx.send(true);
     });
-    info!("Starting server on {}", addr);
-    let root_dir = Arc::new(args.root.clone());
-
-    let arc_base64_auth = Arc::new(base64_auth);
-    let make_svc = make_service_fn(|_conn: &AddrStream| {
-        let remote_addr = _conn.remote_addr();
-        let root_dir = root_dir.clone();
-        let arc_base64_auth = arc_base64_auth.clone();
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                handler::handle_requests(
-                    req,
-                    remote_addr.clone(),
-                    Arc::clone(&root_dir),
-                    Arc::clone(&arc_base64_auth),
-                    args.upload,
-                )
-            }))
+    let stopped = |mut stop: tokio::sync::watch::Receiver<bool>| async move {
+        let _ = stop.wait_for(|stop| *stop).await;
+    };
+    let drain_expired = async {
+        stopped(stop_rx.clone()).await;
+        tokio::select! {
+            _ = tokio::time::sleep(std::time::Duration::from_secs(args.drain_timeout)) => {
+                warn!("Drain timeout reached, closing remaining connections");
+            }
+            _ = shutdown_signal() => warn!("Second shutdown signal, closing remaining connections"),
         }
+    };
+    // All listeners share the configuration, and with it every piece of
+    // handler state.
+    let servers = listeners.into_iter().map(|listener| {
+        run_server(
+            listener,
+            config.clone(),
+            tls.clone(),
+            stopped(stop_rx.clone()),
+        )
     });
-    if let Err(e) = Server::bind(&addr).serve(make_svc).await {
-        error!("Server Error: {}",e);
+    systemd::notify("READY=1");
+    let code = tokio::select! {
+        result = futures_util::future::try_join_all(servers) => match result {
+            Ok(_) => {
+                info!("All connections drained");
+                0
+            }
+            Err(e) => {
+                error!("Server Error: {}", e);
+                1
+            }
+        },
+        _ = drain_expired => 1,
+    };
+    if let Some(stats_file) = &stats_file {
+        stats::save(stats_file);
+    }
+    if let Some(path) = &args.unix_socket {
+        let _ = std::fs::remove_file(path);
+    }
+    std::process::exit(code);
+}
+
+/// Loads or orders the ACME certificate in the background. Handshakes fail
+/// until a certificate is on hand.
+fn start_acme(
+    args: &Args,
+    client_auth: Option<Arc<dyn tokio_rustls::rustls::server::danger::ClientCertVerifier>>,
+) -> TlsAcceptor {
+    let state_dir = args
+        .acme_state_dir
+        .clone()
+        .expect("required by --acme-domain");
+    // The key must never be downloadable from the share itself.
+    if let (Ok(root), Ok(state)) = (
+        std::fs::canonicalize(&args.root),
+        std::path::absolute(&state_dir),
+    ) && state.starts_with(root)
+    {
+        error!(
+            "--acme-state-dir must be outside the root directory | dir: {:?}",
+            state_dir
+        );
         std::process::exit(1);
+    }
+    let directory_url = if args.acme_staging {
+        instant_acme::LetsEncrypt::Staging.url().to_string()
+    } else {
+        args.acme_directory.clone()
+    };
+    let manager = acme::Manager::new(
+        args.acme_domain.clone(),
+        args.acme_email.clone(),
+        directory_url,
+        state_dir,
+    )
+    .unwrap_or_else(|e| {
+        error!("ACME setup failed: {}", e);
+        std::process::exit(1);
+    });
+    let acceptor = tls::resolver_acceptor(manager.store(), client_auth);
+    tokio::spawn(manager.maintain());
+    acceptor
+}
+
+/// Applies `--sandbox`, leaving room for the files outside the root that
+/// the server still reads or writes. Under a chroot the root becomes `/`.
+fn enter_sandbox(args: &mut Args) {
+    let root = std::fs::canonicalize(&args.root).unwrap_or_else(|e| {
+        error!("Invalid root directory {:?}: {}", args.root, e);
+        std::process::exit(1);
+    });
+    // Stats are written at exit, when the file can no longer be created.
+    let stats_file = args
+        .download_stats_file
+        .clone()
+        .filter(|_| args.download_stats);
+    if let Some(path) = &stats_file
+        && let Err(e) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+    {
+        error!("Failed to create {:?}: {}", path, e);
+        std::process::exit(1);
+    }
+    // Error pages are looked up below the root unless given as absolute paths.
+    let error_pages = args
+        .error_pages
+        .iter()
+        .map(|(_, page)| page)
+        .filter(|page| page.is_absolute());
+    let paths = sandbox::Paths {
+        writable_root: args.upload,
+        served: args
+            .mounts
+            .iter()
+            .map(|mount| {
+                (
+                    mount.dir.clone().into(),
+                    mount.upload.unwrap_or(args.upload),
+                )
+            })
+            .chain(args.vhosts.iter().map(|(_, dir)| (dir.into(), args.upload)))
+            .collect(),
+        readable: [
+            &args.config,
+            &args.auth_page,
+            &args.welcome_page,
+            &args.tls_cert,
+            &args.tls_key,
+            &args.tls_client_ca,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(error_pages)
+        .chain(&args.plugins)
+        .cloned()
+        .collect(),
+        writable: stats_file.into_iter().collect(),
+        sockets: args.unix_socket.iter().cloned().collect(),
+    };
+    match sandbox::enter(&root, &paths) {
+        Ok(sandbox::Sandbox::Landlock) => {
+            info!("Sandboxed with Landlock | root: {:?}", root);
+            args.root = root.to_string_lossy().into_owned();
+        }
+        Ok(sandbox::Sandbox::Chroot) if args.exit_on_root_loss => {
+            error!("--exit-on-root-loss cannot watch the root from inside a chroot");
+            std::process::exit(1);
+        }
+        Ok(sandbox::Sandbox::Chroot) => {
+            info!("Sandboxed with chroot | root: {:?}", root);
+            args.root = "/".to_string();
+        }
+        Err(e) => {
+            error!("Failed to sandbox: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Binds a side listener right away, so it happens before privileges are
+/// dropped.
+fn bind_plain(addr: SocketAddr) -> AddrIncoming {
+    AddrIncoming::bind(&addr).unwrap_or_else(|e| {
+        error!("Failed to bind {}: {}", addr, e);
+        std::process::exit(1);
+    })
+}
+
+/// Binds a `redirect::serve` listener; the returned future runs it for the
+/// life of the process.
+fn plain_listener(
+    addr: SocketAddr,
+    acme: bool,
+    https_port: Option<u16>,
+) -> futures_util::future::BoxFuture<'static, ()> {
+    let incoming = bind_plain(addr);
+    Box::pin(async move {
+        if let Err(e) = redirect::serve(incoming, acme, https_port).await {
+            error!("HTTP listener error on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    })
+}
+
+/// Names a self-signed certificate is issued for: loopback, this host and
+/// the bind addresses that are specific ones.
+fn certificate_names(addrs: &[SocketAddr]) -> Vec<String> {
+    let mut names = vec![
+        "localhost".to_string(),
+        "127.0.0.1".to_string(),
+        "::1".to_string(),
+    ];
+    if let Ok(host) = hostname::get() {
+        names.push(host.to_string_lossy().into_owned());
+    }
+    for addr in addrs {
+        let ip = addr.ip().to_canonical();
+        if !ip.is_unspecified() && !ip.is_loopback() && !names.contains(&ip.to_string()) {
+            names.push(ip.to_string());
+        }
+    }
+    names.dedup();
+    names
+}
+
+/// How often `--exit-on-root-loss` checks that the root is still there.
+const ROOT_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Exits the process once the root directory is gone, e.g. when the drive
+/// or mount it lives on is removed.
+async fn watch_root(root: String, stats_file: Option<std::path::PathBuf>) {
+    let mut interval = tokio::time::interval(ROOT_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let problem = match tokio::fs::metadata(&root).await {
+            Ok(meta) if meta.is_dir() || archive::is_archive(std::path::Path::new(&root)) => {
+                continue;
+            }
+            Ok(_) => "not a directory".to_string(),
+            Err(e) => e.to_string(),
+        };
+        error!(
+            "Root directory lost, exiting | root: {:?} | error: {}",
+            root, problem
+        );
+        if let Some(stats_file) = &stats_file {
+            stats::save(stats_file);
+        }
+        std::process::exit(1);
+    }
+}
+
+/// Re-reads the command line, `--config` and `--auth-file` on SIGHUP and swaps in
+/// the new handler settings (credentials, header policies, ...). Listener
+/// and TLS options keep their startup values; connections stay open, and
+/// so do the files uploaded to `--tmpfs`.
+#[cfg(unix)]
+async fn reload_on_hangup(config: SharedConfig, tmpfs: bool) {
+    use tokio::signal::unix::{SignalKind, signal};
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!(
+                "Cannot listen for SIGHUP, reloading is disabled | error: {}",
+                e
+            );
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        let reloaded = Args::load_from(std::env::args_os().collect())
+            .map_err(|e| e.to_string().trim_end().to_string())
+            .and_then(|args| {
+                if args.tmpfs != tmpfs {
+                    return Err("--tmpfs cannot be switched by a reload".to_string());
+                }
+                Config::from_args(&args)
+            });
+        match reloaded {
+            Ok(mut new) => {
+                if tmpfs {
+                    new.storage = Arc::clone(&config.current().storage);
+                }
+                if let (Some(old), Some(sessions)) = (&config.current().sessions, &new.sessions) {
+                    new.sessions = Some(Arc::new(sessions.with_key_of(old)));
+                }
+                new.lockout = Arc::new(new.lockout.with_clients_of(&config.current().lockout));
+                if let (Some(old), Some(limiter)) =
+                    (&config.current().rate_limiter, &new.rate_limiter)
+                {
+                    new.rate_limiter = Some(Arc::new(limiter.with_buckets_of(old)));
+                }
+                // Transfers under way keep the old schedule; share it when the rate is unchanged.
+                if let (Some(old), Some(bandwidth)) = (&config.current().bandwidth, &new.bandwidth)
+                    && old.bytes_per_second() == bandwidth.bytes_per_second()
+                {
+                    new.bandwidth = Some(Arc::clone(old));
+                }
+                config.replace(new);
+                info!("Configuration reloaded");
+            }
+            Err(e) => error!(
+                "Configuration reload failed, keeping the current one | error: {}",
+                e
+            ),
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_on_hangup(_config: SharedConfig, _tmpfs: bool) {}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
     };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
 }
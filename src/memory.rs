@@ -0,0 +1,298 @@
+//! `--tmpfs`: an in-memory tree that uploads land in and are served back
+//! from, for drop-boxes that must not write to disk. Everything is gone
+//! when the server stops.
+
+use crate::archive::entry_name;
+use crate::storage::{self, ByteStream, DirEntry, Metadata, Staged, Storage};
+use futures_util::StreamExt;
+use futures_util::future::BoxFuture;
+use hyper::body::Bytes;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+#[derive(Debug, Clone)]
+struct Node {
+    /// `None` for a directory.
+    data: Option<Bytes>,
+    modified: SystemTime,
+}
+
+impl Node {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            is_dir: self.data.is_none(),
+            len: self.data.as_ref().map_or(0, |data| data.len() as u64),
+            modified: Some(self.modified),
+        }
+    }
+}
+
+/// Files kept in memory, served as if they were below `root`.
+#[derive(Debug)]
+pub struct MemoryStorage {
+    root: PathBuf,
+    /// Files and directories by their `/`-separated path; the top level is
+    /// `""`.
+    nodes: RwLock<BTreeMap<String, Node>>,
+}
+
+impl MemoryStorage {
+    /// An empty tree.
+    pub fn new(root: &Path) -> MemoryStorage {
+        let top = Node {
+            data: None,
+            modified: SystemTime::now(),
+        };
+        MemoryStorage {
+            root: root.to_path_buf(),
+            nodes: RwLock::new(BTreeMap::from([(String::new(), top)])),
+        }
+    }
+
+    fn name(&self, path: &Path) -> io::Result<String> {
+        let relative = path.strip_prefix(&self.root).map_err(|_| not_found())?;
+        entry_name(relative).ok_or_else(not_found)
+    }
+
+    fn node(&self, path: &Path) -> io::Result<Node> {
+        let name = self.name(path)?;
+        self.nodes
+            .read()
+            .unwrap()
+            .get(&name)
+            .cloned()
+            .ok_or_else(not_found)
+    }
+
+    /// Stores `data` as the file `path`, creating its parent directories.
+    fn insert(&self, path: &Path, data: Bytes) -> io::Result<()> {
+        let name = self.name(path)?;
+        if name.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                "is a directory",
+            ));
+        }
+        let now = SystemTime::now();
+        let mut nodes = self.nodes.write().unwrap();
+        let mut parents = Vec::new();
+        let mut parent = name.as_str();
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            parents.push(dir);
+            parent = dir;
+        }
+        for dir in &parents {
+            if nodes.get(*dir).is_some_and(|node| node.data.is_some()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotADirectory,
+                    "a parent is a file",
+                ));
+            }
+        }
+        if nodes.get(&name).is_some_and(|node| node.data.is_none()) {
+            return Err(io::Error::new(
+                io::ErrorKind::IsADirectory,
+                "is a directory",
+            ));
+        }
+        for dir in parents {
+            let node = nodes.entry(dir.to_string()).or_insert(Node {
+                data: None,
+                modified: now,
+            });
+            node.modified = now;
+        }
+        nodes.insert(
+            name,
+            Node {
+                data: Some(data),
+                modified: now,
+            },
+        );
+        Ok(())
+    }
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file")
+}
+
+impl Storage for MemoryStorage {
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Metadata>> {
+        Box::pin(async move { self.node(path).map(|node| node.metadata()) })
+    }
+
+    fn list<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<DirEntry>>> {
+        Box::pin(async move {
+            let name = self.name(path)?;
+            let nodes = self.nodes.read().unwrap();
+            match nodes.get(&name) {
+                None => return Err(not_found()),
+                Some(node) if node.data.is_some() => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotADirectory,
+                        "not a directory",
+                    ));
+                }
+                Some(_) => {}
+            }
+            let prefix = if name.is_empty() {
+                name
+            } else {
+                format!("{}/", name)
+            };
+            Ok(nodes
+                .range(prefix.clone()..)
+                .take_while(|(child, _)| child.starts_with(&prefix))
+                .filter_map(|(child, node)| {
+                    let child = &child[prefix.len()..];
+                    (!child.is_empty() && !child.contains('/')).then(|| DirEntry {
+                        name: child.into(),
+                        metadata: Some(node.metadata()),
+                    })
+                })
+                .collect())
+        })
+    }
+
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<ByteStream>> {
+        self.read_range(path, 0, u64::MAX)
+    }
+
+    fn read_range<'a>(
+        &'a self,
+        path: &'a Path,
+        start: u64,
+        len: u64,
+    ) -> BoxFuture<'a, io::Result<ByteStream>> {
+        Box::pin(async move {
+            let data = self
+                .node(path)?
+                .data
+                .ok_or_else(|| io::Error::new(io::ErrorKind::IsADirectory, "is a directory"))?;
+            let start = data.len().min(start as usize);
+            let end = data.len().min(start.saturating_add(len as usize));
+            Ok(futures_util::stream::iter([Ok(data.slice(start..end))]).boxed())
+        })
+    }
+
+    fn write<'a>(&'a self, path: &'a Path, data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        Box::pin(async move {
+            let data = Bytes::from(storage::collect(data).await?);
+            let size = data.len() as u64;
+            self.insert(path, data).map(|()| size)
+        })
+    }
+
+    /// Moves a file, or a directory with everything below it.
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let (from, to) = (self.name(from)?, self.name(to)?);
+            let prefix = format!("{}/", from);
+            if from.is_empty() || to.is_empty() || to.starts_with(&prefix) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "cannot move a directory into itself",
+                ));
+            }
+            let mut nodes = self.nodes.write().unwrap();
+            let parent = to.rsplit_once('/').map_or("", |(dir, _)| dir);
+            if nodes.get(parent).is_none_or(|node| node.data.is_some()) {
+                return Err(not_found());
+            }
+            if nodes.get(&to).is_some_and(|node| node.data.is_none()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::IsADirectory,
+                    "is a directory",
+                ));
+            }
+            let node = nodes.remove(&from).ok_or_else(not_found)?;
+            let children: Vec<String> = nodes
+                .range(prefix.clone()..)
+                .take_while(|(child, _)| child.starts_with(&prefix))
+                .map(|(child, _)| child.clone())
+                .collect();
+            for child in children {
+                let moved = nodes.remove(&child).unwrap();
+                nodes.insert(format!("{}/{}", to, &child[prefix.len()..]), moved);
+            }
+            nodes.insert(to, node);
+            Ok(())
+        })
+    }
+
+    fn staging_dir(&self, _dir: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    fn persist<'a>(&'a self, staged: Staged, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            match staged {
+                Staged::Memory(data) => self.insert(path, data),
+                Staged::File(temp_path) => {
+                    let result = tokio::fs::read(&temp_path).await;
+                    let _ = tokio::fs::remove_file(&temp_path).await;
+                    self.insert(path, Bytes::from(result?))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::collect;
+
+    #[tokio::test]
+    async fn keeps_files_in_memory() {
+        let root = Path::new("/drop");
+        let storage = MemoryStorage::new(root);
+        assert!(storage.list(root).await.unwrap().is_empty());
+        let upload = Staged::Memory(Bytes::from_static(b"hello memory"));
+        storage
+            .persist(upload, &root.join("a/b.txt"))
+            .await
+            .unwrap();
+
+        assert!(storage.metadata(&root.join("a")).await.unwrap().is_dir);
+        let entries = storage.list(&root.join("a")).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "b.txt");
+        assert_eq!(entries[0].metadata.unwrap().len, 12);
+        assert_eq!(storage.dir_size(root, None).await, 12);
+        assert_eq!(storage.dir_size(root, Some(1)).await, 0);
+        let body = storage
+            .read_range(&root.join("a/b.txt"), 6, 6)
+            .await
+            .unwrap();
+        assert_eq!(collect(body).await.unwrap(), b"memory");
+        let upload = Staged::Memory(Bytes::from_static(b"x"));
+        assert!(storage.persist(upload, &root.join("a")).await.is_err());
+        let upload = Staged::Memory(Bytes::from_static(b"x"));
+        assert!(
+            storage
+                .persist(upload, &root.join("a/b.txt/c"))
+                .await
+                .is_err()
+        );
+        assert!(storage.metadata(&root.join("../etc")).await.is_err());
+
+        storage
+            .rename(&root.join("a"), &root.join("c"))
+            .await
+            .unwrap();
+        assert!(!storage.exists(&root.join("a/b.txt")).await);
+        assert_eq!(
+            storage.metadata(&root.join("c/b.txt")).await.unwrap().len,
+            12
+        );
+        let into_itself = storage.rename(&root.join("c"), &root.join("c/d")).await;
+        assert!(into_itself.is_err());
+        let no_parent = storage.rename(&root.join("c"), &root.join("x/y")).await;
+        assert_eq!(no_parent.unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+}
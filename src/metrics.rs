@@ -0,0 +1,144 @@
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode, header};
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Process-wide counters exported on the Prometheus endpoint.
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    requests_by_status: Mutex<BTreeMap<u16, u64>>,
+    bytes_served: AtomicU64,
+    uploads: AtomicU64,
+    auth_failures: AtomicU64,
+    active_connections: AtomicI64,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            requests_by_status: Mutex::new(BTreeMap::new()),
+            bytes_served: AtomicU64::new(0),
+            uploads: AtomicU64::new(0),
+            auth_failures: AtomicU64::new(0),
+            active_connections: AtomicI64::new(0),
+        }
+    }
+
+    /// Records a finished response. `bytes` is the declared Content-Length,
+    /// which is what a streamed body will send if the client reads it all.
+    pub fn record_response(&self, status: StatusCode, bytes: u64) {
+        *self
+            .requests_by_status
+            .lock()
+            .unwrap()
+            .entry(status.as_u16())
+            .or_default() += 1;
+        self.bytes_served.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn record_upload(&self) {
+        self.uploads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_auth_failure(&self) {
+        self.auth_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a connection as active until the returned guard is dropped.
+    pub fn connection(&'static self) -> ConnectionGuard {
+        self.active_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self)
+    }
+
+    /// Renders all metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP samserve_requests_total Requests served, by response status.\n");
+        out.push_str("# TYPE samserve_requests_total counter\n");
+        for (status, count) in self.requests_by_status.lock().unwrap().iter() {
+            let _ = writeln!(
+                out,
+                "samserve_requests_total{{status=\"{}\"}} {}",
+                status, count
+            );
+        }
+        let scalars = [
+            (
+                "samserve_bytes_served_total",
+                "counter",
+                "Response body bytes announced to clients.",
+                self.bytes_served.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "samserve_uploads_total",
+                "counter",
+                "Files stored through uploads.",
+                self.uploads.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "samserve_auth_failures_total",
+                "counter",
+                "Requests rejected by authentication.",
+                self.auth_failures.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "samserve_active_connections",
+                "gauge",
+                "Currently open client connections.",
+                self.active_connections.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, kind, help, value) in scalars {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
+
+pub struct ConnectionGuard(&'static Metrics);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.active_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub fn metrics_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+        .header(header::ACCEPT_RANGES, "none")
+        .body(Body::from(METRICS.render()))
+        .unwrap()
+}
+
+/// Serves only the metrics endpoint on a dedicated admin listener.
+pub async fn serve_admin(incoming: AddrIncoming, path: String) -> Result<(), hyper::Error> {
+    let path = Arc::new(path);
+    let make_svc = make_service_fn(move |_conn: &AddrStream| {
+        let path = Arc::clone(&path);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let found = req.uri().path() == path.as_str();
+                async move {
+                    if found {
+                        Ok::<_, Infallible>(metrics_response())
+                    } else {
+                        Ok(Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::from("Not found"))
+                            .unwrap())
+                    }
+                }
+            }))
+        }
+    });
+    Server::builder(incoming).serve(make_svc).await
+}
@@ -0,0 +1,198 @@
+//! Which paths below the root clients may see. Paths here are relative to
+//! the root and start with `/`, like request paths after decoding.
+
+use crate::config::Config;
+use std::path::Path;
+
+/// Whether `path` is kept from clients: it is, or lies inside, a path
+/// matching an `--exclude` pattern, or a dotfile that no `--dotfile-allow`
+/// pattern lets through (unless `--hidden` is given). Hidden paths are
+/// neither listed, served nor written to.
+pub fn is_hidden(path: &str, config: &Config) -> bool {
+    let mut prefix = String::new();
+    for part in path
+        .split('/')
+        // `..` is left to the traversal check, which refuses it outright.
+        .filter(|part| !matches!(*part, "" | "." | ".."))
+    {
+        prefix.push('/');
+        prefix.push_str(part);
+        let dotfile = !config.hidden
+            && part.starts_with('.')
+            && !config
+                .dotfile_allow
+                .iter()
+                .any(|pattern| matches(pattern, &prefix));
+        if dotfile
+            || config
+                .exclude
+                .iter()
+                .any(|pattern| matches(pattern, &prefix))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// Whether `path` needs authentication: it does unless `--protect` patterns
+/// are given, and then only when it, or a directory it lies in, matches one.
+/// `/private/**` covers `/private` itself as well as everything inside.
+pub fn is_protected(path: &str, config: &Config) -> bool {
+    if config.protect.is_empty() {
+        return true;
+    }
+    let protects = |prefix: &str| {
+        config
+            .protect
+            .iter()
+            .any(|pattern| matches(pattern, prefix) || matches(pattern, &format!("{}/", prefix)))
+    };
+    // `..` is resolved here rather than refused, so it cannot step around
+    // a pattern.
+    let mut parts = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    (0..=parts.len()).any(|depth| {
+        let prefix: String = parts[..depth]
+            .iter()
+            .map(|part| format!("/{}", part))
+            .collect();
+        protects(&prefix)
+    })
+}
+
+/// `path`, which lies below `root`, in the form the filters take
+/// (`/docs/notes.txt`).
+pub fn relative_path(path: &Path, root: &str) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    Some(
+        relative
+            .components()
+            .map(|part| format!("/{}", part.as_os_str().to_string_lossy()))
+            .collect::<String>(),
+    )
+}
+
+/// Reads the patterns of an ignore file: one per line, with blank lines
+/// and `#` comments skipped.
+pub fn parse_ignore_file(text: &str) -> Vec<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Matches `path` against a glob. Patterns containing `/` match the whole
+/// path (`/assets/*`; the leading `/` is optional), others only the last
+/// component (`*.css`).
+pub fn matches(pattern: &str, path: &str) -> bool {
+    if pattern.contains('/') {
+        let path = path.strip_prefix('/').unwrap_or(path);
+        let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+        glob_match(pattern.as_bytes(), path.as_bytes())
+    } else {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        glob_match(pattern.as_bytes(), name.as_bytes())
+    }
+}
+
+/// Matches `text` against a pattern where `*` stands for any run of
+/// characters (`/` included) and `?` for any single one.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // Where the last `*` was seen, and how much of `text` it has swallowed.
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some(b'*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == b'?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star, swallowed)) => {
+                    p = star + 1;
+                    t = swallowed + 1;
+                    backtrack = Some((star, swallowed + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_dotfiles_outside_the_allowlist() {
+        let config = Config::default();
+        assert!(is_hidden("/.env", &config));
+        assert!(is_hidden("/project/.git/config", &config));
+        assert!(!is_hidden("/docs/./notes.txt", &config));
+        assert!(!is_hidden("/.well-known/acme-challenge/token", &config));
+        assert!(is_hidden("/.well-known/.secret", &config));
+
+        let config = Config {
+            dotfile_allow: vec!["/public/.*".to_string()],
+            ..Config::default()
+        };
+        assert!(!is_hidden("/public/.htaccess", &config));
+        assert!(is_hidden("/.well-known/security.txt", &config));
+
+        let config = Config {
+            hidden: true,
+            ..Config::default()
+        };
+        assert!(!is_hidden("/.env", &config));
+    }
+
+    #[test]
+    fn protects_matching_subtrees() {
+        assert!(is_protected("/anything", &Config::default()));
+        let config = Config {
+            protect: vec!["/private/**".to_string(), "*.key".to_string()],
+            ..Config::default()
+        };
+        assert!(is_protected("/private", &config));
+        assert!(is_protected("/private/", &config));
+        assert!(is_protected("/private/a/b.txt", &config));
+        assert!(is_protected("/docs/server.key/", &config));
+        assert!(is_protected("/public/../private/x", &config));
+        assert!(!is_protected("/", &config));
+        assert!(!is_protected("/privateer.txt", &config));
+        assert!(!is_protected("/docs/readme.txt", &config));
+        let config = Config {
+            protect: vec!["/**".to_string()],
+            ..Config::default()
+        };
+        assert!(is_protected("/", &config));
+    }
+
+    #[test]
+    fn hides_excluded_paths() {
+        let config = Config {
+            exclude: parse_ignore_file("# keys\n*.key\n\nsecrets/**\nbuild\n"),
+            ..Config::default()
+        };
+        assert!(is_hidden("/tls/server.key", &config));
+        assert!(is_hidden("/secrets/db/password.txt", &config));
+        assert!(is_hidden("/app/build/out.js", &config));
+        assert!(!is_hidden("/secrets", &config));
+        assert!(!is_hidden("/docs/key.txt", &config));
+    }
+}
@@ -0,0 +1,247 @@
+//! `--plugin`: WebAssembly modules that hook into request handling, so
+//! custom rules do not need a fork.
+//!
+//! A plugin is a core WebAssembly module exporting `memory` and
+//! `alloc(len: i32) -> i32`, which returns room for `len` bytes the server
+//! writes a hook's JSON input into. Each hook is optional:
+//!
+//! - `on_request(ptr: i32, len: i32) -> i32` gets
+//!   `{"method", "path", "query", "headers", "remote"}` and returns 0 to
+//!   let the request through, or the status to refuse it with.
+//! - `on_upload(ptr: i32, len: i32) -> i32` gets `{"path", "size"}` once an
+//!   upload is received and returns 0 to store it, or the status to refuse
+//!   it with.
+//! - `on_listing(ptr: i32, len: i32) -> i64` gets `{"path", "entries"}` and
+//!   returns 0 to keep the listing, or `ptr << 32 | len` of a JSON array
+//!   naming the entries to show, in order.
+//!
+//! Plugins run without imports, so they cannot reach the filesystem or the
+//! network, and each call is stopped after `FUEL_PER_CALL` instructions.
+
+use hyper::{Body, Request, StatusCode};
+use log::{error, warn};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use wasmi::{Engine, Instance, Linker, Memory, Module, Store};
+
+/// Roughly how many instructions one hook call may run.
+const FUEL_PER_CALL: u64 = 50_000_000;
+
+pub struct Plugin {
+    path: PathBuf,
+    state: Mutex<(Store<()>, Instance, Memory)>,
+}
+
+impl std::fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugin").field("path", &self.path).finish()
+    }
+}
+
+impl Plugin {
+    /// Compiles and instantiates the module at `path`.
+    pub fn load(path: &Path) -> Result<Arc<Plugin>, String> {
+        let wasm = std::fs::read(path).map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+        Self::from_bytes(path, &wasm)
+    }
+
+    fn from_bytes(path: &Path, wasm: &[u8]) -> Result<Arc<Plugin>, String> {
+        let invalid = |e: &dyn std::fmt::Display| format!("invalid plugin {:?}: {}", path, e);
+        let mut settings = wasmi::Config::default();
+        settings.consume_fuel(true);
+        let engine = Engine::new(&settings);
+        let module = Module::new(&engine, wasm).map_err(|e| invalid(&e))?;
+        let mut store = Store::new(&engine, ());
+        store.set_fuel(FUEL_PER_CALL).map_err(|e| invalid(&e))?;
+        let instance = Linker::<()>::new(&engine)
+            .instantiate(&mut store, &module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|e| invalid(&e))?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| invalid(&"no exported memory"))?;
+        instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| invalid(&e))?;
+        Ok(Arc::new(Plugin {
+            path: path.to_path_buf(),
+            state: Mutex::new((store, instance, memory)),
+        }))
+    }
+
+    /// Calls `hook` with `input`, or returns `None` when the module does
+    /// not export it. Traps and exhausted fuel are errors.
+    fn call<R: wasmi::WasmResults>(&self, hook: &str, input: &[u8]) -> Option<Result<R, String>> {
+        let mut state = self.state.lock().unwrap();
+        let (store, instance, memory) = &mut *state;
+        let func = instance
+            .get_typed_func::<(i32, i32), R>(&*store, hook)
+            .ok()?;
+        let result = store
+            .set_fuel(FUEL_PER_CALL)
+            .map_err(|e| e.to_string())
+            .and_then(|()| write_input(store, instance, memory, input))
+            .and_then(|args| func.call(&mut *store, args).map_err(|e| e.to_string()));
+        Some(result)
+    }
+
+    /// Reads `len` bytes at `ptr` from the module's memory.
+    fn read(&self, ptr: u32, len: u32) -> Result<Vec<u8>, String> {
+        let state = self.state.lock().unwrap();
+        let (store, _, memory) = &*state;
+        let mut buffer = vec![0; len as usize];
+        memory
+            .read(store, ptr as usize, &mut buffer)
+            .map_err(|e| e.to_string())?;
+        Ok(buffer)
+    }
+
+    /// Turns a status a hook returned into a refusal, if it is one.
+    fn verdict(&self, hook: &str, result: Option<Result<i32, String>>) -> Option<StatusCode> {
+        match result? {
+            Ok(0) => None,
+            Ok(code) => Some(
+                u16::try_from(code)
+                    .ok()
+                    .and_then(|code| StatusCode::from_u16(code).ok())
+                    .filter(|status| status.is_client_error() || status.is_server_error())
+                    .unwrap_or_else(|| {
+                        warn!(
+                            "Plugin returned an invalid status | plugin: {:?} | hook: {} | status: {}",
+                            self.path, hook, code
+                        );
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    }),
+            ),
+            Err(err) => {
+                error!(
+                    "Plugin failed | plugin: {:?} | hook: {} | error: {}",
+                    self.path, hook, err
+                );
+                Some(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+/// Copies `input` into memory the module allocates, returning the
+/// `(ptr, len)` arguments hooks take.
+fn write_input(
+    store: &mut Store<()>,
+    instance: &Instance,
+    memory: &Memory,
+    input: &[u8],
+) -> Result<(i32, i32), String> {
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&*store, "alloc")
+        .map_err(|e| e.to_string())?;
+    let len = i32::try_from(input.len()).map_err(|e| e.to_string())?;
+    let ptr = alloc.call(&mut *store, len).map_err(|e| e.to_string())?;
+    memory
+        .write(&mut *store, ptr as u32 as usize, input)
+        .map_err(|e| e.to_string())?;
+    Ok((ptr, len))
+}
+
+/// Asks each plugin's `on_request` about `req`; the first refusal wins.
+pub fn on_request(
+    plugins: &[Arc<Plugin>],
+    req: &Request<Body>,
+    remote_addr: SocketAddr,
+) -> Option<StatusCode> {
+    if plugins.is_empty() {
+        return None;
+    }
+    let headers: serde_json::Map<String, serde_json::Value> = req
+        .headers()
+        .iter()
+        .filter(|(name, _)| **name != hyper::header::AUTHORIZATION)
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.into())))
+        .collect();
+    let input = serde_json::json!({
+        "method": req.method().as_str(),
+        "path": req.uri().path(),
+        "query": req.uri().query(),
+        "headers": headers,
+        "remote": remote_addr.to_string(),
+    })
+    .to_string();
+    plugins.iter().find_map(|plugin| {
+        plugin.verdict("on_request", plugin.call("on_request", input.as_bytes()))
+    })
+}
+
+/// Asks each plugin's `on_upload` whether a received upload of `size`
+/// bytes may be stored at `path`, below the root.
+pub fn on_upload(plugins: &[Arc<Plugin>], path: &str, size: u64) -> Option<StatusCode> {
+    if plugins.is_empty() {
+        return None;
+    }
+    let input = serde_json::json!({ "path": path, "size": size }).to_string();
+    plugins
+        .iter()
+        .find_map(|plugin| plugin.verdict("on_upload", plugin.call("on_upload", input.as_bytes())))
+}
+
+/// Passes the entry names of the listing of `path` through each plugin's
+/// `on_listing`. Directory names end in `/`.
+pub fn on_listing(plugins: &[Arc<Plugin>], path: &str, entries: Vec<String>) -> Vec<String> {
+    plugins.iter().fold(entries, |entries, plugin| {
+        let input = serde_json::json!({ "path": path, "entries": entries }).to_string();
+        let rewritten = match plugin.call::<i64>("on_listing", input.as_bytes()) {
+            None | Some(Ok(0)) => return entries,
+            Some(Ok(packed)) => plugin
+                .read((packed >> 32) as u32, packed as u32)
+                .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string())),
+            Some(Err(err)) => Err(err),
+        };
+        rewritten.unwrap_or_else(|err| {
+            error!(
+                "Plugin failed | plugin: {:?} | hook: on_listing | error: {}",
+                plugin.path, err
+            );
+            entries
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Refuses uploads over 4 bytes and lists only the first entry.
+    const PLUGIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (global $next (mut i32) (i32.const 1024))
+        (data (i32.const 0) "[\"a.txt\"]")
+        (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+        (func (export "on_upload") (param i32 i32) (result i32)
+            ;; The size is the last digit before the closing brace.
+            (if (result i32)
+                (i32.gt_u
+                    (i32.load8_u (i32.sub (i32.add (local.get 0) (local.get 1)) (i32.const 2)))
+                    (i32.const 52))
+                (then (i32.const 413))
+                (else (i32.const 0))))
+        (func (export "on_listing") (param i32 i32) (result i64)
+            (i64.const 9)))"#;
+
+    #[test]
+    fn runs_hooks() {
+        let wasm = wat::parse_str(PLUGIN).unwrap();
+        let plugins = [Plugin::from_bytes(Path::new("test.wasm"), &wasm).unwrap()];
+        assert_eq!(on_upload(&plugins, "/a.txt", 3), None);
+        assert_eq!(
+            on_upload(&plugins, "/a.txt", 9),
+            Some(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        let entries = vec!["b/".to_string(), "a.txt".to_string()];
+        assert_eq!(on_listing(&plugins, "/", entries), ["a.txt"]);
+        assert!(Plugin::from_bytes(Path::new("bad.wasm"), b"not wasm").is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Dropping root privileges once the listening sockets are bound, so
+//! samserve can take ports below 1024 without serving requests as root.
+
+#[cfg(unix)]
+use log::info;
+#[cfg(unix)]
+use std::ffi::{CStr, CString};
+
+/// The account to switch to. It is looked up before anything is bound so a
+/// typo fails fast, and before any chroot hides `/etc/passwd`.
+#[cfg(unix)]
+pub struct Account {
+    name: CString,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+}
+
+#[cfg(unix)]
+impl Account {
+    /// Resolves `user` and optionally `group`, each a name or a numeric id.
+    /// Without `group` the user's primary group is used.
+    pub fn resolve(user: &str, group: Option<&str>) -> Result<Account, String> {
+        let (name, uid, primary_gid) = lookup_user(user)?;
+        let gid = match group {
+            Some(group) => lookup_group(group)?,
+            None => primary_gid
+                .ok_or_else(|| format!("user {:?} has no primary group; pass --group", user))?,
+        };
+        Ok(Account { name, uid, gid })
+    }
+
+    /// Switches the process to this account: supplementary groups first,
+    /// then the group, then the user. Afterwards regaining root must fail.
+    pub fn switch(&self) -> Result<(), String> {
+        // SAFETY: plain libc calls; `self.name` is a valid C string.
+        unsafe {
+            if libc::geteuid() != 0 {
+                return Err("--user needs samserve to be started as root".to_string());
+            }
+            if libc::initgroups(self.name.as_ptr(), self.gid as _) != 0 {
+                return Err(format!(
+                    "initgroups failed: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            if libc::setgid(self.gid) != 0 {
+                return Err(format!(
+                    "setgid failed: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            if libc::setuid(self.uid) != 0 {
+                return Err(format!(
+                    "setuid failed: {}",
+                    std::io::Error::last_os_error()
+                ));
+            }
+            if self.uid != 0 && libc::setuid(0) == 0 {
+                return Err("root privileges could be regained after setuid".to_string());
+            }
+        }
+        info!(
+            "Dropped privileges | user: {:?} | uid: {} | gid: {}",
+            self.name, self.uid, self.gid
+        );
+        Ok(())
+    }
+}
+
+/// Returns the login name, uid and primary gid of `user`. A numeric id
+/// without a passwd entry is accepted, but has no primary group.
+#[cfg(unix)]
+fn lookup_user(user: &str) -> Result<(CString, libc::uid_t, Option<libc::gid_t>), String> {
+    let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    let mut found: *mut libc::passwd = std::ptr::null_mut();
+    let numeric = user.parse::<libc::uid_t>().ok();
+    let c_user = CString::new(user).map_err(|_| format!("invalid user {:?}", user))?;
+    // SAFETY: the buffers outlive the call and their sizes are passed along.
+    let status = unsafe {
+        match numeric {
+            Some(uid) => libc::getpwuid_r(
+                uid,
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            ),
+            None => libc::getpwnam_r(
+                c_user.as_ptr(),
+                &mut entry,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut found,
+            ),
+        }
+    };
+    if status != 0 {
+        return Err(format!(
+            "failed to look up user {:?}: {}",
+            user,
+            std::io::Error::from_raw_os_error(status)
+        ));
+    }
+    if found.is_null() {
+        return match numeric {
+            Some(uid) => Ok((c_user, uid, None)),
+            None => Err(format!("no such user {:?}", user)),
+        };
+    }
+    // SAFETY: on success `pw_name` points into `buffer`, which is still alive.
+    let name = unsafe { CStr::from_ptr(entry.pw_name) }.to_owned();
+    Ok((name, entry.pw_uid, Some(entry.pw_gid)))
+}
+
+#[cfg(unix)]
+fn lookup_group(group: &str) -> Result<libc::gid_t, String> {
+    if let Ok(gid) = group.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let mut found: *mut libc::group = std::ptr::null_mut();
+    let c_group = CString::new(group).map_err(|_| format!("invalid group {:?}", group))?;
+    // SAFETY: the buffers outlive the call and their sizes are passed along.
+    let status = unsafe {
+        libc::getgrnam_r(
+            c_group.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut found,
+        )
+    };
+    if status != 0 {
+        return Err(format!(
+            "failed to look up group {:?}: {}",
+            group,
+            std::io::Error::from_raw_os_error(status)
+        ));
+    }
+    if found.is_null() {
+        return Err(format!("no such group {:?}", group));
+    }
+    Ok(entry.gr_gid)
+}
+
+#[cfg(not(unix))]
+pub struct Account;
+
+#[cfg(not(unix))]
+impl Account {
+    pub fn resolve(_user: &str, _group: Option<&str>) -> Result<Account, String> {
+        Err("--user is only supported on Unix".to_string())
+    }
+
+    pub fn switch(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_users_and_groups() {
+        let root = Account::resolve("root", None).unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        let numeric = Account::resolve("0", Some("0")).unwrap();
+        assert_eq!(numeric.name.to_str(), Ok("root"));
+        assert!(Account::resolve("no-such-user-samserve", None).is_err());
+        assert!(Account::resolve("root", Some("no-such-group-samserve")).is_err());
+    }
+}
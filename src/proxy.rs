@@ -0,0 +1,182 @@
+//! `--proxy`: forwards requests under a URL prefix to an upstream HTTP
+//! server, so a static build can be tried against a local API.
+
+use crate::session;
+use hyper::client::HttpConnector;
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::http::uri::Authority;
+use hyper::{Body, Client, Request, Response, StatusCode, Uri};
+use log::{error, info};
+use std::net::SocketAddr;
+use std::sync::LazyLock;
+
+static CLIENT: LazyLock<Client<HttpConnector>> = LazyLock::new(Client::new);
+
+/// Headers that describe a single connection and are not forwarded.
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// A `PREFIX=URL` rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyRule {
+    pub prefix: String,
+    pub upstream: Authority,
+}
+
+/// Parses a `--proxy PREFIX=URL` argument. The URL names the upstream
+/// server only; requests keep their path.
+pub fn parse_rule(s: &str) -> Result<ProxyRule, String> {
+    let (prefix, url) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected PREFIX=URL, got {:?}", s))?;
+    let prefix = crate::config::parse_base_path(prefix)?;
+    if prefix.is_empty() {
+        return Err("--proxy prefix must not be /".to_string());
+    }
+    let uri = url
+        .trim()
+        .parse::<Uri>()
+        .map_err(|_| format!("{:?} is not a valid URL", url))?;
+    if uri.scheme_str() != Some("http") {
+        return Err(format!("expected an http:// upstream, got {:?}", url));
+    }
+    if uri.path() != "/" || uri.query().is_some() {
+        return Err(format!(
+            "expected an upstream without a path like http://127.0.0.1:3000, got {:?}",
+            url
+        ));
+    }
+    match uri.into_parts().authority {
+        Some(upstream) => Ok(ProxyRule { prefix, upstream }),
+        None => Err(format!("{:?} has no host", url)),
+    }
+}
+
+/// The rule for `path`, if it lies under one of the prefixes; `rules` are
+/// sorted longest prefix first.
+pub fn route<'a>(rules: &'a [ProxyRule], path: &str) -> Option<&'a ProxyRule> {
+    rules.iter().find(|rule| {
+        path.strip_prefix(&rule.prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Sends `req` upstream and hands back its response; bodies are streamed
+/// in both directions. The session cookie never leaves the server, and
+/// neither does `Authorization` when `own_credentials` says it was checked
+/// here.
+pub async fn forward(
+    mut req: Request<Body>,
+    rule: &ProxyRule,
+    own_credentials: bool,
+    remote_addr: SocketAddr,
+) -> Response<Body> {
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map_or("/", |p| p.as_str())
+        .to_string();
+    let uri = Uri::builder()
+        .scheme("http")
+        .authority(rule.upstream.clone())
+        .path_and_query(path_and_query)
+        .build()
+        .unwrap();
+    let original_uri = std::mem::replace(req.uri_mut(), uri);
+    // Upstream always gets HTTP/1.1, whatever the client spoke.
+    *req.version_mut() = hyper::Version::HTTP_11;
+    let headers = req.headers_mut();
+    remove_hop_by_hop(headers);
+    session::remove_cookie(headers);
+    if own_credentials {
+        headers.remove(header::AUTHORIZATION);
+    }
+    if let Some(host) = headers.remove(header::HOST) {
+        headers.insert(HeaderName::from_static("x-forwarded-host"), host);
+    }
+    headers.insert(
+        header::HOST,
+        HeaderValue::from_str(rule.upstream.as_str()).unwrap(),
+    );
+    let forwarded_for = match headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+        Some(chain) => format!("{}, {}", chain, remote_addr.ip()),
+        None => remote_addr.ip().to_string(),
+    };
+    headers.insert(
+        HeaderName::from_static("x-forwarded-for"),
+        HeaderValue::from_str(&forwarded_for).unwrap(),
+    );
+
+    match CLIENT.request(req).await {
+        Ok(mut response) => {
+            info!(
+                "Proxied | uri: {} | upstream: {} | status: {} | remote: {}",
+                original_uri,
+                rule.upstream,
+                response.status(),
+                remote_addr
+            );
+            remove_hop_by_hop(response.headers_mut());
+            response
+        }
+        Err(err) => {
+            error!(
+                "Proxy failed | uri: {} | upstream: {} | error: {} | status: {} | remote: {}",
+                original_uri,
+                rule.upstream,
+                err,
+                StatusCode::BAD_GATEWAY,
+                remote_addr
+            );
+            Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(Body::from("Upstream unavailable"))
+                .unwrap()
+        }
+    }
+}
+
+/// Removes the hop-by-hop headers, including those named in `Connection`.
+fn remove_hop_by_hop(headers: &mut header::HeaderMap) {
+    let named: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+    for name in named {
+        headers.remove(name);
+    }
+    for name in HOP_BY_HOP {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_and_routes_rules() {
+        let api = parse_rule("/api=http://127.0.0.1:3000").unwrap();
+        assert_eq!(api.prefix, "/api");
+        assert_eq!(api.upstream.as_str(), "127.0.0.1:3000");
+        assert!(parse_rule("/api=https://example.com").is_err());
+        assert!(parse_rule("/api=http://127.0.0.1:3000/v1").is_err());
+        assert!(parse_rule("/=http://127.0.0.1:3000").is_err());
+
+        let rules = [api];
+        assert!(route(&rules, "/api/users").is_some());
+        assert!(route(&rules, "/api").is_some());
+        assert!(route(&rules, "/apis").is_none());
+    }
+}
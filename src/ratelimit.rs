@@ -0,0 +1,146 @@
+//! `--rate-limit`: a token bucket per client IP address, so one client
+//! cannot take all of the server. Each request takes a token; tokens come
+//! back at the configured rate, up to the burst size.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Buckets kept before the full ones, which clients no longer need, are
+/// dropped.
+const PRUNE_AT: usize = 1024;
+
+/// Requests per second and how many may come at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Parses `10r/s`, `600r/m` or `10r/s burst=20` (also `10r/s,burst=20`).
+/// The burst defaults to one second's worth of requests.
+pub fn parse_rate_limit(s: &str) -> Result<RateLimit, String> {
+    let mut parts = s.split([' ', ',']).filter(|part| !part.is_empty());
+    let rate = parts.next().ok_or("empty rate limit")?;
+    let (count, per) = rate
+        .split_once("r/")
+        .ok_or_else(|| format!("invalid rate {:?}, expected e.g. 10r/s", rate))?;
+    let count: f64 = count
+        .parse()
+        .ok()
+        .filter(|count: &f64| count.is_finite() && *count > 0.0)
+        .ok_or_else(|| format!("invalid request count {:?}", count))?;
+    let per_second = match per {
+        "s" => count,
+        "m" => count / 60.0,
+        "h" => count / 3600.0,
+        _ => return Err(format!("invalid rate unit {:?}, expected s, m or h", per)),
+    };
+    let mut burst = per_second.ceil().max(1.0) as u32;
+    for part in parts {
+        match part.split_once('=') {
+            Some(("burst", value)) => {
+                burst = value
+                    .parse()
+                    .ok()
+                    .filter(|burst| *burst > 0)
+                    .ok_or_else(|| format!("invalid burst {:?}", value))?;
+            }
+            _ => return Err(format!("unknown rate limit option {:?}", part)),
+        }
+    }
+    Ok(RateLimit { per_second, burst })
+}
+
+#[derive(Debug, Clone)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The buckets of the clients seen recently.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> RateLimiter {
+        RateLimiter {
+            limit,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// This limit with the buckets of `old`, so a reload does not refill
+    /// them.
+    pub fn with_buckets_of(&self, old: &RateLimiter) -> RateLimiter {
+        RateLimiter {
+            limit: self.limit,
+            buckets: Mutex::new(old.buckets.lock().unwrap().clone()),
+        }
+    }
+
+    /// Takes a token for a request from `ip`, or tells how long until one
+    /// is available.
+    pub fn check(&self, ip: IpAddr) -> Result<(), Duration> {
+        let RateLimit { per_second, burst } = self.limit;
+        let burst = f64::from(burst);
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= PRUNE_AT {
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_second
+                    < burst
+            });
+        }
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() * per_second;
+        bucket.tokens = (bucket.tokens + refilled).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rates() {
+        let limit = |per_second, burst| Ok(RateLimit { per_second, burst });
+        assert_eq!(parse_rate_limit("10r/s"), limit(10.0, 10));
+        assert_eq!(parse_rate_limit("10r/s burst=20"), limit(10.0, 20));
+        assert_eq!(parse_rate_limit("30r/m,burst=5"), limit(0.5, 5));
+        assert!(parse_rate_limit("10/s").is_err());
+        assert!(parse_rate_limit("0r/s").is_err());
+        assert!(parse_rate_limit("10r/d").is_err());
+        assert!(parse_rate_limit("10r/s burst=0").is_err());
+        assert!(parse_rate_limit("10r/s size=3").is_err());
+    }
+
+    #[test]
+    fn refuses_requests_beyond_the_burst() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_second: 1.0,
+            burst: 3,
+        });
+        let (ip, other) = ("192.0.2.1".parse().unwrap(), "192.0.2.2".parse().unwrap());
+        for _ in 0..3 {
+            assert_eq!(limiter.check(ip), Ok(()));
+        }
+        let wait = limiter.check(ip).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+        assert_eq!(limiter.check(other), Ok(()));
+    }
+}
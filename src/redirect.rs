@@ -0,0 +1,109 @@
+use crate::acme;
+use crate::listener::RemoteAddr;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode, header};
+use log::{info, warn};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+/// Serves plain HTTP beside the HTTPS listener. ACME challenges are answered
+/// when `acme` is set; everything else is redirected to HTTPS on
+/// `https_port`, or gets a 404 when there is nowhere to redirect to.
+pub async fn serve(
+    incoming: AddrIncoming,
+    acme: bool,
+    https_port: Option<u16>,
+) -> Result<(), hyper::Error> {
+    let make_svc = make_service_fn(move |conn: &AddrStream| {
+        let remote_addr = RemoteAddr::remote_addr(conn);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| async move {
+                if acme && let Some(response) = acme::challenge_response(req.uri().path()) {
+                    return Ok::<_, Infallible>(response);
+                }
+                Ok(match https_port {
+                    Some(port) => redirect(&req, port, remote_addr),
+                    None => Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::from("Not found"))
+                        .unwrap(),
+                })
+            }))
+        }
+    });
+    Server::builder(incoming).serve(make_svc).await
+}
+
+fn redirect(req: &Request<Body>, port: u16, remote_addr: SocketAddr) -> Response<Body> {
+    let Some(host) = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(host_without_port)
+    else {
+        warn!(
+            "HTTPS redirect without a usable Host header | uri: {} | status: {} | remote: {}",
+            req.uri(),
+            StatusCode::BAD_REQUEST,
+            remote_addr
+        );
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(Body::from("Missing Host header"))
+            .unwrap();
+    };
+    let path = req.uri().path_and_query().map_or("/", |p| p.as_str());
+    let location = if port == 443 {
+        format!("https://{}{}", host, path)
+    } else {
+        format!("https://{}:{}{}", host, port, path)
+    };
+    info!(
+        "Redirecting to HTTPS | uri: {} | location: {:?} | status: {} | remote: {}",
+        req.uri(),
+        location,
+        StatusCode::MOVED_PERMANENTLY,
+        remote_addr
+    );
+    Response::builder()
+        .status(StatusCode::MOVED_PERMANENTLY)
+        .header(header::LOCATION, location)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Strips the port from a Host header value, keeping IPv6 literals
+/// bracketed. Values that could change the redirect's origin are rejected.
+pub fn host_without_port(host: &str) -> Option<&str> {
+    let host = if host.starts_with('[') {
+        &host[..=host.find(']')?]
+    } else {
+        host.split(':').next()?
+    };
+    let valid = !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-.[]:".contains(c));
+    valid.then_some(host)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_ports() {
+        assert_eq!(host_without_port("example.com:8080"), Some("example.com"));
+        assert_eq!(host_without_port("example.com"), Some("example.com"));
+        assert_eq!(host_without_port("[::1]:80"), Some("[::1]"));
+    }
+
+    #[test]
+    fn rejects_hosts_that_change_the_origin() {
+        assert_eq!(host_without_port("evil.com/x"), None);
+        assert_eq!(host_without_port("user@evil.com"), None);
+        assert_eq!(host_without_port(":80"), None);
+        assert_eq!(host_without_port("[::1"), None);
+    }
+}
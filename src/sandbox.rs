@@ -0,0 +1,121 @@
+//! `--sandbox`: confines the process to the served root, so a bug in path
+//! handling cannot reach files outside it. Landlock is used where the
+//! kernel supports it, otherwise the process chroots into the root, which
+//! needs samserve to be started as root.
+
+use std::path::{Path, PathBuf};
+
+/// How the sandbox was applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sandbox {
+    /// Access is limited to the root and the listed extra paths.
+    Landlock,
+    /// The root is now `/`; nothing outside it is reachable.
+    Chroot,
+}
+
+/// Files outside the root the server still needs once sandboxed.
+#[derive(Debug, Default)]
+pub struct Paths {
+    /// Whether the root itself must be writable (uploads, moves).
+    pub writable_root: bool,
+    /// Directories served besides the root (`--mount`, `--vhost`), and
+    /// whether each must be writable like it.
+    pub served: Vec<(PathBuf, bool)>,
+    pub readable: Vec<PathBuf>,
+    pub writable: Vec<PathBuf>,
+    /// Unix sockets to create; stale ones are replaced, so their directory
+    /// needs both rights.
+    pub sockets: Vec<PathBuf>,
+}
+
+/// Restricts the process to `root`, which must be canonical. Landlock only
+/// covers the calling thread and its future threads, so call this before
+/// any others are started.
+pub fn enter(root: &Path, paths: &Paths) -> Result<Sandbox, String> {
+    #[cfg(target_os = "linux")]
+    if landlock(root, paths)? {
+        return Ok(Sandbox::Landlock);
+    }
+    if let Some(path) = paths
+        .served
+        .iter()
+        .map(|(dir, _)| dir)
+        .chain(&paths.readable)
+        .chain(&paths.writable)
+        .chain(&paths.sockets)
+        .next()
+    {
+        return Err(format!(
+            "Landlock is not available and a chroot cannot reach {:?}",
+            path
+        ));
+    }
+    chroot(root)?;
+    Ok(Sandbox::Chroot)
+}
+
+/// Returns `false` when the kernel does not support Landlock.
+#[cfg(target_os = "linux")]
+fn landlock(root: &Path, paths: &Paths) -> Result<bool, String> {
+    use landlock::{
+        ABI, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, RulesetStatus,
+        path_beneath_rules,
+    };
+    let abi = ABI::V3;
+    let served_access = |writable: bool| {
+        if writable {
+            AccessFs::from_all(abi)
+        } else {
+            AccessFs::from_read(abi)
+        }
+    };
+    // Local time is formatted from the zone file when it exists.
+    let localtime = Some(Path::new("/etc/localtime")).filter(|path| path.exists());
+    let root_access = served_access(paths.writable_root);
+    let served_rules = paths
+        .served
+        .iter()
+        .flat_map(|(dir, writable)| path_beneath_rules([dir], served_access(*writable)));
+    let status = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules([root], root_access)))
+        .and_then(|ruleset| ruleset.add_rules(served_rules))
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(
+                paths.readable.iter().map(PathBuf::as_path).chain(localtime),
+                AccessFs::from_read(abi),
+            ))
+        })
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(
+                &paths.writable,
+                AccessFs::from_read(abi) | AccessFs::WriteFile | AccessFs::Truncate,
+            ))
+        })
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(
+                paths.sockets.iter().map(|socket| match socket.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => dir,
+                    _ => Path::new("."),
+                }),
+                AccessFs::MakeSock | AccessFs::RemoveFile,
+            ))
+        })
+        .and_then(|ruleset| ruleset.restrict_self())
+        .map_err(|e| format!("Landlock setup failed: {}", e))?;
+    Ok(status.ruleset != RulesetStatus::NotEnforced)
+}
+
+#[cfg(unix)]
+fn chroot(root: &Path) -> Result<(), String> {
+    std::os::unix::fs::chroot(root)
+        .and_then(|()| std::env::set_current_dir("/"))
+        .map_err(|e| format!("chroot into {:?} failed: {}", root, e))
+}
+
+#[cfg(not(unix))]
+fn chroot(_root: &Path) -> Result<(), String> {
+    Err("sandboxing is only supported on Unix".to_string())
+}
@@ -0,0 +1,176 @@
+//! Running the server from a library: `Server::builder()` for embedding,
+//! and `run_server` for callers that set up their own listeners.
+
+use crate::config::{Config, SharedConfig};
+use crate::handler;
+use crate::listener::{self, Listener, RemoteAddr};
+use crate::metrics;
+use crate::tls::{self, ClientIdentity};
+use base64::{Engine as _, engine::general_purpose};
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrIncoming;
+use hyper::service::{make_service_fn, service_fn};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
+
+/// Accept queue length of listeners bound by `ServerBuilder::bind`, the
+/// same as the `--backlog` default.
+const BACKLOG: i32 = 1024;
+
+/// Entry point of the builder API.
+pub struct Server;
+
+impl Server {
+    /// A builder starting from `Config::default()`: the current directory,
+    /// read-only, without authentication.
+    pub fn builder() -> ServerBuilder {
+        Self::from_config(Config::default())
+    }
+
+    /// A builder starting from complete settings.
+    pub fn from_config(config: Config) -> ServerBuilder {
+        ServerBuilder { config }
+    }
+}
+
+/// Settings of a server that is not listening yet.
+pub struct ServerBuilder {
+    config: Config,
+}
+
+impl ServerBuilder {
+    /// Directory files are served from.
+    pub fn root(mut self, root: impl Into<String>) -> Self {
+        self.config.root = root.into();
+        self
+    }
+
+    /// Requires Basic Auth with these credentials.
+    pub fn auth(mut self, user: &str, password: &str) -> Self {
+        let credentials = format!("{}:{}", user, password);
+        self.config.auth = Some(general_purpose::STANDARD.encode(credentials));
+        self
+    }
+
+    /// Accepts uploads, moves and, with WebDAV, other changes to files.
+    pub fn upload(mut self, upload: bool) -> Self {
+        self.config.upload = upload;
+        self
+    }
+
+    /// Starts serving on `addr` in the background; port 0 picks a free one.
+    /// Must be called from within a Tokio runtime.
+    pub fn bind(self, addr: SocketAddr) -> std::io::Result<ServerHandle> {
+        let listener = listener::bind_tcp(addr, BACKLOG, false, false)?;
+        let local_addr = listener.local_addr()?;
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let task = tokio::spawn(run_server(
+            listener,
+            SharedConfig::new(self.config),
+            None,
+            async {
+                // A dropped handle closes the channel without stopping.
+                if stop_rx.await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+            },
+        ));
+        Ok(ServerHandle {
+            local_addr,
+            stop: stop_tx,
+            task,
+        })
+    }
+}
+
+/// A server started by `ServerBuilder::bind`. Dropping the handle leaves
+/// the server running until the runtime shuts down.
+pub struct ServerHandle {
+    local_addr: SocketAddr,
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+}
+
+impl ServerHandle {
+    /// The address the server listens on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting connections and waits for the open ones to finish.
+    pub async fn shutdown(self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let _ = self.stop.send(());
+        self.task.await?
+    }
+}
+
+/// Serves requests on an already listening socket, over TLS when an
+/// acceptor is given. Each request uses the configuration current when it
+/// arrives. Once `shutdown` resolves no new connections are accepted, and
+/// this returns when the open ones have finished. Process-level setup (logging,
+/// signals, stats persistence, admin listeners) stays in the binary, so
+/// tests can call this on an ephemeral port.
+pub async fn run_server(
+    listener: impl Into<Listener>,
+    config: SharedConfig,
+    tls: Option<TlsAcceptor>,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match listener.into() {
+        Listener::Tcp(listener) => {
+            let incoming =
+                AddrIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?)?;
+            match tls {
+                Some(acceptor) => {
+                    serve(tls::TlsIncoming::new(incoming, acceptor), config, shutdown).await?
+                }
+                None => serve(incoming, config, shutdown).await?,
+            }
+        }
+        #[cfg(unix)]
+        Listener::Unix(_) if tls.is_some() => {
+            return Err("TLS is not supported on Unix sockets".into());
+        }
+        #[cfg(unix)]
+        Listener::Unix(listener) => {
+            serve(listener::UnixIncoming::new(listener)?, config, shutdown).await?
+        }
+    }
+    Ok(())
+}
+
+async fn serve<I>(
+    incoming: I,
+    config: SharedConfig,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), hyper::Error>
+where
+    I: Accept,
+    I::Conn: RemoteAddr + ClientIdentity + AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let make_svc = make_service_fn(|conn: &I::Conn| {
+        let remote_addr = conn.remote_addr();
+        let client: Option<Arc<str>> = conn.client_subject().map(Into::into);
+        let config = config.clone();
+        let connection = metrics::METRICS.connection();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                // Keeps the connection counted for as long as the service lives.
+                let _ = &connection;
+                handler::handle_requests(req, remote_addr, client.clone(), config.current())
+            }))
+        }
+    });
+    // Plain connections starting with the HTTP/2 preface (h2c with prior
+    // knowledge) are served as HTTP/2, everything else as HTTP/1.
+    hyper::Server::builder(incoming)
+        .serve(make_svc)
+        .with_graceful_shutdown(shutdown)
+        .await
+}
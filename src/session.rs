@@ -0,0 +1,290 @@
+//! `--login-page`: an HTML login form in place of the browser's Basic Auth
+//! prompt. A successful login sets a session cookie holding the user and an
+//! expiry time, signed with a key drawn at startup, so sessions need no
+//! storage; they end when they expire, on logout, or when the server
+//! restarts.
+
+use crate::users::constant_time_eq;
+use hmac::{Hmac, Mac};
+use html_escape::{encode_double_quoted_attribute, encode_text};
+use hyper::HeaderMap;
+use hyper::header::{self, HeaderValue};
+use sha2::Sha256;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where the login form is shown and posted to, below the base path.
+pub const LOGIN_PATH: &str = "/_login";
+/// Ends the session and returns to the login form.
+pub const LOGOUT_PATH: &str = "/_logout";
+
+const COOKIE: &str = "samserve_session";
+
+/// Issues and checks session cookies.
+#[derive(Debug)]
+pub struct Sessions {
+    secret: [u8; 32],
+    lifetime: Duration,
+    /// Whether cookies are only sent over HTTPS.
+    secure: bool,
+}
+
+impl Sessions {
+    pub fn new(lifetime: Duration, secure: bool) -> Result<Sessions, String> {
+        let mut secret = [0; 32];
+        getrandom::fill(&mut secret).map_err(|e| format!("no random source: {}", e))?;
+        Ok(Sessions {
+            secret,
+            lifetime,
+            secure,
+        })
+    }
+
+    /// These settings with the key of `old`, so a reload keeps everyone
+    /// logged in.
+    pub fn with_key_of(&self, old: &Sessions) -> Sessions {
+        Sessions {
+            secret: old.secret,
+            lifetime: self.lifetime,
+            secure: self.secure,
+        }
+    }
+
+    /// The `Set-Cookie` value starting a session for `user`.
+    pub fn start(&self, user: &str) -> String {
+        let expires = now() + self.lifetime.as_secs();
+        let value = format!("{}.{}", hex(user.as_bytes()), expires);
+        let mac = hex(&self.mac(&value));
+        self.cookie(&format!("{}.{}", value, mac), self.lifetime.as_secs())
+    }
+
+    /// The `Set-Cookie` value removing the session cookie.
+    pub fn end(&self) -> String {
+        self.cookie("", 0)
+    }
+
+    fn cookie(&self, value: &str, max_age: u64) -> String {
+        format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax{}",
+            COOKIE,
+            value,
+            max_age,
+            if self.secure { "; Secure" } else { "" }
+        )
+    }
+
+    /// The user of a valid, unexpired session cookie among `headers`.
+    pub fn user(&self, headers: &HeaderMap) -> Option<String> {
+        let cookie = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(';'))
+            .find_map(|pair| pair.trim().strip_prefix(COOKIE)?.strip_prefix('='))?;
+        let (value, mac) = cookie.rsplit_once('.')?;
+        if !constant_time_eq(hex(&self.mac(value)).as_bytes(), mac.as_bytes()) {
+            return None;
+        }
+        let (user, expires) = value.split_once('.')?;
+        if expires.parse::<u64>().ok()? <= now() {
+            return None;
+        }
+        String::from_utf8(unhex(user)?).ok()
+    }
+
+    fn mac(&self, value: &str) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("any key size");
+        mac.update(value.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+}
+
+/// Takes the session cookie out of `headers`, keeping any others, so it
+/// is not handed on to another server.
+pub fn remove_cookie(headers: &mut HeaderMap) {
+    let kept: Vec<HeaderValue> = headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| {
+            let Ok(value) = value.to_str() else {
+                return Some(value.clone());
+            };
+            let others = value
+                .split(';')
+                .map(str::trim)
+                .filter(|pair| {
+                    pair.strip_prefix(COOKIE)
+                        .is_none_or(|rest| !rest.starts_with('='))
+                })
+                .collect::<Vec<_>>()
+                .join("; ");
+            HeaderValue::from_str(&others)
+                .ok()
+                .filter(|_| !others.is_empty())
+        })
+        .collect();
+    headers.remove(header::COOKIE);
+    for value in kept {
+        headers.append(header::COOKIE, value);
+    }
+}
+
+/// Where to go after logging in: a path on this server, or the top.
+/// Browsers drop tabs and newlines from URLs, which could turn `/\t/host`
+/// into `//host`, so any whitespace or control character is refused too.
+pub fn return_path(next: Option<&str>) -> &str {
+    match next {
+        Some(next)
+            if next.starts_with('/')
+                && !next.starts_with("//")
+                && !next
+                    .chars()
+                    .any(|c| c == '\\' || c.is_whitespace() || c.is_control()) =>
+        {
+            next
+        }
+        _ => "/",
+    }
+}
+
+/// The login form, posting to `action` and then going to `next`.
+pub fn login_page(title: &str, action: &str, next: &str, failed: bool) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>{title}</title>
+    <style>
+        body {{
+            font-family: sans-serif;
+            background: #f8f9fa;
+            color: #333;
+            padding: 2rem;
+        }}
+        form {{
+            max-width: 20rem;
+            margin: 3rem auto;
+        }}
+        h1 {{
+            font-size: 1.5rem;
+            margin-bottom: 1rem;
+        }}
+        label, input {{
+            display: block;
+            width: 100%;
+            box-sizing: border-box;
+        }}
+        input {{
+            margin: 0.25rem 0 1rem;
+            padding: 0.4rem;
+        }}
+        p.error {{
+            color: #c00;
+        }}
+    </style>
+</head>
+<body>
+    <form action="{action}" method="POST">
+        <h1>{title}</h1>
+        {error}
+        <input type="hidden" name="next" value="{next}">
+        <label for="user">User</label>
+        <input id="user" name="user" autocomplete="username" required autofocus>
+        <label for="password">Password</label>
+        <input id="password" name="password" type="password" autocomplete="current-password" required>
+        <input type="submit" value="Log in">
+    </form>
+</body>
+</html>
+"#,
+        title = encode_text(title),
+        action = encode_double_quoted_attribute(action),
+        next = encode_double_quoted_attribute(next),
+        error = if failed {
+            r#"<p class="error">Wrong user or password.</p>"#
+        } else {
+            ""
+        },
+    )
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(set_cookie: &str) -> HeaderMap {
+        let pair = set_cookie.split(';').next().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            format!("theme=dark; {}", pair).parse().unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn sessions_are_signed_and_expire() {
+        let sessions = Sessions::new(Duration::from_secs(60), true).unwrap();
+        let cookie = sessions.start("alice.smith");
+        assert!(cookie.ends_with("Max-Age=60; HttpOnly; SameSite=Lax; Secure"));
+        assert_eq!(
+            sessions.user(&headers(&cookie)).as_deref(),
+            Some("alice.smith")
+        );
+
+        let other = Sessions::new(Duration::from_secs(60), true).unwrap();
+        assert_eq!(other.user(&headers(&cookie)), None);
+        assert!(
+            other
+                .with_key_of(&sessions)
+                .user(&headers(&cookie))
+                .is_some()
+        );
+        let forged = cookie.replacen(&hex(b"alice.smith"), &hex(b"admin"), 1);
+        assert_eq!(sessions.user(&headers(&forged)), None);
+        let expired = Sessions::new(Duration::ZERO, false).unwrap();
+        assert_eq!(expired.user(&headers(&expired.start("bob"))), None);
+        assert_eq!(sessions.user(&headers(&sessions.end())), None);
+
+        let mut others = headers(&cookie);
+        remove_cookie(&mut others);
+        assert_eq!(others[header::COOKIE], "theme=dark");
+        let mut only = headers(&cookie);
+        only.insert(
+            header::COOKIE,
+            cookie.split(';').next().unwrap().parse().unwrap(),
+        );
+        remove_cookie(&mut only);
+        assert!(!only.contains_key(header::COOKIE));
+
+        assert_eq!(return_path(Some("/a/b?c")), "/a/b?c");
+        assert_eq!(return_path(Some("//evil.example")), "/");
+        assert_eq!(return_path(Some("/\n")), "/");
+        assert_eq!(return_path(Some("/\t/evil.example")), "/");
+        assert_eq!(return_path(Some("/a\rb")), "/");
+        assert_eq!(return_path(Some("/a b")), "/");
+        assert_eq!(return_path(Some("https://evil.example")), "/");
+        assert_eq!(return_path(None), "/");
+    }
+}
@@ -0,0 +1,85 @@
+use futures_util::{Stream, StreamExt};
+use hyper::body::Bytes;
+use log::{error, info};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+
+/// Completed full downloads per served file path.
+static DOWNLOADS: LazyLock<Mutex<HashMap<PathBuf, u64>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn download_count(path: &Path) -> u64 {
+    DOWNLOADS.lock().unwrap().get(path).copied().unwrap_or(0)
+}
+
+pub fn record_download(path: &Path) {
+    *DOWNLOADS
+        .lock()
+        .unwrap()
+        .entry(path.to_path_buf())
+        .or_default() += 1;
+}
+
+/// Passes `stream` through and records a download for `path` once
+/// `expected` bytes have gone by.
+pub fn count_on_completion<S>(
+    stream: S,
+    path: PathBuf,
+    expected: u64,
+) -> impl Stream<Item = std::io::Result<Bytes>>
+where
+    S: Stream<Item = std::io::Result<Bytes>>,
+{
+    let mut sent = 0u64;
+    stream.inspect(move |chunk| {
+        if let Ok(chunk) = chunk {
+            sent += chunk.len() as u64;
+            if sent == expected && !chunk.is_empty() {
+                record_download(&path);
+            }
+        }
+    })
+}
+
+/// Loads previously persisted counts; a missing or empty file starts from
+/// zero.
+pub fn load(file: &Path) {
+    let data = match std::fs::read_to_string(file) {
+        Ok(data) if data.trim().is_empty() => return,
+        Ok(data) => data,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+        Err(err) => {
+            error!(
+                "Failed to read download stats | file: {:?} | error: {}",
+                file, err
+            );
+            return;
+        }
+    };
+    match serde_json::from_str::<HashMap<PathBuf, u64>>(&data) {
+        Ok(counts) => {
+            info!(
+                "Loaded download stats | file: {:?} | files: {}",
+                file,
+                counts.len()
+            );
+            *DOWNLOADS.lock().unwrap() = counts;
+        }
+        Err(err) => error!(
+            "Invalid download stats file | file: {:?} | error: {}",
+            file, err
+        ),
+    }
+}
+
+pub fn save(file: &Path) {
+    let json = serde_json::to_string_pretty(&*DOWNLOADS.lock().unwrap()).unwrap();
+    match std::fs::write(file, json) {
+        Ok(()) => info!("Saved download stats | file: {:?}", file),
+        Err(err) => error!(
+            "Failed to save download stats | file: {:?} | error: {}",
+            file, err
+        ),
+    }
+}
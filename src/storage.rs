@@ -0,0 +1,378 @@
+//! Where served files come from. The handler reaches files through the
+//! `Storage` of its `Config`, so other backends can stand in for the local
+//! filesystem.
+//!
+//! Paths are the ones the handler resolves below `Config::root`; a backend
+//! that is not a directory maps them onto its own namespace.
+
+use futures_util::future::BoxFuture;
+use futures_util::stream::{BoxStream, StreamExt, TryStreamExt};
+use hyper::body::Bytes;
+use log::warn;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
+use tokio::fs::{self, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio_util::io::ReaderStream;
+
+/// File contents, streamed in chunks.
+pub type ByteStream = BoxStream<'static, io::Result<Bytes>>;
+
+/// What the handler needs to know about a file or directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub is_dir: bool,
+    pub len: u64,
+    /// `None` when the backend does not keep modification times.
+    pub modified: Option<SystemTime>,
+}
+
+/// A directory entry. `metadata` is `None` when the entry could not be
+/// inspected (broken symlink, permission denied, ...).
+#[derive(Debug)]
+pub struct DirEntry {
+    pub name: OsString,
+    pub metadata: Option<Metadata>,
+}
+
+pub trait Storage: Send + Sync + std::fmt::Debug {
+    /// Metadata of `path`, following symlinks.
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Metadata>>;
+
+    /// The entries of the directory `path`. Only failing to open the
+    /// directory is an error; entries that cannot be inspected are listed
+    /// without metadata.
+    fn list<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<DirEntry>>>;
+
+    /// The whole contents of the file `path`.
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<ByteStream>>;
+
+    /// `len` bytes of the file `path`, from offset `start`.
+    fn read_range<'a>(
+        &'a self,
+        path: &'a Path,
+        start: u64,
+        len: u64,
+    ) -> BoxFuture<'a, io::Result<ByteStream>>;
+
+    /// Replaces the file `path` with `data`, returning its size. Readers
+    /// never see a partly written file.
+    fn write<'a>(&'a self, path: &'a Path, data: ByteStream) -> BoxFuture<'a, io::Result<u64>>;
+
+    /// Whether anything is at `path`.
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, bool> {
+        Box::pin(async move { self.metadata(path).await.is_ok() })
+    }
+
+    /// Moves the file or directory `from` to `to`, replacing a file there.
+    fn rename<'a>(&'a self, _from: &'a Path, _to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "moving files is not supported by this storage",
+            ))
+        })
+    }
+
+    /// Total size of the files below the directory `path`, going at most
+    /// `max_depth` levels down. Directories that cannot be read are skipped.
+    fn dir_size<'a>(&'a self, path: &'a Path, max_depth: Option<usize>) -> BoxFuture<'a, u64> {
+        Box::pin(async move {
+            let mut total = 0;
+            let mut stack = vec![(path.to_path_buf(), 0usize)];
+            while let Some((dir, depth)) = stack.pop() {
+                let entries = match self.list(&dir).await {
+                    Ok(entries) => entries,
+                    Err(err) => {
+                        warn!(
+                            "Directory size walk skipped a directory | path: {:?} | error: {}",
+                            dir, err
+                        );
+                        continue;
+                    }
+                };
+                for entry in entries {
+                    let Some(metadata) = entry.metadata else {
+                        continue;
+                    };
+                    if !metadata.is_dir {
+                        total += metadata.len;
+                    } else if max_depth.is_none_or(|max| depth + 1 < max) {
+                        stack.push((dir.join(&entry.name), depth + 1));
+                    }
+                }
+            }
+            total
+        })
+    }
+
+    /// Local directory an upload into `dir` is received in before `persist`
+    /// moves it into place, or `None` to keep uploads in memory.
+    fn staging_dir(&self, _dir: &Path) -> Option<PathBuf> {
+        Some(std::env::temp_dir())
+    }
+
+    /// Moves a complete upload to `path`. A staged file is gone afterwards,
+    /// whether or not it succeeded.
+    fn persist<'a>(&'a self, staged: Staged, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let temp_path = match staged {
+                Staged::File(temp_path) => temp_path,
+                Staged::Memory(data) => {
+                    let data = futures_util::stream::iter([Ok(data)]).boxed();
+                    return self.write(path, data).await.map(drop);
+                }
+            };
+            let result = match File::open(&temp_path).await {
+                Ok(file) => self
+                    .write(path, ReaderStream::new(file).boxed())
+                    .await
+                    .map(drop),
+                Err(err) => Err(err),
+            };
+            let _ = fs::remove_file(&temp_path).await;
+            result
+        })
+    }
+}
+
+/// An upload received in full, waiting for `Storage::persist`.
+#[derive(Debug)]
+pub enum Staged {
+    /// A temporary file in the backend's `staging_dir`.
+    File(PathBuf),
+    /// Held in memory, for backends without a staging directory.
+    Memory(Bytes),
+}
+
+impl Staged {
+    /// Drops an upload that is not going to be stored.
+    pub async fn discard(self) {
+        if let Staged::File(temp_path) = self {
+            let _ = fs::remove_file(temp_path).await;
+        }
+    }
+}
+
+/// Files on the local filesystem, at the paths the handler resolves.
+#[derive(Debug, Default)]
+pub struct LocalStorage;
+
+impl From<std::fs::Metadata> for Metadata {
+    fn from(metadata: std::fs::Metadata) -> Self {
+        Metadata {
+            is_dir: metadata.is_dir(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        }
+    }
+}
+
+impl Storage for LocalStorage {
+    fn metadata<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Metadata>> {
+        Box::pin(async move { fs::metadata(path).await.map(Metadata::from) })
+    }
+
+    fn list<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<Vec<DirEntry>>> {
+        Box::pin(async move {
+            let mut entries = fs::read_dir(path).await?;
+            let mut listing = Vec::new();
+            loop {
+                let entry = match entries.next_entry().await {
+                    Ok(Some(entry)) => entry,
+                    Ok(None) => break,
+                    Err(err) => {
+                        // The directory stream cannot be resumed reliably after an error.
+                        warn!(
+                            "Directory read interrupted, listing is partial | path: {:?} | error: {}",
+                            path, err
+                        );
+                        break;
+                    }
+                };
+                // Follow symlinks so linked directories list as directories.
+                let metadata = match fs::metadata(entry.path()).await {
+                    Ok(metadata) => Some(metadata.into()),
+                    Err(err) => {
+                        warn!(
+                            "Inaccessible directory entry | path: {:?} | error: {}",
+                            entry.path(),
+                            err
+                        );
+                        None
+                    }
+                };
+                listing.push(DirEntry {
+                    name: entry.file_name(),
+                    metadata,
+                });
+            }
+            Ok(listing)
+        })
+    }
+
+    fn open<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, io::Result<ByteStream>> {
+        Box::pin(async move {
+            let file = File::open(path).await?;
+            Ok(ReaderStream::new(file).boxed())
+        })
+    }
+
+    fn read_range<'a>(
+        &'a self,
+        path: &'a Path,
+        start: u64,
+        len: u64,
+    ) -> BoxFuture<'a, io::Result<ByteStream>> {
+        Box::pin(async move {
+            let mut file = File::open(path).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            Ok(ReaderStream::new(file.take(len)).boxed())
+        })
+    }
+
+    fn write<'a>(&'a self, path: &'a Path, data: ByteStream) -> BoxFuture<'a, io::Result<u64>> {
+        Box::pin(async move {
+            static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+            let dir = path.parent().unwrap_or(Path::new("."));
+            let temp_path = dir.join(format!(
+                ".samserve-write-{}-{}.part",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut file = File::create(&temp_path).await?;
+            let mut data = data;
+            let mut size = 0u64;
+            let result = async {
+                while let Some(chunk) = data.try_next().await? {
+                    file.write_all(&chunk).await?;
+                    size += chunk.len() as u64;
+                }
+                file.flush().await?;
+                fs::rename(&temp_path, path).await
+            }
+            .await;
+            if let Err(err) = result {
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(err);
+            }
+            Ok(size)
+        })
+    }
+
+    /// A dangling symlink exists too, so it can be moved.
+    fn exists<'a>(&'a self, path: &'a Path) -> BoxFuture<'a, bool> {
+        Box::pin(async move { fs::symlink_metadata(path).await.is_ok() })
+    }
+
+    fn rename<'a>(&'a self, from: &'a Path, to: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(fs::rename(from, to))
+    }
+
+    /// Walks without following symlinks, unlike `list`, so link cycles
+    /// cannot make it loop.
+    fn dir_size<'a>(&'a self, path: &'a Path, max_depth: Option<usize>) -> BoxFuture<'a, u64> {
+        let root = path.to_path_buf();
+        Box::pin(async move {
+            tokio::task::spawn_blocking(move || walk_local(&root, max_depth))
+                .await
+                .unwrap_or(0)
+        })
+    }
+
+    fn staging_dir(&self, dir: &Path) -> Option<PathBuf> {
+        Some(dir.to_path_buf())
+    }
+
+    fn persist<'a>(&'a self, staged: Staged, path: &'a Path) -> BoxFuture<'a, io::Result<()>> {
+        Box::pin(async move {
+            let temp_path = match staged {
+                Staged::File(temp_path) => temp_path,
+                Staged::Memory(data) => {
+                    let data = futures_util::stream::iter([Ok(data)]).boxed();
+                    return self.write(path, data).await.map(drop);
+                }
+            };
+            let result = fs::rename(&temp_path, path).await;
+            if result.is_err() {
+                let _ = fs::remove_file(&temp_path).await;
+            }
+            result
+        })
+    }
+}
+
+/// Sums file sizes below `root` depth-first, skipping unreadable entries.
+fn walk_local(root: &Path, max_depth: Option<usize>) -> u64 {
+    let mut total = 0;
+    let mut stack = vec![(root.to_path_buf(), 0usize)];
+    while let Some((dir, depth)) = stack.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(err) => {
+                warn!(
+                    "Directory size walk skipped a directory | path: {:?} | error: {}",
+                    dir, err
+                );
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            if file_type.is_file() {
+                total += entry.metadata().map_or(0, |m| m.len());
+            } else if file_type.is_dir() && max_depth.is_none_or(|max| depth + 1 < max) {
+                stack.push((entry.path(), depth + 1));
+            }
+        }
+    }
+    total
+}
+
+/// Reads a whole stream into memory.
+pub async fn collect(stream: ByteStream) -> io::Result<Vec<u8>> {
+    stream
+        .try_fold(Vec::new(), |mut buffer, chunk| async move {
+            buffer.extend_from_slice(&chunk);
+            Ok(buffer)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn local_storage_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage;
+        let path = dir.path().join("a.txt");
+        let data =
+            futures_util::stream::iter([Ok(Bytes::from("hello ")), Ok(Bytes::from("world"))]);
+        assert_eq!(storage.write(&path, data.boxed()).await.unwrap(), 11);
+
+        let metadata = storage.metadata(&path).await.unwrap();
+        assert!(!metadata.is_dir);
+        assert_eq!(metadata.len, 11);
+        let body = storage.read_range(&path, 6, 3).await.unwrap();
+        assert_eq!(collect(body).await.unwrap(), b"wor");
+        let body = storage.open(&path).await.unwrap();
+        assert_eq!(collect(body).await.unwrap(), b"hello world");
+
+        let entries = storage.list(dir.path()).await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "a.txt");
+        assert!(storage.metadata(&dir.path().join("missing")).await.is_err());
+
+        let moved = dir.path().join("b.txt");
+        storage.rename(&path, &moved).await.unwrap();
+        assert!(!storage.exists(&path).await);
+        assert!(storage.exists(&moved).await);
+    }
+}
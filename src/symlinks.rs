@@ -0,0 +1,87 @@
+//! `--follow-symlinks`: whether paths reached through symbolic links are
+//! served. `..` is always refused; this covers links that lead out of the
+//! root, which the path check alone cannot see.
+
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FollowSymlinks {
+    /// Refuse any path that goes through a symlink.
+    Never,
+    /// Follow symlinks whose target stays inside the root.
+    WithinRoot,
+    /// Follow every symlink.
+    Always,
+}
+
+/// Whether `path`, which lies below `root`, may be used under `policy`. A
+/// path that does not exist yet (an upload target) is judged by its
+/// nearest existing ancestor.
+pub async fn allowed(path: &Path, root: &str, policy: FollowSymlinks) -> bool {
+    match policy {
+        FollowSymlinks::Always => true,
+        FollowSymlinks::WithinRoot => {
+            let Ok(root) = fs::canonicalize(root).await else {
+                return false;
+            };
+            let mut existing = path;
+            loop {
+                match fs::canonicalize(existing).await {
+                    Ok(real) => return real.starts_with(&root),
+                    Err(_) => match existing.parent() {
+                        Some(parent) => existing = parent,
+                        None => return false,
+                    },
+                }
+            }
+        }
+        FollowSymlinks::Never => {
+            let Ok(relative) = path.strip_prefix(root) else {
+                return false;
+            };
+            let mut current = PathBuf::from(root);
+            for part in relative.components() {
+                current.push(part);
+                match fs::symlink_metadata(&current).await {
+                    Ok(metadata) if metadata.file_type().is_symlink() => return false,
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+            true
+        }
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn applies_each_policy() {
+        let outside = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let root_str = root.path().to_str().unwrap();
+        std::fs::create_dir(root.path().join("real")).unwrap();
+        std::os::unix::fs::symlink(root.path().join("real"), root.path().join("inner")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.path().join("outer")).unwrap();
+
+        let inner = root.path().join("inner/file.txt");
+        let outer = root.path().join("outer/file.txt");
+        let plain = root.path().join("real/file.txt");
+        for (path, never, within_root) in [
+            (&plain, true, true),
+            (&inner, false, true),
+            (&outer, false, false),
+        ] {
+            assert_eq!(allowed(path, root_str, FollowSymlinks::Never).await, never);
+            assert_eq!(
+                allowed(path, root_str, FollowSymlinks::WithinRoot).await,
+                within_root
+            );
+            assert!(allowed(path, root_str, FollowSymlinks::Always).await);
+        }
+    }
+}
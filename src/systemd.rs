@@ -0,0 +1,72 @@
+//! The parts of the systemd service protocol samserve speaks: socket
+//! activation (`LISTEN_FDS`) and readiness notification (`NOTIFY_SOCKET`).
+//! Both are no-ops outside systemd.
+
+use log::warn;
+
+/// First descriptor systemd passes (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Returns the listening socket systemd passed to this process, if any.
+/// The variables are cleared so child processes do not pick them up; call
+/// this before any threads are spawned.
+#[cfg(unix)]
+pub fn take_listen_fd() -> Option<i32> {
+    let pid = std::env::var("LISTEN_PID").ok()?;
+    let count = std::env::var("LISTEN_FDS").ok()?;
+    // SAFETY: called from `main` while the process is still single-threaded.
+    unsafe {
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+    }
+    if pid.parse::<u32>().ok()? != std::process::id() {
+        return None;
+    }
+    match count.parse::<i32>().ok()? {
+        0 => None,
+        1 => Some(LISTEN_FDS_START),
+        n => {
+            warn!(
+                "systemd passed {} sockets, serving only the first | fd: {}",
+                n, LISTEN_FDS_START
+            );
+            Some(LISTEN_FDS_START)
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn take_listen_fd() -> Option<i32> {
+    None
+}
+
+/// Sends a state change such as `READY=1` or `STOPPING=1` to the service
+/// manager. Failures are logged, never fatal.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+    let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+        return;
+    };
+    let sent = UnixDatagram::unbound().and_then(|socket| {
+        // A leading '@' names a socket in Linux's abstract namespace.
+        #[cfg(target_os = "linux")]
+        if let Some(name) = path.as_encoded_bytes().strip_prefix(b"@") {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            return socket.send_to_addr(state.as_bytes(), &addr);
+        }
+        socket.send_to(state.as_bytes(), &path)
+    });
+    if let Err(e) = sent {
+        warn!(
+            "Failed to notify systemd | state: {} | socket: {:?} | error: {}",
+            state, path, e
+        );
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) {}
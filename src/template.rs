@@ -0,0 +1,51 @@
+use crate::config::Config;
+use html_escape::encode_text;
+
+/// Replaces `{{name}}` placeholders with `--var` values and the built-ins
+/// `server_version` and `hostname`. Values are HTML-escaped; unknown
+/// placeholders are left untouched.
+pub fn render(template: &str, config: &Config) -> String {
+    let mut vars = vec![
+        (
+            "server_version".to_string(),
+            env!("CARGO_PKG_VERSION").to_string(),
+        ),
+        (
+            "hostname".to_string(),
+            hostname::get()
+                .map(|h| h.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+        ),
+    ];
+    vars.extend(config.vars.iter().cloned());
+
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let key = after[..end].trim();
+        // Later definitions win, so --var can shadow a built-in.
+        match vars.iter().rev().find(|(name, _)| name == key) {
+            Some((_, value)) => out.push_str(&encode_text(value)),
+            None => out.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Parses a `--var name=value` argument.
+pub fn parse_var(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected name=value, got {:?}", s)),
+    }
+}
@@ -0,0 +1,1389 @@
+//! End-to-end tests: each test starts the real server on an ephemeral port
+//! over a temporary root and talks to it with a hyper client.
+
+use crate::cache;
+use crate::config::{self, Config};
+use crate::listener;
+use crate::server::Server;
+use crate::session::Sessions;
+use crate::users::Users;
+use base64::{Engine as _, engine::general_purpose};
+use hyper::body::to_bytes;
+use hyper::client::HttpConnector;
+use hyper::{Body, Client, Method, Request, Response, StatusCode, Version, header};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// A running server and the directory it serves. The server task ends
+/// with the test's runtime.
+struct TestServer {
+    addr: SocketAddr,
+    root: TempDir,
+    client: Client<HttpConnector>,
+}
+
+impl TestServer {
+    /// Starts samserve on `127.0.0.1:0` with `config`, serving a fresh
+    /// temporary root in place of `config.root`.
+    fn start(config: Config) -> Self {
+        let root = TempDir::new().unwrap();
+        let config = Config {
+            root: root.path().to_str().unwrap().to_string(),
+            ..config
+        };
+        let server = Server::from_config(config)
+            .bind("127.0.0.1:0".parse().unwrap())
+            .unwrap();
+        TestServer {
+            addr: server.local_addr(),
+            root,
+            client: Client::new(),
+        }
+    }
+
+    fn root(&self) -> &Path {
+        self.root.path()
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    async fn send(&self, request: Request<Body>) -> Response<Body> {
+        self.client.request(request).await.unwrap()
+    }
+
+    async fn get(&self, path: &str) -> Response<Body> {
+        self.send(Request::get(self.url(path)).body(Body::empty()).unwrap())
+            .await
+    }
+}
+
+/// `user:secret`, base64-encoded as `Config::auth` expects.
+const CREDENTIALS: &str = "dXNlcjpzZWNyZXQ=";
+
+async fn body_string(response: Response<Body>) -> String {
+    String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+}
+
+fn multipart_upload(url: String, file_name: &str, content: &str) -> Request<Body> {
+    let boundary = "samserve-test-boundary";
+    let body = format!(
+        "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{f}\"\r\n\
+         Content-Type: application/octet-stream\r\n\r\n{c}\r\n--{b}--\r\n",
+        b = boundary,
+        f = file_name,
+        c = content
+    );
+    Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .body(Body::from(body))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn serves_files() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("hello.txt"), "hello world").unwrap();
+
+    let response = server.get("/hello.txt").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(body_string(response).await, "hello world");
+}
+
+#[tokio::test]
+async fn builder_serves_until_shutdown() {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join("hello.txt"), "hello").unwrap();
+    let server = Server::builder()
+        .root(root.path().to_str().unwrap())
+        .auth("user", "secret")
+        .bind("127.0.0.1:0".parse().unwrap())
+        .unwrap();
+    let url = format!("http://{}/hello.txt", server.local_addr());
+
+    let client = Client::new();
+    let response = client.get(url.parse().unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let request = Request::get(&url)
+        .header(header::AUTHORIZATION, "Basic dXNlcjpzZWNyZXQ=")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        body_string(client.request(request).await.unwrap()).await,
+        "hello"
+    );
+
+    let addr = server.local_addr();
+    server.shutdown().await.unwrap();
+    assert!(tokio::net::TcpStream::connect(addr).await.is_err());
+}
+
+#[tokio::test]
+async fn serves_http2_with_prior_knowledge() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("hello.txt"), "hello world").unwrap();
+
+    let client: Client<HttpConnector> = Client::builder().http2_only(true).build_http();
+    let response = client
+        .get(server.url("/hello.txt").parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.version(), Version::HTTP_2);
+    assert_eq!(body_string(response).await, "hello world");
+}
+
+#[tokio::test]
+async fn compresses_text_files_on_request() {
+    let server = TestServer::start(Config {
+        compress: true,
+        no_compress_ext: vec!["txt".to_string()],
+        ..Config::default()
+    });
+    let text = "a line of a log file\n".repeat(500);
+    std::fs::write(server.root().join("app.log"), &text).unwrap();
+
+    let request = Request::get(server.url("/app.log"))
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+    let compressed = to_bytes(response.into_body()).await.unwrap();
+    assert!(compressed.len() < text.len() / 10);
+    let mut decoded = String::new();
+    std::io::Read::read_to_string(
+        &mut flate2::read::GzDecoder::new(&compressed[..]),
+        &mut decoded,
+    )
+    .unwrap();
+    assert_eq!(decoded, text);
+
+    let response = server.get("/app.log").await;
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    assert_eq!(body_string(response).await, text);
+    std::fs::write(server.root().join("notes.txt"), &text).unwrap();
+    let request = Request::get(server.url("/notes.txt"))
+        .header(header::ACCEPT_ENCODING, "gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+}
+
+#[tokio::test]
+async fn serves_precompressed_siblings() {
+    let server = TestServer::start(Config {
+        precompressed: true,
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("app.js"), "console.log(1);").unwrap();
+    std::fs::write(server.root().join("app.js.gz"), b"stored gzip").unwrap();
+
+    let request = Request::get(server.url("/app.js"))
+        .header(header::ACCEPT_ENCODING, "br, gzip")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "gzip");
+    assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+    assert!(
+        response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .contains("javascript")
+    );
+    assert_eq!(body_string(response).await, "stored gzip");
+
+    let response = server.get("/app.js").await;
+    assert!(!response.headers().contains_key(header::CONTENT_ENCODING));
+    assert_eq!(body_string(response).await, "console.log(1);");
+}
+
+#[tokio::test]
+async fn head_returns_headers_without_a_body() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("notes.txt"), "some notes").unwrap();
+
+    let request = Request::head(server.url("/notes.txt"))
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_LENGTH], "10");
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/plain");
+    assert_eq!(response.headers()[header::ACCEPT_RANGES], "bytes");
+    assert_eq!(body_string(response).await, "");
+}
+
+#[tokio::test]
+async fn options_and_unsupported_methods_report_allow() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("notes.txt"), "some notes").unwrap();
+
+    let request = Request::options(server.url("/notes.txt"))
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(response.headers()[header::ALLOW], "OPTIONS, GET, HEAD");
+
+    for method in [Method::PUT, Method::DELETE, Method::PATCH] {
+        let request = Request::builder()
+            .method(method)
+            .uri(server.url("/notes.txt"))
+            .body(Body::empty())
+            .unwrap();
+        let response = server.send(request).await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "OPTIONS, GET, HEAD");
+    }
+    assert_eq!(
+        std::fs::read_to_string(server.root().join("notes.txt")).unwrap(),
+        "some notes"
+    );
+
+    let server = TestServer::start(Config {
+        upload: true,
+        ..Config::default()
+    });
+    let request = Request::options(server.url("/"))
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(
+        response.headers()[header::ALLOW],
+        "OPTIONS, GET, HEAD, POST, MOVE"
+    );
+}
+
+#[tokio::test]
+async fn cors_allows_configured_origins() {
+    let server = TestServer::start(Config {
+        cors: vec!["http://localhost:3000".to_string()],
+        auth: Some(CREDENTIALS.to_string()),
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("data.json"), "{}").unwrap();
+
+    let request = Request::options(server.url("/data.json"))
+        .header(header::ORIGIN, "http://localhost:3000")
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let headers = response.headers();
+    assert_eq!(
+        headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "http://localhost:3000"
+    );
+    assert_eq!(
+        headers[header::ACCESS_CONTROL_ALLOW_METHODS],
+        "OPTIONS, GET, HEAD"
+    );
+    assert_eq!(
+        headers[header::ACCESS_CONTROL_ALLOW_HEADERS],
+        "authorization"
+    );
+
+    let request = Request::get(server.url("/data.json"))
+        .header(header::ORIGIN, "http://localhost:3000")
+        .header(header::AUTHORIZATION, format!("Basic {}", CREDENTIALS))
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+        "http://localhost:3000"
+    );
+    assert_eq!(response.headers()[header::VARY], "Origin");
+
+    let request = Request::get(server.url("/data.json"))
+        .header(header::ORIGIN, "http://evil.example")
+        .header(header::AUTHORIZATION, format!("Basic {}", CREDENTIALS))
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert!(
+        !response
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+    );
+}
+
+#[tokio::test]
+async fn custom_headers_are_added_to_every_response() {
+    let server = TestServer::start(Config {
+        headers: vec![
+            config::parse_header("X-Policy: internal").unwrap(),
+            config::parse_header("Cache-Control: no-store").unwrap(),
+        ],
+        cache_rules: vec![cache::parse_rule("*=max-age=60").unwrap()],
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("notes.txt"), "notes").unwrap();
+
+    for path in ["/notes.txt", "/", "/missing.txt"] {
+        let response = server.get(path).await;
+        assert_eq!(
+            response.headers()[header::CACHE_CONTROL],
+            "no-store",
+            "{}",
+            path
+        );
+        assert_eq!(response.headers()["x-policy"], "internal", "{}", path);
+    }
+    assert!(config::parse_header("no colon").is_err());
+    assert!(config::parse_header("Bad Name: x").is_err());
+}
+
+#[tokio::test]
+async fn error_pages_replace_plain_error_bodies() {
+    let server = TestServer::start(Config {
+        error_pages: vec![(401, "errors/401.html".into()), (404, "404.html".into())],
+        auth: Some(CREDENTIALS.to_string()),
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("errors")).unwrap();
+    std::fs::write(server.root().join("errors/401.html"), "<p>Log in</p>").unwrap();
+    std::fs::write(server.root().join("404.html"), "<p>Nothing here</p>").unwrap();
+
+    let response = server.get("/missing.txt").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(body_string(response).await, "<p>Log in</p>");
+
+    let request = Request::get(server.url("/missing.txt"))
+        .header(header::AUTHORIZATION, format!("Basic {}", CREDENTIALS))
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "text/html; charset=utf-8"
+    );
+    assert_eq!(body_string(response).await, "<p>Nothing here</p>");
+}
+
+#[tokio::test]
+async fn spa_mode_falls_back_to_the_root_index() {
+    let server = TestServer::start(Config {
+        spa: true,
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("index.html"), "<div id=app></div>").unwrap();
+    std::fs::write(server.root().join("app.js"), "render()").unwrap();
+
+    let response = server.get("/users/42/settings").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/html");
+    assert_eq!(body_string(response).await, "<div id=app></div>");
+    assert_eq!(body_string(server.get("/app.js").await).await, "render()");
+}
+
+#[tokio::test]
+async fn base_path_prefixes_every_route() {
+    let server = TestServer::start(Config {
+        base_path: "/files".to_string(),
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("docs")).unwrap();
+    std::fs::write(server.root().join("docs/notes.txt"), "notes").unwrap();
+
+    let response = server.get("/files/docs/notes.txt").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "notes");
+    assert_eq!(
+        server.get("/docs/notes.txt").await.status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        server.get("/filesdocs/notes.txt").await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let response = server.get("/files").await;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()[header::LOCATION], "/files/");
+
+    let listing = body_string(server.get("/files/docs/").await).await;
+    assert!(listing.contains("<title>Index of /files/docs/</title>"));
+    assert!(listing.contains(r#"href="notes.txt""#));
+}
+
+#[tokio::test]
+async fn index_files_are_configurable() {
+    let server = TestServer::start(Config {
+        index_files: vec!["index.html".to_string(), "default.htm".to_string()],
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("legacy")).unwrap();
+    std::fs::write(server.root().join("legacy/default.htm"), "legacy home").unwrap();
+    assert_eq!(
+        body_string(server.get("/legacy/").await).await,
+        "legacy home"
+    );
+
+    let server = TestServer::start(Config {
+        index_files: Vec::new(),
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("index.html"), "home").unwrap();
+    let listing = body_string(server.get("/").await).await;
+    assert!(listing.contains("index.html"));
+    assert_ne!(listing, "home");
+}
+
+#[tokio::test]
+async fn listings_can_be_disabled() {
+    let server = TestServer::start(Config {
+        listing: false,
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("site")).unwrap();
+    std::fs::write(server.root().join("site/index.html"), "site").unwrap();
+    std::fs::write(server.root().join("notes.txt"), "notes").unwrap();
+
+    assert_eq!(server.get("/").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(body_string(server.get("/site/").await).await, "site");
+    assert_eq!(body_string(server.get("/notes.txt").await).await, "notes");
+}
+
+#[tokio::test]
+async fn thumbnails_link_to_the_encoded_name() {
+    let server = TestServer::start(Config {
+        thumbnails: true,
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("a #1?&\".png"), "").unwrap();
+
+    let listing = body_string(server.get("/").await).await;
+    assert!(
+        listing.contains(r#"src="a%20%231%3F&amp;%22.png?thumb="#),
+        "{}",
+        listing
+    );
+}
+
+#[tokio::test]
+async fn dotfiles_are_hidden_by_default() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join(".env"), "SECRET=1").unwrap();
+    std::fs::create_dir_all(server.root().join(".well-known")).unwrap();
+    std::fs::write(server.root().join(".well-known/security.txt"), "Contact: x").unwrap();
+    std::fs::write(server.root().join("notes.txt"), "notes").unwrap();
+
+    assert_eq!(server.get("/.env").await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(server.get("/%2eenv").await.status(), StatusCode::NOT_FOUND);
+    assert_eq!(
+        body_string(server.get("/.well-known/security.txt").await).await,
+        "Contact: x"
+    );
+    let listing = body_string(server.get("/?format=text").await).await;
+    assert_eq!(listing, ".well-known/\nnotes.txt\n");
+
+    let server = TestServer::start(Config {
+        hidden: true,
+        ..Config::default()
+    });
+    std::fs::write(server.root().join(".env"), "SECRET=1").unwrap();
+    assert_eq!(body_string(server.get("/.env").await).await, "SECRET=1");
+}
+
+#[tokio::test]
+async fn missing_files_are_404() {
+    let server = TestServer::start(Config::default());
+    let response = server.get("/nope.txt").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn range_requests_return_partial_content() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("digits.txt"), "0123456789").unwrap();
+
+    let request = Request::get(server.url("/digits.txt"))
+        .header(header::RANGE, "bytes=2-5")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 2-5/10");
+    assert_eq!(body_string(response).await, "2345");
+
+    let request = Request::get(server.url("/digits.txt"))
+        .header(header::RANGE, "bytes=20-30")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */10");
+
+    let request = Request::get(server.url("/digits.txt"))
+        .header(header::RANGE, "bytes=0-999999")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-9/10");
+
+    // Overlapping and adjacent ranges come back as one.
+    let request = Request::get(server.url("/digits.txt"))
+        .header(header::RANGE, "bytes=4-6, 0-2, 1-3")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes 0-6/10");
+    assert_eq!(body_string(response).await, "0123456");
+
+    std::fs::write(server.root().join("empty.txt"), "").unwrap();
+    for range in ["bytes=0-", "bytes=-5", "bytes=0-0"] {
+        let request = Request::get(server.url("/empty.txt"))
+            .header(header::RANGE, range)
+            .body(Body::empty())
+            .unwrap();
+        let response = server.send(request).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            "{}",
+            range
+        );
+    }
+}
+
+#[tokio::test]
+async fn multiple_ranges_return_multipart_byteranges() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("digits.txt"), "0123456789").unwrap();
+
+    let request = Request::get(server.url("/digits.txt"))
+        .header(header::RANGE, "bytes=0-1, 7-")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    let content_type = response.headers()[header::CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let boundary = content_type
+        .strip_prefix("multipart/byteranges; boundary=")
+        .unwrap();
+    let content_length = response.headers()[header::CONTENT_LENGTH].clone();
+    let body = body_string(response).await;
+    assert_eq!(content_length, body.len().to_string().as_str());
+    assert_eq!(
+        body,
+        format!(
+            "\r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-1/10\r\n\r\n01\
+             \r\n--{0}\r\nContent-Type: text/plain\r\nContent-Range: bytes 7-9/10\r\n\r\n789\
+             \r\n--{0}--\r\n",
+            boundary
+        )
+    );
+}
+
+#[tokio::test]
+async fn if_range_falls_back_to_the_full_file() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("digits.txt"), "0123456789").unwrap();
+    let response = server.get("/digits.txt").await;
+    let last_modified = response.headers()[header::LAST_MODIFIED].clone();
+
+    let request = Request::get(server.url("/digits.txt"))
+        .header(header::RANGE, "bytes=2-5")
+        .header(header::IF_RANGE, last_modified)
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body_string(response).await, "2345");
+
+    let etag = server.get("/digits.txt").await.headers()[header::ETAG].clone();
+    let request = Request::get(server.url("/digits.txt"))
+        .header(header::RANGE, "bytes=2-5")
+        .header(header::IF_RANGE, etag.clone())
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+    assert_eq!(response.headers()[header::ETAG], etag);
+
+    let weak = format!("W/{}", etag.to_str().unwrap());
+    for validator in ["Sun, 06 Nov 1994 08:49:37 GMT", "\"some-etag\"", &weak] {
+        let request = Request::get(server.url("/digits.txt"))
+            .header(header::RANGE, "bytes=2-5")
+            .header(header::IF_RANGE, validator)
+            .body(Body::empty())
+            .unwrap();
+        let response = server.send(request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_string(response).await, "0123456789");
+    }
+}
+
+#[tokio::test]
+async fn honours_if_modified_since() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("notes.txt"), "notes").unwrap();
+
+    let response = server.get("/notes.txt").await;
+    let last_modified = response.headers()[header::LAST_MODIFIED].clone();
+
+    let request = Request::get(server.url("/notes.txt"))
+        .header(header::IF_MODIFIED_SINCE, last_modified.clone())
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    assert_eq!(response.headers()[header::LAST_MODIFIED], last_modified);
+    assert_eq!(body_string(response).await, "");
+
+    let request = Request::get(server.url("/notes.txt"))
+        .header(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "notes");
+}
+
+#[tokio::test]
+async fn honours_if_none_match() {
+    let server = TestServer::start(Config::default());
+    std::fs::write(server.root().join("notes.txt"), "notes").unwrap();
+
+    let response = server.get("/notes.txt").await;
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(etag.starts_with('"') && etag.ends_with("-5\""));
+
+    let listed = format!("\"other\", {}", etag);
+    let weak = format!("W/{}", etag);
+    for tags in [etag.as_str(), &listed, &weak, "*"] {
+        let request = Request::get(server.url("/notes.txt"))
+            .header(header::IF_NONE_MATCH, tags)
+            // A matching `If-None-Match` wins over a stale date.
+            .header(header::IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")
+            .body(Body::empty())
+            .unwrap();
+        let response = server.send(request).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+    }
+
+    let request = Request::get(server.url("/notes.txt"))
+        .header(header::IF_NONE_MATCH, "\"other\"")
+        .header(
+            header::IF_MODIFIED_SINCE,
+            response.headers()[header::LAST_MODIFIED].clone(),
+        )
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "notes");
+}
+
+#[tokio::test]
+async fn sends_cache_control_from_rules() {
+    let server = TestServer::start(Config {
+        cache_rules: vec![
+            cache::parse_no_cache("/docs/*").unwrap(),
+            cache::parse_rule("*.css=max-age=604800").unwrap(),
+        ],
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("docs")).unwrap();
+    std::fs::write(server.root().join("site.css"), "body {}").unwrap();
+    std::fs::write(server.root().join("docs/print.css"), "body {}").unwrap();
+    std::fs::write(server.root().join("notes.txt"), "notes").unwrap();
+
+    let response = server.get("/site.css").await;
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "max-age=604800");
+    let response = server.get("/docs/print.css").await;
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+    let response = server.get("/notes.txt").await;
+    assert!(!response.headers().contains_key(header::CACHE_CONTROL));
+    let response = server.get("/docs/").await;
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn symlinks_out_of_the_root_can_be_refused() {
+    let outside = tempfile::tempdir().unwrap();
+    std::fs::write(outside.path().join("secret.txt"), "secret").unwrap();
+    let server = TestServer::start(Config {
+        follow_symlinks: crate::symlinks::FollowSymlinks::WithinRoot,
+        ..Config::default()
+    });
+    std::os::unix::fs::symlink(outside.path(), server.root().join("escape")).unwrap();
+    std::fs::write(server.root().join("notes.txt"), "notes").unwrap();
+    std::os::unix::fs::symlink("notes.txt", server.root().join("alias.txt")).unwrap();
+
+    assert_eq!(
+        server.get("/escape/secret.txt").await.status(),
+        StatusCode::FORBIDDEN
+    );
+    assert_eq!(body_string(server.get("/alias.txt").await).await, "notes");
+    let listing = body_string(server.get("/?format=text&sort=name").await).await;
+    assert_eq!(listing, "alias.txt\nnotes.txt\n");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn cgi_scripts_run_instead_of_being_served() {
+    use std::os::unix::fs::PermissionsExt;
+    let server = TestServer::start(Config {
+        cgi: Some("/cgi-bin".to_string()),
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("cgi-bin")).unwrap();
+    let script = server.root().join("cgi-bin/echo.sh");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\nprintf 'Content-Type: text/plain\\r\\nX-Path: %s\\r\\n\\r\\n' \"$PATH_INFO\"\n\
+         printf '%s %s ' \"$REQUEST_METHOD\" \"$QUERY_STRING\"\ncat\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+    std::fs::write(server.root().join("cgi-bin/data.txt"), "data").unwrap();
+
+    let request = Request::post(server.url("/cgi-bin/echo.sh/extra?x=1"))
+        .body(Body::from("posted"))
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-path"], "/extra");
+    assert_eq!(body_string(response).await, "POST x=1 posted");
+    assert_eq!(
+        server.get("/cgi-bin/data.txt").await.status(),
+        StatusCode::FORBIDDEN
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn cgi_scripts_see_only_the_authenticated_user() {
+    use std::os::unix::fs::PermissionsExt;
+    let server = TestServer::start(Config {
+        auth: Some(CREDENTIALS.to_string()),
+        cgi: Some("/cgi-bin".to_string()),
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("cgi-bin")).unwrap();
+    let script = server.root().join("cgi-bin/user.sh");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\nprintf 'Content-Type: text/plain\\r\\n\\r\\n%s %s' \"$AUTH_TYPE\" \"$REMOTE_USER\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let request = Request::get(server.url("/cgi-bin/user.sh"))
+        .header(header::AUTHORIZATION, format!("Basic {}", CREDENTIALS))
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(body_string(response).await, "Basic user");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn cgi_scripts_do_not_get_a_proxy_from_the_client() {
+    use std::os::unix::fs::PermissionsExt;
+    let server = TestServer::start(Config {
+        cgi: Some("/cgi-bin".to_string()),
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("cgi-bin")).unwrap();
+    let script = server.root().join("cgi-bin/proxy.sh");
+    std::fs::write(
+        &script,
+        "#!/bin/sh\nprintf 'Content-Type: text/plain\\r\\n\\r\\n%s|%s' \"${HTTP_PROXY-unset}\" \"$HTTP_X_OTHER\"\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+    let request = Request::get(server.url("/cgi-bin/proxy.sh"))
+        .header("proxy", "http://evil")
+        .header("x-other", "kept")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(body_string(response).await, "unset|kept");
+}
+
+#[tokio::test]
+async fn files_cannot_be_uploaded_or_moved_into_the_cgi_directory() {
+    let server = TestServer::start(Config {
+        upload: true,
+        upload_strip_prefix: Some("/drop".to_string()),
+        cgi: Some("/cgi-bin".to_string()),
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("cgi-bin")).unwrap();
+    std::fs::create_dir(server.root().join("site")).unwrap();
+    std::fs::write(server.root().join("script.sh"), "#!/bin/sh\n").unwrap();
+
+    let request = multipart_upload(server.url("/drop/cgi-bin/"), "evil.sh", "#!/bin/sh\n");
+    assert_eq!(server.send(request).await.status(), StatusCode::FORBIDDEN);
+    assert!(!server.root().join("cgi-bin/evil.sh").exists());
+
+    let moves = [
+        "/script.sh?action=move&to=/cgi-bin/script.sh",
+        "/site?action=move&to=/cgi-bin",
+    ];
+    for uri in moves {
+        let request = Request::post(server.url(uri)).body(Body::empty()).unwrap();
+        assert_eq!(
+            server.send(request).await.status(),
+            StatusCode::FORBIDDEN,
+            "{}",
+            uri
+        );
+    }
+    assert!(server.root().join("script.sh").exists());
+
+    let request = multipart_upload(server.url("/drop/site/"), "page.html", "page");
+    assert_eq!(server.send(request).await.status(), StatusCode::SEE_OTHER);
+}
+
+#[tokio::test]
+async fn traversal_is_rejected() {
+    let server = TestServer::start(Config::default());
+    std::fs::create_dir(server.root().join("sub")).unwrap();
+
+    let response = server.get("/sub/%2e%2e/%2e%2e/etc/passwd").await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn auth_challenges_and_accepts_credentials() {
+    let server = TestServer::start(Config {
+        auth: Some(CREDENTIALS.to_string()),
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("private.txt"), "secret data").unwrap();
+
+    let response = server.get("/private.txt").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers()[header::WWW_AUTHENTICATE],
+        r#"Basic realm="Restricted""#
+    );
+
+    let request = Request::get(server.url("/private.txt"))
+        .header(header::AUTHORIZATION, format!("Basic {}", CREDENTIALS))
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "secret data");
+
+    let lowercase = format!("basic {}", CREDENTIALS);
+    let malformed = [
+        "Basic",
+        "Basic !!!",
+        "Basic dXNlcg==",
+        "Basic dXNlcjpzZWNyZX",
+    ];
+    for (value, status) in [(lowercase.as_str(), StatusCode::OK)]
+        .into_iter()
+        .chain(malformed.map(|value| (value, StatusCode::UNAUTHORIZED)))
+    {
+        let request = Request::get(server.url("/private.txt"))
+            .header(header::AUTHORIZATION, value)
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(server.send(request).await.status(), status, "{}", value);
+    }
+}
+
+#[tokio::test]
+async fn bearer_tokens_are_accepted() {
+    let server = TestServer::start(Config {
+        tokens: vec!["ci-secret".to_string(), "other".to_string()],
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("private.txt"), "secret data").unwrap();
+
+    assert_eq!(
+        server.get("/private.txt").await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    for (token, status) in [
+        ("ci-secret", StatusCode::OK),
+        ("other", StatusCode::OK),
+        ("ci-secre", StatusCode::UNAUTHORIZED),
+    ] {
+        let request = Request::get(server.url("/private.txt"))
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(server.send(request).await.status(), status, "{}", token);
+    }
+}
+
+#[tokio::test]
+async fn login_page_starts_a_session() {
+    let sessions = Arc::new(Sessions::new(Duration::from_secs(60), false).unwrap());
+    let server = TestServer::start(Config {
+        auth: Some(CREDENTIALS.to_string()),
+        sessions: Some(Arc::clone(&sessions)),
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("private.txt"), "secret data").unwrap();
+    let login_to = |next: &str, password: &str| {
+        Request::post(server.url("/_login"))
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!(
+                "next={}&user=user&password={}",
+                next, password
+            )))
+            .unwrap()
+    };
+    let login = |password: &str| login_to("%2Fprivate.txt", password);
+
+    let browser = Request::get(server.url("/private.txt"))
+        .header(header::ACCEPT, "text/html")
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(browser).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        response.headers()[header::LOCATION],
+        "/_login?next=/private.txt"
+    );
+    assert_eq!(
+        server.get("/private.txt").await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+    assert!(
+        body_string(server.get("/_login?next=/private.txt").await)
+            .await
+            .contains(r#"value="/private.txt""#)
+    );
+
+    let response = server.send(login("wrong")).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert!(!response.headers().contains_key(header::SET_COOKIE));
+    for next in ["/%0a", "/%09/evil.example", "/%0d"] {
+        let response = server.send(login_to(next, "secret")).await;
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(response.headers()[header::LOCATION], "/", "{}", next);
+    }
+    let response = server.send(login("secret")).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[header::LOCATION], "/private.txt");
+    let cookie = response.headers()[header::SET_COOKIE]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
+
+    let request = Request::get(server.url("/private.txt"))
+        .header(header::COOKIE, &cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "secret data");
+    // The session ends with the account, as after a reload removing it.
+    let without_user = TestServer::start(Config {
+        users: Some(Arc::new(Users::parse("other:secret").unwrap())),
+        sessions: Some(sessions),
+        ..Config::default()
+    });
+    let request = Request::get(without_user.url("/"))
+        .header(header::COOKIE, &cookie)
+        .body(Body::empty())
+        .unwrap();
+    let response = without_user.send(request).await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = server.get("/_logout").await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(
+        response.headers()[header::SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .contains("Max-Age=0")
+    );
+}
+
+#[tokio::test]
+async fn auth_file_accounts_are_accepted() {
+    let users = Users::parse(
+        "alice:$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5\nbob:hunter2",
+    )
+    .unwrap();
+    let server = TestServer::start(Config {
+        users: Some(Arc::new(users)),
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("private.txt"), "secret data").unwrap();
+
+    for (credentials, status) in [
+        ("alice:Hello world!", StatusCode::OK),
+        ("bob:hunter2", StatusCode::OK),
+        ("bob:Hello world!", StatusCode::UNAUTHORIZED),
+        ("mallory:hunter2", StatusCode::UNAUTHORIZED),
+    ] {
+        let request = Request::get(server.url("/private.txt"))
+            .header(
+                header::AUTHORIZATION,
+                format!("Basic {}", general_purpose::STANDARD.encode(credentials)),
+            )
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            server.send(request).await.status(),
+            status,
+            "{}",
+            credentials
+        );
+    }
+}
+
+#[tokio::test]
+async fn uploads_store_files() {
+    let server = TestServer::start(Config {
+        upload: true,
+        ..Config::default()
+    });
+
+    let response = server
+        .send(multipart_upload(server.url("/"), "up.txt", "uploaded"))
+        .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        std::fs::read_to_string(server.root().join("up.txt")).unwrap(),
+        "uploaded"
+    );
+}
+
+#[tokio::test]
+async fn writes_need_a_writer_account() {
+    let server = TestServer::start(Config {
+        upload: true,
+        auth: Some(CREDENTIALS.to_string()),
+        protect_writes: true,
+        write_users: Some(Arc::new(Users::parse("writer:pencil").unwrap())),
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("public.txt"), "public").unwrap();
+    let upload = |credentials: Option<&str>| {
+        let mut request = multipart_upload(server.url("/"), "up.txt", "uploaded");
+        if let Some(credentials) = credentials {
+            let value = format!("Basic {}", general_purpose::STANDARD.encode(credentials));
+            request
+                .headers_mut()
+                .insert(header::AUTHORIZATION, value.parse().unwrap());
+        }
+        request
+    };
+
+    assert_eq!(server.get("/public.txt").await.status(), StatusCode::OK);
+    for credentials in [None, Some("user:secret"), Some("writer:wrong")] {
+        let response = server.send(upload(credentials)).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    assert!(!server.root().join("up.txt").exists());
+    let response = server.send(upload(Some("writer:pencil"))).await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert!(server.root().join("up.txt").exists());
+}
+
+#[tokio::test]
+async fn excluded_paths_are_hidden_everywhere() {
+    let server = TestServer::start(Config {
+        upload: true,
+        exclude: vec!["*.key".to_string(), "secrets/*".to_string()],
+        ..Config::default()
+    });
+    std::fs::create_dir(server.root().join("secrets")).unwrap();
+    std::fs::write(server.root().join("secrets/db.txt"), "password").unwrap();
+    std::fs::write(server.root().join("server.key"), "key").unwrap();
+    std::fs::write(server.root().join("notes.txt"), "notes").unwrap();
+
+    assert_eq!(
+        server.get("/server.key").await.status(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(
+        server.get("/secrets/db.txt").await.status(),
+        StatusCode::NOT_FOUND
+    );
+    let listing = body_string(server.get("/?format=text").await).await;
+    assert_eq!(listing, "secrets/\nnotes.txt\n");
+    assert_eq!(
+        body_string(server.get("/secrets/?format=text").await).await,
+        ""
+    );
+
+    let response = server
+        .send(multipart_upload(server.url("/"), "new.key", "uploaded"))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!server.root().join("new.key").exists());
+}
+
+#[tokio::test]
+async fn mounts_serve_directories_with_their_own_settings() {
+    let docs = tempfile::tempdir().unwrap();
+    let drop = tempfile::tempdir().unwrap();
+    std::fs::write(docs.path().join("guide.txt"), "guide").unwrap();
+    let mount = |dir: &TempDir, prefix: &str, upload: bool| {
+        let config = Config {
+            root: dir.path().to_str().unwrap().to_string(),
+            base_path: prefix.to_string(),
+            upload,
+            ..Config::default()
+        };
+        (prefix.to_string(), std::sync::Arc::new(config))
+    };
+    let server = TestServer::start(Config {
+        upload: true,
+        mounts: vec![mount(&docs, "/docs", false), mount(&drop, "/drop", true)],
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("root.txt"), "root").unwrap();
+
+    assert_eq!(body_string(server.get("/root.txt").await).await, "root");
+    assert_eq!(
+        body_string(server.get("/docs/guide.txt").await).await,
+        "guide"
+    );
+    let response = server.get("/docs").await;
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    assert_eq!(response.headers()[header::LOCATION], "/docs/");
+
+    let response = server
+        .send(multipart_upload(server.url("/docs/"), "up.txt", "uploaded"))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = server
+        .send(multipart_upload(server.url("/drop/"), "up.txt", "uploaded"))
+        .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(
+        std::fs::read_to_string(drop.path().join("up.txt")).unwrap(),
+        "uploaded"
+    );
+}
+
+#[tokio::test]
+async fn vhosts_pick_the_root_by_host() {
+    let photos = tempfile::tempdir().unwrap();
+    std::fs::write(photos.path().join("name.txt"), "photos").unwrap();
+    let hosted = Config {
+        root: photos.path().to_str().unwrap().to_string(),
+        ..Config::default()
+    };
+    let server = TestServer::start(Config {
+        vhosts: vec![("photos.lan".to_string(), std::sync::Arc::new(hosted))],
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("name.txt"), "default").unwrap();
+
+    let get_for = |host: &str| {
+        Request::get(server.url("/name.txt"))
+            .header(header::HOST, host)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = server.send(get_for("Photos.LAN:8000")).await;
+    assert_eq!(body_string(response).await, "photos");
+    let response = server.send(get_for("files.lan")).await;
+    assert_eq!(body_string(response).await, "default");
+    assert_eq!(body_string(server.get("/name.txt").await).await, "default");
+}
+
+#[tokio::test]
+async fn proxy_forwards_prefixed_requests_upstream() {
+    let upstream = TestServer::start(Config::default());
+    std::fs::create_dir(upstream.root().join("api")).unwrap();
+    std::fs::write(upstream.root().join("api/data.json"), "{}").unwrap();
+    let server = TestServer::start(Config {
+        proxies: vec![crate::proxy::parse_rule(&format!("/api=http://{}", upstream.addr)).unwrap()],
+        ..Config::default()
+    });
+    std::fs::write(server.root().join("index.html"), "app").unwrap();
+
+    let response = server.get("/api/data.json").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "{}");
+    assert_eq!(body_string(server.get("/").await).await, "app");
+
+    // Credentials for this server are not passed on.
+    let guarded = TestServer::start(Config {
+        auth: Some(CREDENTIALS.to_string()),
+        ..Config::default()
+    });
+    std::fs::create_dir(guarded.root().join("api")).unwrap();
+    let server = TestServer::start(Config {
+        auth: Some(CREDENTIALS.to_string()),
+        proxies: vec![crate::proxy::parse_rule(&format!("/api=http://{}", guarded.addr)).unwrap()],
+        ..Config::default()
+    });
+    let request = Request::get(server.url("/api/"))
+        .header(header::AUTHORIZATION, format!("Basic {}", CREDENTIALS))
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(
+        server.send(request).await.status(),
+        StatusCode::UNAUTHORIZED
+    );
+
+    let unreachable = TestServer::start(Config {
+        proxies: vec![crate::proxy::parse_rule("/api=http://127.0.0.1:1").unwrap()],
+        ..Config::default()
+    });
+    assert_eq!(
+        unreachable.get("/api/data.json").await.status(),
+        StatusCode::BAD_GATEWAY
+    );
+}
+
+#[tokio::test]
+async fn uploads_are_refused_when_disabled() {
+    let server = TestServer::start(Config::default());
+
+    let response = server
+        .send(multipart_upload(server.url("/"), "up.txt", "uploaded"))
+        .await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!server.root().join("up.txt").exists());
+}
+
+#[tokio::test]
+async fn webdav_writes_and_lists_files() {
+    let server = TestServer::start(Config {
+        webdav: true,
+        upload: true,
+        ..Config::default()
+    });
+    let dav = |method: &str, path: &str, body: &'static str| {
+        Request::builder()
+            .method(method)
+            .uri(server.url(path))
+            .header("depth", "1")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let response = server.send(dav("MKCOL", "/docs", "")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = server.send(dav("PUT", "/docs/a%20b.txt", "hello")).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(
+        std::fs::read_to_string(server.root().join("docs/a b.txt")).unwrap(),
+        "hello"
+    );
+
+    let response = server.send(dav("PROPFIND", "/docs", "")).await;
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let body = body_string(response).await;
+    assert!(body.contains("<D:href>/docs/</D:href>"));
+    assert!(body.contains("<D:href>/docs/a%20b.txt</D:href>"));
+    assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+
+    let response = server.send(dav("DELETE", "/docs", "")).await;
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!server.root().join("docs").exists());
+}
+
+#[tokio::test]
+async fn webdav_is_read_only_without_uploads() {
+    let server = TestServer::start(Config {
+        webdav: true,
+        ..Config::default()
+    });
+    let request = Request::builder()
+        .method("PUT")
+        .uri(server.url("/new.txt"))
+        .body(Body::from("data"))
+        .unwrap();
+    let response = server.send(request).await;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!server.root().join("new.txt").exists());
+}
+
+#[tokio::test]
+async fn listings_carry_security_headers() {
+    let server = TestServer::start(Config::default());
+    let response = server.get("/").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::X_CONTENT_TYPE_OPTIONS],
+        "nosniff"
+    );
+    assert!(
+        response.headers()[header::CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .starts_with("default-src 'none'")
+    );
+
+    let server = TestServer::start(Config {
+        csp: Some(header::HeaderValue::from_static("default-src 'self'")),
+        ..Config::default()
+    });
+    let response = server.get("/").await;
+    assert_eq!(
+        response.headers()[header::CONTENT_SECURITY_POLICY],
+        "default-src 'self'"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn serves_over_unix_sockets() {
+    let root = TempDir::new().unwrap();
+    std::fs::write(root.path().join("hello.txt"), "hello world").unwrap();
+    let socket = root.path().join("samserve.sock");
+    let listener = listener::bind_unix(&socket, 0o600).unwrap();
+    let config = Config {
+        root: root.path().to_str().unwrap().to_string(),
+        ..Config::default()
+    };
+    tokio::spawn(crate::server::run_server(
+        listener::Listener::Unix(listener),
+        config::SharedConfig::new(config),
+        None,
+        std::future::pending(),
+    ));
+
+    let stream = tokio::net::UnixStream::connect(&socket).await.unwrap();
+    let (mut sender, connection) = hyper::client::conn::handshake(stream).await.unwrap();
+    tokio::spawn(connection);
+    let request = Request::get("/hello.txt")
+        .header(header::HOST, "localhost")
+        .body(Body::empty())
+        .unwrap();
+    let response = sender.send_request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_string(response).await, "hello world");
+}
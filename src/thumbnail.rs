@@ -0,0 +1,75 @@
+use crate::storage::{self, Storage};
+use hyper::body::Bytes;
+use image::ImageFormat;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use std::time::SystemTime;
+
+/// Smallest and largest edge length accepted for `?thumb=<size>`.
+pub const MIN_SIZE: u32 = 16;
+pub const MAX_SIZE: u32 = 512;
+
+/// Edge length used by the directory listing.
+pub const LISTING_SIZE: u32 = 64;
+
+/// Upper bound on cached thumbnails before the cache is reset.
+const CACHE_CAPACITY: usize = 1024;
+
+type CacheKey = (PathBuf, SystemTime, u32);
+
+static CACHE: LazyLock<Mutex<HashMap<CacheKey, Bytes>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Whether the file extension is one of the decodable image formats.
+pub fn is_image(path: &Path) -> bool {
+    ImageFormat::from_path(path).is_ok_and(|format| {
+        matches!(
+            format,
+            ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Gif | ImageFormat::WebP
+        )
+    })
+}
+
+/// Returns a PNG thumbnail of the image `path` in `storage`, fitting in
+/// `size`x`size`, reusing a cached copy while the source file's modification
+/// time is unchanged. Without a modification time nothing is cached.
+pub async fn thumbnail(storage: &dyn Storage, path: &Path, size: u32) -> Result<Bytes, String> {
+    let modified = storage
+        .metadata(path)
+        .await
+        .map_err(|e| e.to_string())?
+        .modified;
+    let key = modified.map(|modified| (path.to_path_buf(), modified, size));
+    if let Some(key) = &key
+        && let Some(bytes) = CACHE.lock().unwrap().get(key)
+    {
+        return Ok(bytes.clone());
+    }
+
+    let format = ImageFormat::from_path(path).map_err(|e| e.to_string())?;
+    let stream = storage.open(path).await.map_err(|e| e.to_string())?;
+    let data = storage::collect(stream).await.map_err(|e| e.to_string())?;
+    let bytes = tokio::task::spawn_blocking(move || -> Result<Bytes, String> {
+        let image =
+            image::load_from_memory_with_format(&data, format).map_err(|e| e.to_string())?;
+        let mut out = Cursor::new(Vec::new());
+        image
+            .thumbnail(size, size)
+            .write_to(&mut out, ImageFormat::Png)
+            .map_err(|e| e.to_string())?;
+        Ok(Bytes::from(out.into_inner()))
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    if let Some(key) = key {
+        let mut cache = CACHE.lock().unwrap();
+        if cache.len() >= CACHE_CAPACITY {
+            cache.clear();
+        }
+        cache.insert(key, bytes.clone());
+    }
+    Ok(bytes)
+}
@@ -0,0 +1,87 @@
+use chrono::format::StrftimeItems;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, Utc};
+use std::time::SystemTime;
+
+/// Timezone listing dates are shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timezone {
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+/// Parses `--timezone`: `local`, `utc` or a fixed offset such as `+02:00`.
+pub fn parse_timezone(value: &str) -> Result<Timezone, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "local" => Ok(Timezone::Local),
+        "utc" | "z" => Ok(Timezone::Utc),
+        _ => value
+            .parse::<FixedOffset>()
+            .map(Timezone::Fixed)
+            .map_err(|_| {
+                format!(
+                    "invalid timezone {:?}: expected local, utc or an offset like +02:00",
+                    value
+                )
+            }),
+    }
+}
+
+/// Validates a strftime pattern up front so a typo fails at startup rather
+/// than on every listing.
+pub fn parse_date_format(value: &str) -> Result<String, String> {
+    StrftimeItems::new(value)
+        .parse()
+        .map(|_| value.to_string())
+        .map_err(|_| format!("invalid strftime format {:?}", value))
+}
+
+/// Formats `time` with the (already validated) strftime `format`.
+pub fn format_time(time: SystemTime, format: &str, timezone: Timezone) -> String {
+    let utc = DateTime::<Utc>::from(time);
+    match timezone {
+        Timezone::Local => utc.with_timezone(&Local).format(format).to_string(),
+        Timezone::Utc => utc.format(format).to_string(),
+        Timezone::Fixed(offset) => utc.with_timezone(&offset).format(format).to_string(),
+    }
+}
+
+/// Formats `time` as an HTTP-date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn format_http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// Parses an HTTP-date in the preferred format or either of the obsolete
+/// ones (RFC 850 and asctime) that recipients must still accept.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    [
+        "%a, %d %b %Y %H:%M:%S GMT",
+        "%A, %d-%b-%y %H:%M:%S GMT",
+        "%a %b %e %H:%M:%S %Y",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value.trim(), format).ok())
+    .map(|date| date.and_utc().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn parses_and_formats_http_dates() {
+        let time = UNIX_EPOCH + Duration::from_secs(784_111_777);
+        assert_eq!(format_http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        for value in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(parse_http_date(value), Some(time), "{}", value);
+        }
+        assert_eq!(parse_http_date("yesterday"), None);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::listener::RemoteAddr;
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use log::{info, warn};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
+use sha2::{Digest as _, Sha256};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::server::danger::ClientCertVerifier;
+use tokio_rustls::rustls::server::{ResolvesServerCert, WantsServerCert, WebPkiClientVerifier};
+use tokio_rustls::rustls::{ConfigBuilder, RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+
+/// Handshakes that take longer than this are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds the TLS acceptor from a PEM certificate chain and private key.
+pub fn acceptor_from_pem(
+    cert: &Path,
+    key: &Path,
+    client_auth: Option<Arc<dyn ClientCertVerifier>>,
+) -> Result<TlsAcceptor, String> {
+    let chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("failed to read certificate {:?}: {}", cert, e))?;
+    if chain.is_empty() {
+        return Err(format!("no certificate found in {:?}", cert));
+    }
+    let key = PrivateKeyDer::from_pem_file(key)
+        .map_err(|e| format!("failed to read private key {:?}: {}", key, e))?;
+    acceptor(chain, key, client_auth)
+}
+
+/// Generates an in-memory self-signed certificate valid for `names` and
+/// logs its SHA-256 fingerprint so clients can verify it out of band.
+pub fn self_signed_acceptor(
+    names: Vec<String>,
+    client_auth: Option<Arc<dyn ClientCertVerifier>>,
+) -> Result<TlsAcceptor, String> {
+    let rcgen::CertifiedKey { cert, signing_key } =
+        rcgen::generate_simple_self_signed(names.clone())
+            .map_err(|e| format!("failed to generate certificate: {}", e))?;
+    info!(
+        "Generated self-signed certificate | names: {:?} | sha256: {}",
+        names,
+        fingerprint(cert.der())
+    );
+    let key = PrivateKeyDer::Pkcs8(signing_key.serialize_der().into());
+    acceptor(vec![cert.der().clone()], key, client_auth)
+}
+
+/// SHA-256 of the DER certificate as colon-separated hex, the form browsers
+/// and `openssl x509 -fingerprint -sha256` display.
+fn fingerprint(cert: &CertificateDer) -> String {
+    Sha256::digest(cert.as_ref())
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Builds a TLS acceptor that asks `resolver` for the certificate on each
+/// handshake, so it can be replaced while the server runs.
+pub fn resolver_acceptor(
+    resolver: Arc<dyn ResolvesServerCert>,
+    client_auth: Option<Arc<dyn ClientCertVerifier>>,
+) -> TlsAcceptor {
+    finish(builder(client_auth).with_cert_resolver(resolver))
+}
+
+/// Verifies client certificates against the CA certificates in `ca`.
+/// Unless `required`, clients may connect without one and are left to
+/// other authentication.
+pub fn client_verifier(ca: &Path, required: bool) -> Result<Arc<dyn ClientCertVerifier>, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(ca)
+        .map_err(|e| format!("failed to read client CA {:?}: {}", ca, e))?
+    {
+        let cert = cert.map_err(|e| format!("failed to read client CA {:?}: {}", ca, e))?;
+        roots
+            .add(cert)
+            .map_err(|e| format!("invalid client CA certificate in {:?}: {}", ca, e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("no certificate found in {:?}", ca));
+    }
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots));
+    let builder = if required {
+        builder
+    } else {
+        builder.allow_unauthenticated()
+    };
+    builder
+        .build()
+        .map_err(|e| format!("invalid client CA {:?}: {}", ca, e))
+}
+
+fn builder(
+    client_auth: Option<Arc<dyn ClientCertVerifier>>,
+) -> ConfigBuilder<ServerConfig, WantsServerCert> {
+    match client_auth {
+        Some(verifier) => ServerConfig::builder().with_client_cert_verifier(verifier),
+        None => ServerConfig::builder().with_no_client_auth(),
+    }
+}
+
+fn acceptor(
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    client_auth: Option<Arc<dyn ClientCertVerifier>>,
+) -> Result<TlsAcceptor, String> {
+    let config = builder(client_auth)
+        .with_single_cert(chain, key)
+        .map_err(|e| format!("invalid certificate or key: {}", e))?;
+    Ok(finish(config))
+}
+
+fn finish(mut config: ServerConfig) -> TlsAcceptor {
+    // hyper picks the protocol from the connection preface, so offering h2
+    // is all HTTP/2 over TLS needs.
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    TlsAcceptor::from(Arc::new(config))
+}
+
+type Handshake =
+    Pin<Box<dyn Future<Output = (SocketAddr, io::Result<TlsStream<AddrStream>>)> + Send>>;
+
+/// Accepts TCP connections and performs TLS handshakes concurrently, so a
+/// slow client cannot hold up connections behind it.
+pub struct TlsIncoming {
+    incoming: AddrIncoming,
+    acceptor: TlsAcceptor,
+    handshakes: FuturesUnordered<Handshake>,
+}
+
+impl TlsIncoming {
+    pub fn new(incoming: AddrIncoming, acceptor: TlsAcceptor) -> Self {
+        TlsIncoming {
+            incoming,
+            acceptor,
+            handshakes: FuturesUnordered::new(),
+        }
+    }
+}
+
+impl Accept for TlsIncoming {
+    type Conn = TlsStream<AddrStream>;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = &mut *self;
+        while let Poll::Ready(Some(stream)) = Pin::new(&mut this.incoming).poll_accept(cx) {
+            let stream = stream?;
+            let remote_addr = RemoteAddr::remote_addr(&stream);
+            let accept = this.acceptor.accept(stream);
+            this.handshakes.push(Box::pin(async move {
+                let result = match tokio::time::timeout(HANDSHAKE_TIMEOUT, accept).await {
+                    Ok(result) => result,
+                    Err(_) => Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        "handshake timed out",
+                    )),
+                };
+                (remote_addr, result)
+            }));
+        }
+        while let Poll::Ready(Some((remote_addr, result))) = this.handshakes.poll_next_unpin(cx) {
+            match result {
+                Ok(stream) => return Poll::Ready(Some(Ok(stream))),
+                Err(err) => warn!(
+                    "TLS handshake failed | error: {} | remote: {}",
+                    err, remote_addr
+                ),
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl RemoteAddr for TlsStream<AddrStream> {
+    fn remote_addr(&self) -> SocketAddr {
+        RemoteAddr::remote_addr(self.get_ref().0)
+    }
+}
+
+/// The authenticated client behind a connection, if any.
+pub trait ClientIdentity {
+    /// Subject of the client certificate verified during the handshake.
+    fn client_subject(&self) -> Option<String>;
+}
+
+impl ClientIdentity for AddrStream {
+    fn client_subject(&self) -> Option<String> {
+        None
+    }
+}
+
+#[cfg(unix)]
+impl ClientIdentity for tokio::net::UnixStream {
+    fn client_subject(&self) -> Option<String> {
+        None
+    }
+}
+
+impl ClientIdentity for TlsStream<AddrStream> {
+    fn client_subject(&self) -> Option<String> {
+        let cert = self.get_ref().1.peer_certificates()?.first()?;
+        let (_, cert) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+        Some(cert.subject().to_string())
+    }
+}
@@ -0,0 +1,122 @@
+use futures_util::Stream;
+use hyper::body::{Bytes, HttpBody};
+use hyper::{Body, Method, Response, StatusCode, Uri, header};
+use log::{info, warn};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+/// Request details reported once the response body has been sent.
+pub struct TransferInfo {
+    pub method: Method,
+    pub uri: Uri,
+    pub remote_addr: std::net::SocketAddr,
+    /// The Basic Auth user, once authenticated.
+    pub user: Option<String>,
+    pub start: Instant,
+}
+
+/// Wraps the response body so the log line is written when the last byte
+/// has been handed to hyper (or the client goes away), not when the
+/// response headers are ready.
+pub fn log_transfer(response: Response<Body>, info: TransferInfo) -> Response<Body> {
+    let status = response.status();
+    if response.body().is_end_stream() {
+        log_done(&info, status, 0, true);
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    // Wrapping hides the body's exact size from hyper, so keep it explicit.
+    if !parts.headers.contains_key(header::CONTENT_LENGTH)
+        && let Some(len) = HttpBody::size_hint(&body).exact()
+    {
+        parts.headers.insert(header::CONTENT_LENGTH, len.into());
+    }
+    let expected = parts
+        .headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    let body = Body::wrap_stream(LoggedBody {
+        inner: body,
+        info,
+        status,
+        bytes: 0,
+        expected,
+        finished: false,
+    });
+    Response::from_parts(parts, body)
+}
+
+fn log_done(info: &TransferInfo, status: StatusCode, bytes: u64, complete: bool) {
+    if complete {
+        info!(
+            "Transfer complete | method: {} | uri: {} | status: {} | bytes: {} | duration: {:?} | user: {} | remote: {}",
+            info.method,
+            info.uri,
+            status,
+            bytes,
+            info.start.elapsed(),
+            info.user.as_deref().unwrap_or("-"),
+            info.remote_addr
+        );
+    } else {
+        warn!(
+            "Transfer aborted | method: {} | uri: {} | status: {} | bytes: {} | duration: {:?} | user: {} | remote: {}",
+            info.method,
+            info.uri,
+            status,
+            bytes,
+            info.start.elapsed(),
+            info.user.as_deref().unwrap_or("-"),
+            info.remote_addr
+        );
+    }
+}
+
+struct LoggedBody {
+    inner: Body,
+    info: TransferInfo,
+    status: StatusCode,
+    bytes: u64,
+    /// hyper stops polling once Content-Length bytes were sent, so reaching
+    /// it counts as completion even without seeing the end of the stream.
+    expected: Option<u64>,
+    finished: bool,
+}
+
+impl LoggedBody {
+    fn finish(&mut self) {
+        if !self.finished {
+            self.finished = true;
+            log_done(&self.info, self.status, self.bytes, true);
+        }
+    }
+}
+
+impl Stream for LoggedBody {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_data(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                self.bytes += chunk.len() as u64;
+                if self.expected.is_some_and(|expected| self.bytes >= expected) {
+                    self.finish();
+                }
+            }
+            Poll::Ready(None) => self.finish(),
+            _ => {}
+        }
+        poll
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        if !self.finished {
+            log_done(&self.info, self.status, self.bytes, false);
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! `--auth-file`: Basic Auth accounts from an htpasswd-style file, one
+//! `user:password` per line; a hashed `--auth` is checked the same way. Passwords are SHA-crypt hashes (`$5$...` or
+//! `$6$...`, as made by `openssl passwd -6` or `mkpasswd -m sha-512`),
+//! bcrypt (`htpasswd -B`), argon2 (`argon2` CLI, PHC format) or, as
+//! `htpasswd -p` writes them, plain text. Blank lines and lines starting
+//! with `#` are skipped.
+
+use argon2::{Argon2, PasswordHash, PasswordVerifier};
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Characters of the base64 variant crypt(3) hashes are written in.
+const CRYPT_ALPHABET: &[u8; 64] =
+    b"./0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+const DEFAULT_ROUNDS: u32 = 5000;
+const MAX_SALT_LEN: usize = 16;
+
+/// The order SHA-crypt writes the digest bytes in, three at a time.
+const SHA256_ORDER: [(usize, usize, usize); 10] = [
+    (0, 10, 20),
+    (21, 1, 11),
+    (12, 22, 2),
+    (3, 13, 23),
+    (24, 4, 14),
+    (15, 25, 5),
+    (6, 16, 26),
+    (27, 7, 17),
+    (18, 28, 8),
+    (9, 19, 29),
+];
+const SHA512_ORDER: [(usize, usize, usize); 21] = [
+    (0, 21, 42),
+    (22, 43, 1),
+    (44, 2, 23),
+    (3, 24, 45),
+    (25, 46, 4),
+    (47, 5, 26),
+    (6, 27, 48),
+    (28, 49, 7),
+    (50, 8, 29),
+    (9, 30, 51),
+    (31, 52, 10),
+    (53, 11, 32),
+    (12, 33, 54),
+    (34, 55, 13),
+    (56, 14, 35),
+    (15, 36, 57),
+    (37, 58, 16),
+    (59, 17, 38),
+    (18, 39, 60),
+    (40, 61, 19),
+    (62, 20, 41),
+];
+
+#[derive(Debug)]
+enum Password {
+    Plain(String),
+    /// A `$5$` (SHA-256) or `$6$` (SHA-512) hash, kept whole.
+    ShaCrypt(String),
+    /// A `$2a$`, `$2b$` or `$2y$` hash.
+    Bcrypt(String),
+    /// An `$argon2i$`, `$argon2d$` or `$argon2id$` PHC string.
+    Argon2(String),
+}
+
+impl Password {
+    fn matches(&self, password: &str) -> bool {
+        match self {
+            Password::Plain(expected) => secrets_match(expected, password),
+            Password::ShaCrypt(hash) => sha_crypt_matches(hash, password),
+            Password::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Password::Argon2(hash) => PasswordHash::new(hash).is_ok_and(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            }),
+        }
+    }
+}
+
+/// The accounts of an `--auth-file`, by user name.
+#[derive(Debug, Default)]
+pub struct Users {
+    accounts: HashMap<String, Password>,
+}
+
+impl Users {
+    /// Reads and parses the file at `path`.
+    pub fn load(path: &Path) -> Result<Users, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {:?}: {}", path, e))?;
+        Users::parse(&text).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Users, String> {
+        let mut users = Users::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            users
+                .add(line)
+                .map_err(|e| format!("line {}: {}", number + 1, e))?;
+        }
+        Ok(users)
+    }
+
+    /// Adds the account of a `user:password` line.
+    pub fn add(&mut self, line: &str) -> Result<(), String> {
+        let Some((user, password)) = line.split_once(':') else {
+            return Err("expected user:password".to_string());
+        };
+        if user.is_empty() {
+            return Err("empty user name".to_string());
+        }
+        let password = if password.starts_with("$5$") || password.starts_with("$6$") {
+            if split_crypt(password).is_none() {
+                return Err(format!("malformed hash for {:?}", user));
+            }
+            Password::ShaCrypt(password.to_string())
+        } else if ["$2a$", "$2b$", "$2y$"]
+            .iter()
+            .any(|p| password.starts_with(p))
+        {
+            if password.parse::<bcrypt::HashParts>().is_err() {
+                return Err(format!("malformed hash for {:?}", user));
+            }
+            Password::Bcrypt(password.to_string())
+        } else if password.starts_with("$argon2") {
+            if PasswordHash::new(password).is_err() {
+                return Err(format!("malformed hash for {:?}", user));
+            }
+            Password::Argon2(password.to_string())
+        } else if is_hash(password) {
+            return Err(format!(
+                "unsupported hash for {:?}; use SHA-crypt, bcrypt or argon2",
+                user
+            ));
+        } else {
+            Password::Plain(password.to_string())
+        };
+        if self.accounts.insert(user.to_string(), password).is_some() {
+            return Err(format!("duplicate user {:?}", user));
+        }
+        Ok(())
+    }
+
+    /// Whether `user` exists and `password` is theirs. An unknown user is
+    /// checked against a real account's hash, at the same cost, so timing
+    /// does not give names away.
+    pub fn verify(&self, user: &str, password: &str) -> bool {
+        match self.accounts.get(user) {
+            Some(expected) => expected.matches(password),
+            None => {
+                let stand_in = self
+                    .accounts
+                    .values()
+                    .find(|p| !matches!(p, Password::Plain(_)))
+                    .or_else(|| self.accounts.values().next());
+                if let Some(stand_in) = stand_in {
+                    stand_in.matches(password);
+                }
+                false
+            }
+        }
+    }
+
+    /// `verify` on a blocking thread: a hash with many rounds takes long
+    /// enough to stall the other requests on the same worker.
+    pub async fn verify_async(self: &Arc<Self>, user: &str, password: &str) -> bool {
+        let (users, user, password) = (Arc::clone(self), user.to_string(), password.to_string());
+        tokio::task::spawn_blocking(move || users.verify(&user, &password))
+            .await
+            .unwrap_or(false)
+    }
+
+    pub fn contains(&self, user: &str) -> bool {
+        self.accounts.contains_key(user)
+    }
+
+    /// The password of `user`, when it is stored in plain text.
+    pub fn plain_password(&self, user: &str) -> Option<&str> {
+        match self.accounts.get(user)? {
+            Password::Plain(password) => Some(password),
+            _ => None,
+        }
+    }
+
+    /// Whether every password is stored in plain text.
+    pub fn all_plain(&self) -> bool {
+        self.accounts
+            .values()
+            .all(|password| matches!(password, Password::Plain(_)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+}
+
+/// Whether `password` is written as a hash rather than in plain text.
+/// MD5-crypt and the old `{SHA}` form are recognised too, so they are
+/// refused instead of being taken for plain-text passwords.
+pub fn is_hash(password: &str) -> bool {
+    const PREFIXES: [&str; 9] = [
+        "$5$", "$6$", "$2a$", "$2b$", "$2y$", "$argon2", "$apr1$", "$1$", "{SHA}",
+    ];
+    PREFIXES.iter().any(|prefix| password.starts_with(prefix))
+}
+
+/// Compares two secrets without stopping at the first difference.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Compares two secrets in constant time, hashing them first so their
+/// lengths do not show either.
+pub fn secrets_match(a: &str, b: &str) -> bool {
+    constant_time_eq(&Sha256::digest(a), &Sha256::digest(b))
+}
+
+/// Splits `$5$[rounds=N$]salt$hash` into its rounds, salt and hash.
+fn split_crypt(hash: &str) -> Option<(u32, &str, &str)> {
+    let rest = &hash[3..];
+    let (rounds, rest) = match rest.strip_prefix("rounds=") {
+        Some(rest) => {
+            let (rounds, rest) = rest.split_once('$')?;
+            (rounds.parse::<u32>().ok()?.clamp(1000, 999_999_999), rest)
+        }
+        None => (DEFAULT_ROUNDS, rest),
+    };
+    let (salt, digest) = rest.split_once('$')?;
+    let salt = salt.get(..salt.len().min(MAX_SALT_LEN))?;
+    Some((rounds, salt, digest))
+}
+
+fn sha_crypt_matches(hash: &str, password: &str) -> bool {
+    let Some((rounds, salt, expected)) = split_crypt(hash) else {
+        return false;
+    };
+    let encoded = if hash.starts_with("$5$") {
+        encode(
+            &sha_crypt::<Sha256>(password, salt, rounds),
+            &SHA256_ORDER,
+            &[31, 30],
+        )
+    } else {
+        encode(
+            &sha_crypt::<Sha512>(password, salt, rounds),
+            &SHA512_ORDER,
+            &[63],
+        )
+    };
+    secrets_match(&encoded, expected)
+}
+
+/// The SHA-crypt digest of `password`, as specified by Ulrich Drepper's
+/// "Unix crypt using SHA-256 and SHA-512".
+fn sha_crypt<D: Digest>(password: &str, salt: &str, rounds: u32) -> Vec<u8> {
+    let (password, salt) = (password.as_bytes(), salt.as_bytes());
+    let repeat =
+        |bytes: &[u8], len: usize| -> Vec<u8> { bytes.iter().copied().cycle().take(len).collect() };
+
+    let alternate = D::new()
+        .chain_update(password)
+        .chain_update(salt)
+        .chain_update(password)
+        .finalize();
+    let mut context = D::new().chain_update(password).chain_update(salt);
+    context.update(repeat(&alternate, password.len()));
+    let mut length = password.len();
+    while length > 0 {
+        if length & 1 == 1 {
+            context.update(&alternate);
+        } else {
+            context.update(password);
+        }
+        length >>= 1;
+    }
+    let mut digest = context.finalize().to_vec();
+
+    let mut context = D::new();
+    for _ in 0..password.len() {
+        context.update(password);
+    }
+    let p_bytes = repeat(&context.finalize(), password.len());
+    let mut context = D::new();
+    for _ in 0..16 + digest[0] as usize {
+        context.update(salt);
+    }
+    let s_bytes = repeat(&context.finalize(), salt.len());
+
+    for round in 0..rounds {
+        let mut context = D::new();
+        if round & 1 == 1 {
+            context.update(&p_bytes);
+        } else {
+            context.update(&digest);
+        }
+        if round % 3 != 0 {
+            context.update(&s_bytes);
+        }
+        if round % 7 != 0 {
+            context.update(&p_bytes);
+        }
+        if round & 1 == 1 {
+            context.update(&digest);
+        } else {
+            context.update(&p_bytes);
+        }
+        digest = context.finalize().to_vec();
+    }
+    digest
+}
+
+/// Writes `digest` in crypt(3) base64, in `order`, ending with the bytes of
+/// `tail`.
+fn encode(digest: &[u8], order: &[(usize, usize, usize)], tail: &[usize]) -> String {
+    let mut out = String::new();
+    let mut push = |word: u32, chars: usize| {
+        let mut word = word;
+        for _ in 0..chars {
+            out.push(CRYPT_ALPHABET[(word & 0x3f) as usize] as char);
+            word >>= 6;
+        }
+    };
+    for &(a, b, c) in order {
+        let word = (digest[a] as u32) << 16 | (digest[b] as u32) << 8 | digest[c] as u32;
+        push(word, 4);
+    }
+    let word = tail.iter().fold(0, |word, &i| word << 8 | digest[i] as u32);
+    push(word, tail.len() + 1);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_htpasswd_entries() {
+        let users = Users::parse(
+            "# accounts\n\
+             alice:$5$saltstring$5B8vYYiY.CVt1RlTTf8KbXBH3hsxY/GNooZaBBGWEc5\n\
+             bob:$6$saltstring$svn8UoSVapNtMuq1ukKS4tPQd8iKwSMHWjl/O817G3uBnIFNjnQJuesI68u4OTLiBFdcbYEdFCoEOfaS35inz1\n\
+             \n\
+             carol:plain\n",
+        )
+        .unwrap();
+        assert_eq!(users.len(), 3);
+        assert!(users.verify("alice", "Hello world!"));
+        assert!(users.verify("bob", "Hello world!"));
+        assert!(users.verify("carol", "plain"));
+        assert!(!users.verify("alice", "hello world!"));
+        assert!(!users.verify("carol", "Hello world!"));
+        assert!(!users.verify("dave", "plain"));
+
+        let rounds = Users::parse(
+            "eve:$5$rounds=10000$saltstringsaltstring$3xv.VbSHBb41AL9AvLeujZkZRBAwqFMz2.opqey6IcA",
+        )
+        .unwrap();
+        assert!(rounds.verify("eve", "Hello world!"));
+        assert!(Users::parse("bcrypt:$2y$05$abc").is_err());
+        assert!(Users::parse("md5:$apr1$abc$def").is_err());
+
+        let modern = Users::parse(
+            "frank:$2b$04$wN8OWKxsP/FcbwPRFMgUreIvR/hUUGXnixaydM.XgaeSwP/z6WSpK\n\
+             grace:$argon2id$v=19$m=64,t=1,p=1$GZG3iFugd84Xy5qjL2mO8A$bsE/67An46jT951erxoX9Q6xHokRa908a+GB9pRQycs\n",
+        )
+        .unwrap();
+        assert!(modern.verify("frank", "Hello world!"));
+        assert!(modern.verify("grace", "Hello world!"));
+        assert!(!modern.verify("frank", "hello world!"));
+        assert!(!modern.verify("grace", "hello world!"));
+        assert!(!modern.verify("heidi", "Hello world!"));
+        assert!(
+            Users::parse("dollar:$ign")
+                .unwrap()
+                .verify("dollar", "$ign")
+        );
+        assert!(Users::parse("no-colon").is_err());
+        assert!(Users::parse("a:x\na:y").is_err());
+    }
+}
@@ -0,0 +1,713 @@
+//! WebDAV (RFC 4918) over the served root, enough for Windows Explorer,
+//! macOS Finder and rclone to mount it as a drive. `MOVE` is handled with
+//! the rest of the handlers since it predates `--webdav`.
+//!
+//! Locks are advisory: `LOCK` hands out a token so clients that insist on
+//! locking can write, but nothing is enforced between clients.
+
+use chrono::{DateTime, Utc};
+use html_escape::encode_text;
+use hyper::{Body, Method, Request, Response, StatusCode, header};
+use log::{error, info, warn};
+use mime_guess::from_path;
+use percent_encoding::{percent_decode_str, utf8_percent_encode};
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::fs;
+
+use crate::config::Config;
+use crate::handler::{URL_PATH, receive_upload, resolve_path, strip_base_path, symlink_refused};
+use crate::pathfilter;
+use crate::plugin;
+use crate::symlinks;
+use crate::timefmt;
+
+/// Methods answered here when `--webdav` is on.
+const METHODS: [&str; 9] = [
+    "OPTIONS",
+    "PROPFIND",
+    "PROPPATCH",
+    "PUT",
+    "DELETE",
+    "MKCOL",
+    "COPY",
+    "LOCK",
+    "UNLOCK",
+];
+
+/// Methods that change the root and therefore need `--upload`.
+const WRITE_METHODS: [&str; 7] = [
+    "PROPPATCH",
+    "PUT",
+    "DELETE",
+    "MKCOL",
+    "COPY",
+    "LOCK",
+    "UNLOCK",
+];
+
+/// Every method the server answers with `--webdav` on, as sent in `Allow`.
+pub const ALLOW: &str =
+    "OPTIONS, GET, HEAD, POST, PROPFIND, PROPPATCH, PUT, DELETE, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+
+/// Lifetime announced for locks, in seconds.
+const LOCK_TIMEOUT: u64 = 3600;
+
+/// Whether `method` is one of the WebDAV methods this module answers.
+pub fn handles(method: &Method) -> bool {
+    METHODS.contains(&method.as_str())
+}
+
+pub async fn handle(
+    req: Request<Body>,
+    config: &Config,
+    remote_addr: SocketAddr,
+) -> Response<Body> {
+    let method = req.method().as_str().to_string();
+    if WRITE_METHODS.contains(&method.as_str()) && !config.upload {
+        error!(
+            "WebDAV write attempted but uploads are disabled | method: {} | uri: {} | status: {} | remote: {}",
+            method,
+            req.uri(),
+            StatusCode::FORBIDDEN,
+            remote_addr
+        );
+        return reply(
+            StatusCode::FORBIDDEN,
+            "File modifications are disabled on this server",
+        );
+    }
+    let root = config.root.as_str();
+    let result = match method.as_str() {
+        "OPTIONS" => Ok(options()),
+        "PROPFIND" => propfind(&req, config, remote_addr).await,
+        "PROPPATCH" => proppatch(&req, config, remote_addr).await,
+        "PUT" => put(req, config, remote_addr).await,
+        "DELETE" => delete(&req, root, remote_addr).await,
+        "MKCOL" => mkcol(&req, root, remote_addr).await,
+        "COPY" => copy(&req, config, remote_addr).await,
+        "LOCK" => lock(&req, config, remote_addr).await,
+        _ => Ok(unlock(&req, remote_addr)),
+    };
+    result.unwrap_or_else(|resp| resp)
+}
+
+fn reply(status: StatusCode, body: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn multistatus(body: String) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::MULTI_STATUS)
+        .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn options() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("dav", "1, 2")
+        // Makes Microsoft clients use WebDAV rather than FrontPage extensions.
+        .header("ms-author-via", "DAV")
+        .header(header::ALLOW, ALLOW)
+        .header(header::CONTENT_LENGTH, "0")
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Lists the properties of the resource and, with `Depth: 1`, of its
+/// children. The request body is not parsed: every live property is
+/// returned, which clients asking for a subset accept.
+async fn propfind(
+    req: &Request<Body>,
+    config: &Config,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    let path = resolve_path(&config.root, req.uri().path(), remote_addr)?;
+    let depth = req
+        .headers()
+        .get("depth")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("1");
+    let children = match depth {
+        "0" => false,
+        "1" => true,
+        _ => {
+            warn!(
+                "PROPFIND with unsupported depth | depth: {:?} | uri: {} | status: {} | remote: {}",
+                depth,
+                req.uri(),
+                StatusCode::FORBIDDEN,
+                remote_addr
+            );
+            return Ok(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+                .body(Body::from(
+                    "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+                     <D:error xmlns:D=\"DAV:\"><D:propfind-finite-depth/></D:error>\n",
+                ))
+                .unwrap());
+        }
+    };
+    let metadata = fs::metadata(&path).await.map_err(|err| {
+        error!(
+            "PROPFIND failed | path: {:?} | error: {} | status: {} | remote: {}",
+            path,
+            err,
+            StatusCode::NOT_FOUND,
+            remote_addr
+        );
+        reply(StatusCode::NOT_FOUND, "File not found")
+    })?;
+
+    let mut href = format!("{}{}", config.base_path, req.uri().path());
+    if metadata.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+    let mut body = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n",
+    );
+    write_response(&mut body, &href, &path, &metadata);
+    let mut count = 1;
+    if children && metadata.is_dir() {
+        let mut entries = fs::read_dir(&path).await.map_err(|err| {
+            error!(
+                "PROPFIND failed to read directory | path: {:?} | error: {} | status: {} | remote: {}",
+                path,
+                err,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                remote_addr
+            );
+            reply(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error reading directory",
+            )
+        })?;
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            // Entries that cannot be inspected, such as broken symlinks, are left out.
+            let Ok(child) = fs::metadata(entry.path()).await else {
+                continue;
+            };
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let dir = percent_decode_str(req.uri().path()).decode_utf8_lossy();
+            let child_path = format!("{}/{}", dir.trim_end_matches('/'), name);
+            if pathfilter::is_hidden(&child_path, config) {
+                continue;
+            }
+            let mut child_href = format!("{}{}", href, utf8_percent_encode(&name, URL_PATH));
+            if child.is_dir() {
+                child_href.push('/');
+            }
+            write_response(&mut body, &child_href, &entry.path(), &child);
+            count += 1;
+        }
+    }
+    body.push_str("</D:multistatus>\n");
+    info!(
+        "PROPFIND | path: {:?} | depth: {} | entries: {} | status: {} | remote: {}",
+        path,
+        depth,
+        count,
+        StatusCode::MULTI_STATUS,
+        remote_addr
+    );
+    Ok(multistatus(body))
+}
+
+fn write_response(body: &mut String, href: &str, path: &Path, metadata: &std::fs::Metadata) {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let _ = write!(
+        body,
+        "<D:response><D:href>{}</D:href><D:propstat><D:prop>\
+         <D:displayname>{}</D:displayname>",
+        encode_text(href),
+        encode_text(&name)
+    );
+    if metadata.is_dir() {
+        body.push_str("<D:resourcetype><D:collection/></D:resourcetype>");
+    } else {
+        let _ = write!(
+            body,
+            "<D:resourcetype/><D:getcontentlength>{}</D:getcontentlength>\
+             <D:getcontenttype>{}</D:getcontenttype>",
+            metadata.len(),
+            encode_text(from_path(path).first_or_octet_stream().as_ref())
+        );
+    }
+    if let Ok(modified) = metadata.modified() {
+        let _ = write!(
+            body,
+            "<D:getlastmodified>{}</D:getlastmodified><D:creationdate>{}</D:creationdate>",
+            timefmt::format_http_date(modified),
+            DateTime::<Utc>::from(modified).format("%Y-%m-%dT%H:%M:%SZ")
+        );
+    }
+    body.push_str(
+        "<D:supportedlock><D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+         <D:locktype><D:write/></D:locktype></D:lockentry></D:supportedlock>\
+         </D:prop><D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n",
+    );
+}
+
+/// Properties cannot be stored, but Windows sets timestamps after every
+/// upload and gives up on a failure, so the change is acknowledged without
+/// being applied.
+async fn proppatch(
+    req: &Request<Body>,
+    config: &Config,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    let path = resolve_path(&config.root, req.uri().path(), remote_addr)?;
+    if fs::symlink_metadata(&path).await.is_err() {
+        return Err(reply(StatusCode::NOT_FOUND, "File not found"));
+    }
+    info!(
+        "PROPPATCH ignored | path: {:?} | status: {} | remote: {}",
+        path,
+        StatusCode::MULTI_STATUS,
+        remote_addr
+    );
+    Ok(multistatus(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\n\
+         <D:response><D:href>{}</D:href><D:propstat><D:prop/>\
+         <D:status>HTTP/1.1 200 OK</D:status></D:propstat></D:response>\n</D:multistatus>\n",
+        encode_text(&format!("{}{}", config.base_path, req.uri().path()))
+    )))
+}
+
+/// The parent directory of `path`, which must exist for it to be created.
+async fn existing_parent<'a>(
+    path: &'a Path,
+    method: &str,
+    remote_addr: SocketAddr,
+) -> Result<&'a Path, Response<Body>> {
+    match path.parent() {
+        Some(parent) if fs::metadata(parent).await.is_ok_and(|m| m.is_dir()) => Ok(parent),
+        _ => {
+            error!(
+                "{} failed: parent directory missing | path: {:?} | status: {} | remote: {}",
+                method,
+                path,
+                StatusCode::CONFLICT,
+                remote_addr
+            );
+            Err(reply(
+                StatusCode::CONFLICT,
+                "Parent directory does not exist",
+            ))
+        }
+    }
+}
+
+fn is_root(
+    path: &Path,
+    root: &str,
+    method: &str,
+    remote_addr: SocketAddr,
+) -> Option<Response<Body>> {
+    (path == Path::new(root)).then(|| {
+        error!(
+            "{} rejected on the root directory | status: {} | remote: {}",
+            method,
+            StatusCode::FORBIDDEN,
+            remote_addr
+        );
+        reply(StatusCode::FORBIDDEN, "Cannot modify the root directory")
+    })
+}
+
+async fn put(
+    req: Request<Body>,
+    config: &Config,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    let root = config.root.as_str();
+    let path = resolve_path(root, req.uri().path(), remote_addr)?;
+    let existing = config.storage.metadata(&path).await.ok();
+    if existing.as_ref().is_some_and(|m| m.is_dir) {
+        error!(
+            "PUT failed: path is a directory | path: {:?} | status: {} | remote: {}",
+            path,
+            StatusCode::METHOD_NOT_ALLOWED,
+            remote_addr
+        );
+        return Err(reply(StatusCode::METHOD_NOT_ALLOWED, "Path is a directory"));
+    }
+    let parent = existing_parent(&path, "PUT", remote_addr).await?;
+    let upload = receive_upload(req.into_body(), Some(parent), false)
+        .await
+        .map_err(|err| {
+            error!(
+                "PUT failed | path: {:?} | error: {} | status: {} | remote: {}",
+                path,
+                err,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                remote_addr
+            );
+            reply(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save file")
+        })?;
+    if let Some(status) = pathfilter::relative_path(&path, root)
+        .and_then(|relative| plugin::on_upload(&config.plugins, &relative, upload.size))
+    {
+        upload.staged.discard().await;
+        warn!(
+            "PUT refused by plugin | path: {:?} | status: {} | remote: {}",
+            path, status, remote_addr
+        );
+        return Err(Response::builder()
+            .status(status)
+            .body(Body::from("Upload refused"))
+            .unwrap());
+    }
+    if let Err(err) = config.storage.persist(upload.staged, &path).await {
+        error!(
+            "PUT failed | path: {:?} | error: {} | status: {} | remote: {}",
+            path,
+            err,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            remote_addr
+        );
+        return Err(reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to save file",
+        ));
+    }
+    let status = if existing.is_some() {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    };
+    info!(
+        "PUT complete | path: {:?} | bytes: {} | status: {} | remote: {}",
+        path, upload.size, status, remote_addr
+    );
+    Ok(Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap())
+}
+
+async fn delete(
+    req: &Request<Body>,
+    root: &str,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    let path = resolve_path(root, req.uri().path(), remote_addr)?;
+    if let Some(resp) = is_root(&path, root, "DELETE", remote_addr) {
+        return Err(resp);
+    }
+    let Ok(metadata) = fs::symlink_metadata(&path).await else {
+        error!(
+            "DELETE failed: not found | path: {:?} | status: {} | remote: {}",
+            path,
+            StatusCode::NOT_FOUND,
+            remote_addr
+        );
+        return Err(reply(StatusCode::NOT_FOUND, "File not found"));
+    };
+    let removed = if metadata.is_dir() {
+        fs::remove_dir_all(&path).await
+    } else {
+        fs::remove_file(&path).await
+    };
+    if let Err(err) = removed {
+        error!(
+            "DELETE failed | path: {:?} | error: {} | status: {} | remote: {}",
+            path,
+            err,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            remote_addr
+        );
+        return Err(reply(StatusCode::INTERNAL_SERVER_ERROR, "Delete failed"));
+    }
+    info!(
+        "DELETE complete | path: {:?} | status: {} | remote: {}",
+        path,
+        StatusCode::NO_CONTENT,
+        remote_addr
+    );
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap())
+}
+
+async fn mkcol(
+    req: &Request<Body>,
+    root: &str,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    let path = resolve_path(root, req.uri().path(), remote_addr)?;
+    if fs::symlink_metadata(&path).await.is_ok() {
+        error!(
+            "MKCOL failed: already exists | path: {:?} | status: {} | remote: {}",
+            path,
+            StatusCode::METHOD_NOT_ALLOWED,
+            remote_addr
+        );
+        return Err(reply(StatusCode::METHOD_NOT_ALLOWED, "Already exists"));
+    }
+    existing_parent(&path, "MKCOL", remote_addr).await?;
+    if let Err(err) = fs::create_dir(&path).await {
+        error!(
+            "MKCOL failed | path: {:?} | error: {} | status: {} | remote: {}",
+            path,
+            err,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            remote_addr
+        );
+        return Err(reply(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to create directory",
+        ));
+    }
+    info!(
+        "MKCOL complete | path: {:?} | status: {} | remote: {}",
+        path,
+        StatusCode::CREATED,
+        remote_addr
+    );
+    Ok(Response::builder()
+        .status(StatusCode::CREATED)
+        .body(Body::empty())
+        .unwrap())
+}
+
+/// Copies a file or, unless `Depth: 0`, a whole directory. As with `MOVE`,
+/// an existing destination is only replaced with `Overwrite: T`.
+async fn copy(
+    req: &Request<Body>,
+    config: &Config,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    let root = config.root.as_str();
+    let source = resolve_path(root, req.uri().path(), remote_addr)?;
+    // Destination is an absolute URI or an absolute path; only the path matters.
+    let destination = req
+        .headers()
+        .get("destination")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<hyper::Uri>().ok());
+    let Some(destination) = destination else {
+        error!(
+            "COPY rejected: Missing destination | uri: {} | status: {} | remote: {}",
+            req.uri(),
+            StatusCode::BAD_REQUEST,
+            remote_addr
+        );
+        return Err(reply(StatusCode::BAD_REQUEST, "Missing destination"));
+    };
+    let Some(destination) = strip_base_path(destination.path(), &config.base_path) else {
+        error!(
+            "COPY rejected: Destination outside the base path | uri: {} | status: {} | remote: {}",
+            req.uri(),
+            StatusCode::BAD_GATEWAY,
+            remote_addr
+        );
+        return Err(reply(
+            StatusCode::BAD_GATEWAY,
+            "Destination is on another server",
+        ));
+    };
+    let destination = resolve_path(root, destination, remote_addr)?;
+    if let Some(resp) = is_root(&destination, root, "COPY", remote_addr) {
+        return Err(resp);
+    }
+    if pathfilter::relative_path(&destination, root)
+        .is_some_and(|relative| pathfilter::is_hidden(&relative, config))
+    {
+        error!(
+            "COPY rejected: destination is hidden | destination: {:?} | status: {} | remote: {}",
+            destination,
+            StatusCode::FORBIDDEN,
+            remote_addr
+        );
+        return Err(reply(StatusCode::FORBIDDEN, "Destination is hidden"));
+    }
+    if !symlinks::allowed(&destination, root, config.follow_symlinks).await {
+        return Err(symlink_refused(&destination, config, remote_addr).await);
+    }
+    if destination.starts_with(&source) {
+        error!(
+            "COPY rejected: destination inside source | source: {:?} | destination: {:?} | status: {} | remote: {}",
+            source,
+            destination,
+            StatusCode::FORBIDDEN,
+            remote_addr
+        );
+        return Err(reply(
+            StatusCode::FORBIDDEN,
+            "Destination is inside the source",
+        ));
+    }
+    let Ok(metadata) = fs::metadata(&source).await else {
+        error!(
+            "COPY failed: source not found | source: {:?} | status: {} | remote: {}",
+            source,
+            StatusCode::NOT_FOUND,
+            remote_addr
+        );
+        return Err(reply(StatusCode::NOT_FOUND, "File not found"));
+    };
+    let overwrite = req
+        .headers()
+        .get("overwrite")
+        .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"T"));
+    let replaced = fs::symlink_metadata(&destination).await.ok();
+    if replaced.is_some() && !overwrite {
+        error!(
+            "COPY failed: destination exists | source: {:?} | destination: {:?} | status: {} | remote: {}",
+            source,
+            destination,
+            StatusCode::PRECONDITION_FAILED,
+            remote_addr
+        );
+        return Err(reply(
+            StatusCode::PRECONDITION_FAILED,
+            "Destination already exists",
+        ));
+    }
+    existing_parent(&destination, "COPY", remote_addr).await?;
+    let recursive = req.headers().get("depth").is_none_or(|v| v != "0");
+    let copied = {
+        let (source, destination) = (source.clone(), destination.clone());
+        let is_dir = metadata.is_dir();
+        let replaced_dir = replaced.as_ref().map(|m| m.is_dir());
+        tokio::task::spawn_blocking(move || {
+            match replaced_dir {
+                Some(true) => std::fs::remove_dir_all(&destination)?,
+                Some(false) => std::fs::remove_file(&destination)?,
+                None => {}
+            }
+            if !is_dir {
+                std::fs::copy(&source, &destination).map(|_| ())
+            } else if recursive {
+                copy_tree(&source, &destination)
+            } else {
+                std::fs::create_dir(&destination)
+            }
+        })
+        .await
+        .unwrap_or_else(|err| Err(std::io::Error::other(err)))
+    };
+    if let Err(err) = copied {
+        error!(
+            "COPY failed | source: {:?} | destination: {:?} | error: {} | status: {} | remote: {}",
+            source,
+            destination,
+            err,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            remote_addr
+        );
+        return Err(reply(StatusCode::INTERNAL_SERVER_ERROR, "Copy failed"));
+    }
+    let status = if replaced.is_some() {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::CREATED
+    };
+    info!(
+        "COPY complete | source: {:?} | destination: {:?} | status: {} | remote: {}",
+        source, destination, status, remote_addr
+    );
+    Ok(Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap())
+}
+
+/// Copies a directory tree. Symlinks are recreated rather than followed,
+/// so a link out of the root does not pull its target into it.
+fn copy_tree(source: &Path, destination: &Path) -> std::io::Result<()> {
+    std::fs::create_dir(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let target: PathBuf = destination.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            copy_tree(&entry.path(), &target)?;
+        } else if file_type.is_symlink() {
+            #[cfg(unix)]
+            std::os::unix::fs::symlink(std::fs::read_link(entry.path())?, &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+/// Grants a lock without recording it. Locking a path that does not exist
+/// creates an empty file there, as RFC 4918 requires.
+async fn lock(
+    req: &Request<Body>,
+    config: &Config,
+    remote_addr: SocketAddr,
+) -> Result<Response<Body>, Response<Body>> {
+    static NEXT_LOCK: AtomicU64 = AtomicU64::new(0);
+    let path = resolve_path(&config.root, req.uri().path(), remote_addr)?;
+    let mut status = StatusCode::OK;
+    if fs::symlink_metadata(&path).await.is_err() {
+        existing_parent(&path, "LOCK", remote_addr).await?;
+        if let Err(err) = fs::File::create(&path).await {
+            error!(
+                "LOCK failed to create file | path: {:?} | error: {} | status: {} | remote: {}",
+                path,
+                err,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                remote_addr
+            );
+            return Err(reply(StatusCode::INTERNAL_SERVER_ERROR, "Lock failed"));
+        }
+        status = StatusCode::CREATED;
+    }
+    let token = format!(
+        "opaquelocktoken:samserve-{:x}-{:x}-{:x}",
+        std::process::id(),
+        Utc::now().timestamp_micros(),
+        NEXT_LOCK.fetch_add(1, Ordering::Relaxed)
+    );
+    info!(
+        "LOCK granted | path: {:?} | token: {} | status: {} | remote: {}",
+        path, token, status, remote_addr
+    );
+    let body = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>\
+         <D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:exclusive/></D:lockscope>\
+         <D:depth>0</D:depth><D:timeout>Second-{}</D:timeout>\
+         <D:locktoken><D:href>{}</D:href></D:locktoken>\
+         <D:lockroot><D:href>{}</D:href></D:lockroot>\
+         </D:activelock></D:lockdiscovery></D:prop>\n",
+        LOCK_TIMEOUT,
+        token,
+        encode_text(&format!("{}{}", config.base_path, req.uri().path()))
+    );
+    Ok(Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/xml; charset=utf-8")
+        .header("lock-token", format!("<{}>", token))
+        .body(Body::from(body))
+        .unwrap())
+}
+
+fn unlock(req: &Request<Body>, remote_addr: SocketAddr) -> Response<Body> {
+    info!(
+        "UNLOCK | uri: {} | status: {} | remote: {}",
+        req.uri(),
+        StatusCode::NO_CONTENT,
+        remote_addr
+    );
+    Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::empty())
+        .unwrap()
+}